@@ -0,0 +1,31 @@
+//! Drive the renderer from your own window loop, here spinning the demo scene's teapot.
+//!     cargo run --example embed_window
+
+use minifb::{Key, Scale};
+
+use threedengine::demo::init_scene;
+use threedengine::present::{Presenter, WindowPresenter};
+use threedengine::renderer::Renderer;
+
+fn main() {
+    let mut scene = init_scene();
+    let mut renderer = Renderer::new(640, 480);
+    renderer.grid.enabled = true;
+    let mut presenter = WindowPresenter::new("Embedded renderer", 640, 480, Scale::X1);
+
+    while presenter.window.is_open() && !presenter.window.is_key_down(Key::Escape) {
+        let (width, height) = presenter.framebuffer_size();
+        if (width, height) != (renderer.framebuffer.width, renderer.framebuffer.height) {
+            renderer.resize(width, height);
+        }
+
+        // Objects can be moved freely between frames, the scene hierarchy just needs a refit
+        scene.objects[1].transform.rotation.y += 1.;
+        scene.refit();
+
+        renderer.render(&mut scene);
+        presenter
+            .present(&renderer.framebuffer)
+            .expect("Unable to present frame");
+    }
+}
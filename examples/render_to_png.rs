@@ -0,0 +1,61 @@
+//! Build a scene in code, render one frame and save it, no window needed.
+//!     cargo run --example render_to_png
+
+use threedengine::colour::Colour;
+use threedengine::export::{save_depth, save_image};
+use threedengine::mesh::Mesh;
+use threedengine::renderer::Renderer;
+use threedengine::scene::Scene;
+use threedengine::threed::*;
+
+fn main() {
+    let mesh = Mesh::from_obj_file("Resource/Models/teapot.obj").expect("Unable to load teapot");
+
+    let transform = Transform {
+        position: vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        },
+        rotation: vec3 {
+            x: 0.,
+            y: 30.,
+            z: 0.,
+        },
+    };
+    let teapot = Object::new(
+        "teapot".to_string(),
+        mesh,
+        transform,
+        Colour::new(200, 120, 40),
+    );
+
+    let camera = Camera {
+        fov: 60.,
+        near_plane: 0.1,
+        far_plane: 100.,
+        position: vec3 {
+            x: 0.,
+            y: 2.,
+            z: -8.,
+        },
+        yaw: 0.,
+        fwd_speed: 1.,
+        yaw_speed: 5.,
+    };
+    let light_dir = vec3 {
+        x: 0.,
+        y: 10.,
+        z: -10.,
+    };
+
+    let mut scene = Scene::new(vec![teapot], camera, light_dir);
+    let mut renderer = Renderer::new(640, 480);
+    renderer.render(&mut scene);
+
+    save_image("teapot.png", &renderer.framebuffer).expect("Unable to save image");
+    save_depth("teapot_depth.png", &renderer.framebuffer).expect("Unable to save depth");
+
+    let tris = renderer.stats.vis_tris;
+    println!("Rendered {tris} triangles to teapot.png and teapot_depth.png");
+}
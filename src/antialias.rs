@@ -0,0 +1,220 @@
+//! Anti-aliasing. Supersampling renders the whole frame at a multiple of the output size and
+//! filters it down. Multisampling keeps a colour and depth for several points in each pixel:
+//! each triangle is walked once at the output size, the points it covers are found from its
+//! edges, and its colour is written to those that pass their depth test. The points are then
+//! averaged. Colours are worked out once per triangle either way.
+
+use crate::renderer::{Downsample, FrameBuffer};
+use crate::tile::SamplePlane;
+
+/// The standard multisample patterns, in 1/16ths of a pixel from its centre with y down
+const PATTERN_2X: [(i8, i8); 2] = [(4, 4), (-4, -4)];
+const PATTERN_4X: [(i8, i8); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+const PATTERN_8X: [(i8, i8); 8] = [
+    (1, -3),
+    (-1, 3),
+    (5, 1),
+    (-3, -5),
+    (-5, 5),
+    (-7, -1),
+    (3, 7),
+    (7, -7),
+];
+
+/// Where to sample inside each pixel for a sample count, measured from the pixel's bottom left.
+/// Counts without a pattern get the centre
+pub fn sample_positions(samples: usize) -> Vec<(f32, f32)> {
+    let pattern: &[(i8, i8)] = match samples {
+        2 => &PATTERN_2X,
+        4 => &PATTERN_4X,
+        8 => &PATTERN_8X,
+        _ => &[(0, 0)],
+    };
+    pattern
+        .iter()
+        .map(|&(x, y)| (0.5 + x as f32 / 16., 0.5 - y as f32 / 16.))
+        .collect()
+}
+
+/// Average the sample planes into one colour per pixel and keep the nearest depth
+pub fn resolve_samples(planes: &[SamplePlane], pixels: &mut [u32], depth: &mut [f32]) {
+    if planes.is_empty() {
+        return;
+    }
+
+    let count = planes.len() as u32;
+    for (i, (pixel, z)) in pixels.iter_mut().zip(depth.iter_mut()).enumerate() {
+        let mut sum = [0; 3];
+        let mut nearest = f32::INFINITY;
+        for plane in planes {
+            let colour = plane.pixels[i];
+            sum[0] += (colour >> 16) & 0xff;
+            sum[1] += (colour >> 8) & 0xff;
+            sum[2] += colour & 0xff;
+            nearest = nearest.min(plane.depth[i]);
+        }
+
+        let [r, g, b] = sum.map(|c| (c + count / 2) / count);
+        *pixel = (r << 16) | (g << 8) | b;
+        *z = nearest;
+    }
+}
+
+/// Filter a supersampled frame down into `dst`. `src` must be a whole multiple of `dst`'s size,
+/// the same in both directions. Depth is the nearest sample under each pixel
+pub fn downsample(src: &FrameBuffer, dst: &mut FrameBuffer, filter: Downsample) {
+    let scale = src.width / dst.width.max(1);
+    assert!(scale > 0 && src.width == dst.width * scale && src.height == dst.height * scale);
+
+    let (start, weights) = filter_weights(filter, scale);
+
+    // The filters are separable, across each row first then down the columns
+    let mut rows = vec![[0f32; 3]; dst.width * src.height];
+    for y in 0..src.height {
+        let src_row = &src.pixels[y * src.width..(y + 1) * src.width];
+        for x in 0..dst.width {
+            let first = (x * scale) as isize + start;
+            rows[y * dst.width + x] = filter_at(first, &weights, src.width, |i| {
+                let colour = src_row[i];
+                [
+                    ((colour >> 16) & 0xff) as f32,
+                    ((colour >> 8) & 0xff) as f32,
+                    (colour & 0xff) as f32,
+                ]
+            });
+        }
+    }
+
+    for y in 0..dst.height {
+        let first = (y * scale) as isize + start;
+        for x in 0..dst.width {
+            let [r, g, b] = filter_at(first, &weights, src.height, |i| rows[i * dst.width + x])
+                .map(|c| (c + 0.5).clamp(0., 255.) as u32);
+            dst.pixels[y * dst.width + x] = (r << 16) | (g << 8) | b;
+
+            let mut nearest = f32::INFINITY;
+            for sy in y * scale..(y + 1) * scale {
+                let row = &src.depth[sy * src.width..(sy + 1) * src.width];
+                for z in &row[x * scale..(x + 1) * scale] {
+                    nearest = nearest.min(*z);
+                }
+            }
+            dst.depth[y * dst.width + x] = nearest;
+        }
+    }
+}
+
+/// Offset of the first source sample from the start of the output pixel's own samples, and the
+/// weight of each sample from there on
+fn filter_weights(filter: Downsample, scale: usize) -> (isize, Vec<f32>) {
+    match filter {
+        Downsample::Box => (0, vec![1.; scale]),
+        Downsample::Tent => {
+            // Falls to zero one output pixel either side of the centre
+            let s = scale as f32;
+            let weights = (0..2 * scale)
+                .map(|k| 1. - (k as f32 - s + 0.5).abs() / s)
+                .collect();
+            (-(scale as isize) / 2, weights)
+        }
+    }
+}
+
+/// Weighted average of the samples from `first` on, leaving out any past either end of the line
+fn filter_at(
+    first: isize,
+    weights: &[f32],
+    len: usize,
+    sample: impl Fn(usize) -> [f32; 3],
+) -> [f32; 3] {
+    let mut sum = [0.; 3];
+    let mut total = 0.;
+    for (k, weight) in weights.iter().enumerate() {
+        let i = first + k as isize;
+        if i < 0 || i as usize >= len {
+            continue;
+        }
+        let value = sample(i as usize);
+        for (sum, value) in sum.iter_mut().zip(value) {
+            *sum += weight * value;
+        }
+        total += weight;
+    }
+    sum.map(|s| s / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::antialias::*;
+    use crate::raster::Rect;
+
+    #[test]
+    fn test_sample_positions_inside_pixel() {
+        for samples in [1, 2, 4, 8] {
+            let positions = sample_positions(samples);
+            assert_eq!(samples, positions.len());
+            for (x, y) in positions {
+                assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_samples_averages() {
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let plane = |colour, depth| SamplePlane {
+            rect,
+            pixels: vec![colour],
+            depth: vec![depth],
+        };
+        let planes = [
+            plane(0xffffff, 0.5),
+            plane(0x000000, f32::INFINITY),
+            plane(0xffffff, 0.25),
+            plane(0x000000, f32::INFINITY),
+        ];
+        let (mut pixels, mut depth) = (vec![0], vec![0.]);
+
+        resolve_samples(&planes, &mut pixels, &mut depth);
+
+        assert_eq!(vec![0x808080], pixels);
+        assert_eq!(vec![0.25], depth);
+    }
+
+    #[test]
+    fn test_downsample_flat_colour_unchanged() {
+        for filter in [Downsample::Box, Downsample::Tent] {
+            let mut src = FrameBuffer::new(8, 6);
+            src.pixels.fill(0x204060);
+            let mut dst = FrameBuffer::new(4, 3);
+
+            downsample(&src, &mut dst, filter);
+
+            assert!(dst.pixels.iter().all(|p| *p == 0x204060));
+        }
+    }
+
+    #[test]
+    fn test_downsample_edge() {
+        // Left half black, right half white, the edge falling between output pixels 1 and 2
+        let mut src = FrameBuffer::new(8, 2);
+        for y in 0..2 {
+            src.pixels[y * 8 + 4..(y + 1) * 8].fill(0xffffff);
+        }
+        let mut boxed = FrameBuffer::new(4, 1);
+        let mut tent = FrameBuffer::new(4, 1);
+
+        downsample(&src, &mut boxed, Downsample::Box);
+        downsample(&src, &mut tent, Downsample::Tent);
+
+        assert_eq!(vec![0, 0, 0xffffff, 0xffffff], boxed.pixels);
+        // The tent reaches a quarter of a pixel over the edge
+        assert_eq!(0x202020, tent.pixels[1]);
+        assert_eq!(0xdfdfdf, tent.pixels[2]);
+    }
+}
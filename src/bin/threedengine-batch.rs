@@ -0,0 +1,219 @@
+//! Batch tools that need no display, rendering frames to image files and simplifying meshes
+
+use clap::{Parser, Subcommand};
+use std::process;
+use std::time::Instant;
+
+use threedengine::demo::init_scene;
+use threedengine::mesh::Mesh;
+use threedengine::present::{render_frames, ImagePresenter};
+use threedengine::renderer::{Rasterizer, Renderer};
+use threedengine::scene_file::load_scene;
+use threedengine::simplify::{simplify, SimplifyOptions};
+use threedengine::span::simd_level;
+
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render frames to image files without opening a window
+    Render {
+        frames: usize,
+        output_dir: String,
+        /// Degrees to turn the camera between frames
+        #[arg(default_value_t = 0., allow_hyphen_values = true)]
+        yaw_step: f32,
+        /// Image format: ppm, bmp or png
+        #[arg(default_value = "png")]
+        format: String,
+        /// Scene file to render instead of the demo scene
+        #[arg(long)]
+        scene: Option<String>,
+        #[arg(long, default_value_t = 800)]
+        width: usize,
+        #[arg(long, default_value_t = 600)]
+        height: usize,
+        /// Threads to rasterise on, 0 for one per core
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
+    },
+    /// Time the transform and raster stages for both rasterizers at a range of thread counts
+    Bench {
+        #[arg(default_value_t = 100)]
+        frames: usize,
+        /// Scene file to render instead of the demo scene
+        #[arg(long)]
+        scene: Option<String>,
+        #[arg(long, default_value_t = 800)]
+        width: usize,
+        #[arg(long, default_value_t = 600)]
+        height: usize,
+        /// Thread counts to try
+        #[arg(long, value_delimiter = ',', default_value = "1,2,4,8")]
+        threads: Vec<usize>,
+    },
+    /// Reduce a mesh to a target triangle count
+    Simplify {
+        input: String,
+        output: String,
+        target_tris: usize,
+        max_error: Option<f64>,
+    },
+}
+
+fn main() {
+    let result = match Args::parse().command {
+        Command::Render {
+            frames,
+            output_dir,
+            yaw_step,
+            format,
+            scene,
+            width,
+            height,
+            threads,
+        } => run_render(
+            frames,
+            &output_dir,
+            yaw_step,
+            &format,
+            scene.as_deref(),
+            (width, height),
+            threads,
+        ),
+        Command::Bench {
+            frames,
+            scene,
+            width,
+            height,
+            threads,
+        } => run_bench(frames, scene.as_deref(), (width, height), &threads),
+        Command::Simplify {
+            input,
+            output,
+            target_tris,
+            max_error,
+        } => run_simplify(&input, &output, target_tris, max_error),
+    };
+
+    if let Err(e) = result {
+        println!("{e}");
+        process::exit(1);
+    }
+}
+
+fn run_render(
+    frames: usize,
+    output_dir: &str,
+    yaw_step: f32,
+    format: &str,
+    scene_path: Option<&str>,
+    (width, height): (usize, usize),
+    threads: usize,
+) -> Result<(), String> {
+    let mut renderer = Renderer::new(width, height);
+    renderer.threads = threads;
+    let mut scene = match scene_path {
+        Some(path) => load_scene(path, &mut renderer)
+            .map_err(|e| format!("Unable to load scene {path}: {e}"))?,
+        None => {
+            renderer.grid.enabled = true;
+            init_scene()
+        }
+    };
+    let mut presenter = ImagePresenter::new(output_dir, format)
+        .map_err(|e| format!("Unable to create output directory {output_dir}: {e}"))?;
+
+    let start = Instant::now();
+    render_frames(&mut renderer, &mut scene, &mut presenter, frames, yaw_step)
+        .map_err(|e| format!("Unable to write frame: {e}"))?;
+    let elapsed_ms = start.elapsed().as_secs_f32() * 1000.;
+
+    println!("Rendered {frames} frames to {output_dir} in {elapsed_ms:.0} ms");
+    Ok(())
+}
+
+fn run_bench(
+    frames: usize,
+    scene_path: Option<&str>,
+    (width, height): (usize, usize),
+    thread_counts: &[usize],
+) -> Result<(), String> {
+    let mut renderer = Renderer::new(width, height);
+    let mut scene = match scene_path {
+        Some(path) => load_scene(path, &mut renderer)
+            .map_err(|e| format!("Unable to load scene {path}: {e}"))?,
+        None => {
+            renderer.grid.enabled = true;
+            init_scene()
+        }
+    };
+    let frames = frames.max(1);
+
+    println!("Span path: {}", simd_level());
+    println!("Rasterizer  Threads  Trans. & Proj  Raster   Speedup");
+    for rasterizer in [Rasterizer::Scanline, Rasterizer::EdgeFunction] {
+        renderer.rasterizer = rasterizer;
+        let name = match rasterizer {
+            Rasterizer::Scanline => "scanline",
+            Rasterizer::EdgeFunction => "edge",
+        };
+
+        let mut baseline = None;
+        for &threads in thread_counts {
+            renderer.threads = threads;
+            // One frame to build the thread pool and warm the caches
+            renderer.render(&mut scene);
+
+            let (mut trans_and_proj, mut raster) = (0., 0.);
+            for _ in 0..frames {
+                renderer.render(&mut scene);
+                trans_and_proj += renderer.stats.trans_and_proj_time;
+                raster += renderer.stats.raster_time;
+            }
+            let trans_and_proj_ms = trans_and_proj * 1000. / frames as f32;
+            let raster_ms = raster * 1000. / frames as f32;
+
+            let total = trans_and_proj_ms + raster_ms;
+            let speedup = *baseline.get_or_insert(total) / total;
+            println!(
+                "{name:>10}  {threads:>7}  {trans_and_proj_ms:>10.2} ms  {raster_ms:>6.2} ms  {speedup:>6.2}x"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn run_simplify(
+    input: &str,
+    output: &str,
+    target_tris: usize,
+    max_error: Option<f64>,
+) -> Result<(), String> {
+    let mesh = Mesh::from_obj_file(input).map_err(|e| format!("Unable to read {input}: {e}"))?;
+
+    let mut options = SimplifyOptions {
+        target_tris,
+        ..SimplifyOptions::default()
+    };
+    if let Some(max_error) = max_error {
+        options.max_error = max_error;
+    }
+
+    let start = Instant::now();
+    let simplified = simplify(&mesh, &options);
+    let elapsed_ms = start.elapsed().as_secs_f32() * 1000.;
+
+    let before = mesh.indices.len();
+    let after = simplified.indices.len();
+    println!("Simplified {before} tris to {after} tris in {elapsed_ms:.0} ms");
+
+    simplified
+        .write_obj_file(output)
+        .map_err(|e| format!("Unable to write {output}: {e}"))
+}
@@ -0,0 +1,397 @@
+use ndarray::Array2;
+
+use crate::threed::*;
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vert,
+    pub max: Vert,
+}
+
+impl Aabb {
+    /// An "inside out" box that any point will grow, used as the starting value when fitting
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vert {
+                x: f32::MAX,
+                y: f32::MAX,
+                z: f32::MAX,
+            },
+            max: Vert {
+                x: f32::MIN,
+                y: f32::MIN,
+                z: f32::MIN,
+            },
+        }
+    }
+
+    pub fn from_verts(verts: &[Vert]) -> Self {
+        let mut aabb = Aabb::empty();
+        for vert in verts {
+            aabb.grow(*vert);
+        }
+        aabb
+    }
+
+    pub fn grow(&mut self, v: Vert) {
+        self.min.x = self.min.x.min(v.x);
+        self.min.y = self.min.y.min(v.y);
+        self.min.z = self.min.z.min(v.z);
+        self.max.x = self.max.x.max(v.x);
+        self.max.y = self.max.y.max(v.y);
+        self.max.z = self.max.z.max(v.z);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centre(&self) -> Vert {
+        Vert {
+            x: (self.min.x + self.max.x) * 0.5,
+            y: (self.min.y + self.max.y) * 0.5,
+            z: (self.min.z + self.max.z) * 0.5,
+        }
+    }
+
+    pub fn corners(&self) -> [Vert; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vert {
+                x: min.x,
+                y: min.y,
+                z: min.z,
+            },
+            Vert {
+                x: max.x,
+                y: min.y,
+                z: min.z,
+            },
+            Vert {
+                x: min.x,
+                y: max.y,
+                z: min.z,
+            },
+            Vert {
+                x: max.x,
+                y: max.y,
+                z: min.z,
+            },
+            Vert {
+                x: min.x,
+                y: min.y,
+                z: max.z,
+            },
+            Vert {
+                x: max.x,
+                y: min.y,
+                z: max.z,
+            },
+            Vert {
+                x: min.x,
+                y: max.y,
+                z: max.z,
+            },
+            Vert {
+                x: max.x,
+                y: max.y,
+                z: max.z,
+            },
+        ]
+    }
+
+    /// Transform the eight corners and fit a new axis aligned box around them
+    pub fn transform(&self, mat: &Array2<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let mut aabb = Aabb::empty();
+        for corner in self.corners() {
+            aabb.grow(mult_vec3_mat4(corner, mat));
+        }
+        aabb
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub centre: Vert,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// Centre the sphere on the middle of the bounding box and grow it to reach the furthest vertex.
+    /// Not the tightest possible sphere, but cheap and good enough for culling
+    pub fn from_verts(verts: &[Vert]) -> Self {
+        let centre = Aabb::from_verts(verts).centre();
+
+        let mut radius_sq = 0f32;
+        for vert in verts {
+            let d = *vert - centre;
+            radius_sq = radius_sq.max(dot_product(d, d));
+        }
+
+        Sphere {
+            centre,
+            radius: radius_sq.sqrt(),
+        }
+    }
+
+    /// Move the centre with the matrix and scale the radius by the largest axis scale in the matrix
+    pub fn transform(&self, mat: &Array2<f32>) -> Sphere {
+        let centre = mult_vec3_mat4(self.centre, mat);
+
+        let mut max_scale_sq = 0f32;
+        for row in 0..3 {
+            let axis = Vert {
+                x: mat[[row, 0]],
+                y: mat[[row, 1]],
+                z: mat[[row, 2]],
+            };
+            max_scale_sq = max_scale_sq.max(dot_product(axis, axis));
+        }
+
+        Sphere {
+            centre,
+            radius: self.radius * max_scale_sq.sqrt(),
+        }
+    }
+}
+
+/// Plane in the form normal.p + d = 0, points with a positive distance are on the inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vert,
+    pub d: f32,
+}
+
+impl Plane {
+    fn new(a: f32, b: f32, c: f32, d: f32) -> Self {
+        let length = (a * a + b * b + c * c).sqrt();
+        Plane {
+            normal: Vert {
+                x: a / length,
+                y: b / length,
+                z: c / length,
+            },
+            d: d / length,
+        }
+    }
+
+    pub fn distance(&self, p: Vert) -> f32 {
+        dot_product(self.normal, p) + self.d
+    }
+}
+
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extract the six clip planes from a combined view x projection matrix (Gribb/Hartmann).
+    /// Vectors multiply on the left of our matrices, so the clip space coordinates are
+    /// the columns of the matrix, e.g. the left plane is x_clip >= -w_clip.
+    /// The projection maps z into 0..1, hence near is simply z_clip >= 0
+    pub fn from_matrix(m: &Array2<f32>) -> Self {
+        let col = |c: usize| [m[[0, c]], m[[1, c]], m[[2, c]], m[[3, c]]];
+        let (x, y, z, w) = (col(0), col(1), col(2), col(3));
+
+        let plane = |s: f32, a: [f32; 4]| {
+            Plane::new(
+                w[0] + s * a[0],
+                w[1] + s * a[1],
+                w[2] + s * a[2],
+                w[3] + s * a[3],
+            )
+        };
+
+        Frustum {
+            planes: [
+                plane(1., x),                       // Left
+                plane(-1., x),                      // Right
+                plane(1., y),                       // Bottom
+                plane(-1., y),                      // Top
+                Plane::new(z[0], z[1], z[2], z[3]), // Near
+                plane(-1., z),                      // Far
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.distance(sphere.centre) >= -sphere.radius)
+    }
+
+    /// For each plane only the corner furthest along the plane normal needs testing,
+    /// if even that one is outside then the whole box is
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let p = Vert {
+                x: if plane.normal.x >= 0. {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                y: if plane.normal.y >= 0. {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                z: if plane.normal.z >= 0. {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            };
+            plane.distance(p) >= 0.
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::*;
+    use float_eq::assert_float_eq;
+
+    fn test_camera() -> Camera {
+        Camera {
+            fov: 60.,
+            near_plane: 0.1,
+            far_plane: 100.,
+            position: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            yaw: 0.,
+            fwd_speed: 1.,
+            yaw_speed: 5.,
+        }
+    }
+
+    fn test_frustum() -> Frustum {
+        let camera = test_camera();
+        let screen = Screen {
+            width: 800,
+            height: 600,
+        };
+        let view_proj = camera
+            .create_view_matrix()
+            .dot(&camera.create_projection_matrix(screen));
+        Frustum::from_matrix(&view_proj)
+    }
+
+    fn unit_box_at(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb {
+            min: vec3 {
+                x: x - 1.,
+                y: y - 1.,
+                z: z - 1.,
+            },
+            max: vec3 {
+                x: x + 1.,
+                y: y + 1.,
+                z: z + 1.,
+            },
+        }
+    }
+
+    #[test]
+    fn test_aabb_from_verts() {
+        let verts = [
+            vec3 {
+                x: 1.,
+                y: -2.,
+                z: 3.,
+            },
+            vec3 {
+                x: -4.,
+                y: 5.,
+                z: 0.,
+            },
+        ];
+
+        let aabb = Aabb::from_verts(&verts);
+
+        assert_eq!(
+            vec3 {
+                x: -4.,
+                y: -2.,
+                z: 0.
+            },
+            aabb.min
+        );
+        assert_eq!(
+            vec3 {
+                x: 1.,
+                y: 5.,
+                z: 3.
+            },
+            aabb.max
+        );
+    }
+
+    #[test]
+    fn test_sphere_contains_verts() {
+        let verts = unit_box_at(2., 3., 4.).corners();
+
+        let sphere = Sphere::from_verts(&verts);
+
+        assert_float_eq!(
+            vec3 {
+                x: 2.,
+                y: 3.,
+                z: 4.
+            },
+            sphere.centre,
+            abs_all <= 0.0001
+        );
+        assert_float_eq!(3f32.sqrt(), sphere.radius, abs <= 0.0001);
+    }
+
+    #[test]
+    fn test_aabb_transform() {
+        let aabb = unit_box_at(0., 0., 0.);
+        let mat = create_z_rotation_matrix(45.).dot(&create_translation_matrix(10., 0., 0.));
+
+        let result = aabb.transform(&mat);
+
+        let half_diag = 2f32.sqrt();
+        assert_float_eq!(10. - half_diag, result.min.x, abs <= 0.0001);
+        assert_float_eq!(10. + half_diag, result.max.x, abs <= 0.0001);
+        assert_float_eq!(-1., result.min.z, abs <= 0.0001);
+        assert_float_eq!(1., result.max.z, abs <= 0.0001);
+    }
+
+    #[test]
+    fn test_frustum_in_front() {
+        let frustum = test_frustum();
+        let aabb = unit_box_at(0., 0., 10.);
+
+        assert!(frustum.intersects_aabb(&aabb));
+        assert!(frustum.intersects_sphere(&Sphere::from_verts(&aabb.corners())));
+    }
+
+    #[test]
+    fn test_frustum_behind() {
+        let frustum = test_frustum();
+        let aabb = unit_box_at(0., 0., -10.);
+
+        assert!(!frustum.intersects_aabb(&aabb));
+        assert!(!frustum.intersects_sphere(&Sphere::from_verts(&aabb.corners())));
+    }
+
+    #[test]
+    fn test_frustum_off_to_side_and_beyond_far() {
+        let frustum = test_frustum();
+
+        assert!(!frustum.intersects_aabb(&unit_box_at(50., 0., 10.)));
+        assert!(!frustum.intersects_aabb(&unit_box_at(0., 50., 10.)));
+        assert!(!frustum.intersects_aabb(&unit_box_at(0., 0., 200.)));
+        // Straddling the left edge still counts as visible
+        assert!(frustum.intersects_aabb(&unit_box_at(-7.5, 0., 10.)));
+    }
+}
@@ -0,0 +1,712 @@
+use crate::bounds::{Aabb, Frustum};
+use crate::threed::*;
+
+/// Largest number of primitives stored in a leaf before it is split
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vert,
+    pub dir: Vert,
+}
+
+impl Ray {
+    /// Move the ray into another space, e.g. from world into model space with the inverse model matrix.
+    /// Distances along the ray are unchanged as long as the matrix has no scaling
+    pub fn transform(&self, mat: &ndarray::Array2<f32>) -> Ray {
+        Ray {
+            origin: mult_vec3_mat4(self.origin, mat),
+            dir: mult_dir_mat4(self.dir, mat),
+        }
+    }
+
+    /// Slab test, returns the distance along the ray at which it enters the box
+    pub fn intersect_aabb(&self, aabb: &Aabb, max_t: f32) -> Option<f32> {
+        let mut t_min = 0f32;
+        let mut t_max = max_t;
+
+        for axis in 0..3 {
+            let inv_dir = 1. / component(self.dir, axis);
+            let mut t0 = (component(aabb.min, axis) - component(self.origin, axis)) * inv_dir;
+            let mut t1 = (component(aabb.max, axis) - component(self.origin, axis)) * inv_dir;
+            if inv_dir < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
+    /// Möller–Trumbore ray/triangle intersection, triangles are hit from either side
+    pub fn intersect_tri(&self, tri: &Tri) -> Option<f32> {
+        let edge1 = tri.v2 - tri.v1;
+        let edge2 = tri.v3 - tri.v1;
+        let p = cross_product(self.dir, edge2);
+        let det = dot_product(edge1, p);
+
+        if det.abs() < f32::EPSILON {
+            // Ray is parallel to the triangle
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let s = self.origin - tri.v1;
+        let u = dot_product(s, p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+
+        let q = cross_product(s, edge1);
+        let v = dot_product(self.dir, q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+
+        let t = dot_product(edge2, q) * inv_det;
+        if t > 0. {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// Index of the primitive that was hit, e.g. the triangle index for a mesh
+    pub index: usize,
+    pub t: f32,
+}
+
+pub fn component(v: Vert, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+/// For an interior node (count == 0) the two children are at nodes[first] and nodes[first + 1].
+/// For a leaf the primitives are prim_indices[first..first + count]
+#[derive(Debug, Clone)]
+struct Node {
+    aabb: Aabb,
+    first: usize,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over any list of primitives that can be given a bounding box.
+/// Built with a median split along the longest axis of the primitive centres.
+/// Children are always stored after their parent, which lets refit work backwards through the nodes
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    prim_indices: Vec<usize>,
+    prim_aabbs: Vec<Aabb>,
+}
+
+impl Bvh {
+    pub fn build(prim_aabbs: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            prim_indices: (0..prim_aabbs.len()).collect(),
+            prim_aabbs: prim_aabbs.to_vec(),
+        };
+
+        if prim_aabbs.is_empty() {
+            return bvh;
+        }
+
+        let centres: Vec<Vert> = prim_aabbs.iter().map(|aabb| aabb.centre()).collect();
+
+        bvh.nodes.push(Node {
+            aabb: Aabb::empty(),
+            first: 0,
+            count: prim_aabbs.len(),
+        });
+        bvh.subdivide(0, &centres);
+
+        bvh
+    }
+
+    fn subdivide(&mut self, node_index: usize, centres: &[Vert]) {
+        let first = self.nodes[node_index].first;
+        let count = self.nodes[node_index].count;
+        let prims = first..first + count;
+
+        let mut aabb = Aabb::empty();
+        let mut centre_bounds = Aabb::empty();
+        for &prim in &self.prim_indices[prims.clone()] {
+            let prim_aabb = self.prim_aabbs[prim];
+            aabb.grow(prim_aabb.min);
+            aabb.grow(prim_aabb.max);
+            centre_bounds.grow(centres[prim]);
+        }
+        self.nodes[node_index].aabb = aabb;
+
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let extent = centre_bounds.max - centre_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        if component(extent, axis) <= 0. {
+            // All the centres are in the same place, splitting won't help
+            return;
+        }
+
+        let mid = count / 2;
+        self.prim_indices[prims].select_nth_unstable_by(mid, |a, b| {
+            component(centres[*a], axis).total_cmp(&component(centres[*b], axis))
+        });
+
+        let left = self.nodes.len();
+        self.nodes.push(Node {
+            aabb: Aabb::empty(),
+            first,
+            count: mid,
+        });
+        self.nodes.push(Node {
+            aabb: Aabb::empty(),
+            first: first + mid,
+            count: count - mid,
+        });
+
+        self.nodes[node_index].first = left;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, centres);
+        self.subdivide(left + 1, centres);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Update the node bounds after the primitives have moved, keeping the existing tree structure.
+    /// Much cheaper than rebuilding, but the tree gets less efficient the further things move
+    pub fn refit(&mut self, prim_aabbs: &[Aabb]) {
+        assert_eq!(self.prim_aabbs.len(), prim_aabbs.len());
+        self.prim_aabbs.copy_from_slice(prim_aabbs);
+
+        for node_index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[node_index];
+            let mut aabb = Aabb::empty();
+
+            if node.count > 0 {
+                for &prim in &self.prim_indices[node.first..node.first + node.count] {
+                    aabb.grow(self.prim_aabbs[prim].min);
+                    aabb.grow(self.prim_aabbs[prim].max);
+                }
+            } else {
+                for child in [node.first, node.first + 1] {
+                    aabb.grow(self.nodes[child].aabb.min);
+                    aabb.grow(self.nodes[child].aabb.max);
+                }
+            }
+
+            self.nodes[node_index].aabb = aabb;
+        }
+    }
+
+    /// Find the closest primitive hit by the ray.
+    /// `hit_prim` is called with a primitive index and should return the distance along the ray to it
+    pub fn intersect_ray_nearest<F>(&self, ray: &Ray, max_t: f32, mut hit_prim: F) -> Option<Hit>
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        let mut nearest: Option<Hit> = None;
+        let mut best_t = max_t;
+
+        self.traverse_ray(ray, max_t, |prim| {
+            if let Some(t) = hit_prim(prim) {
+                if t < best_t {
+                    best_t = t;
+                    nearest = Some(Hit { index: prim, t });
+                }
+            }
+            // Keep going, but only look at nodes closer than the best hit so far
+            Some(best_t)
+        });
+
+        nearest
+    }
+
+    /// Find any primitive hit by the ray closer than `max_t`, e.g. for shadow or line of sight tests
+    pub fn intersect_ray_any<F>(&self, ray: &Ray, max_t: f32, mut hit_prim: F) -> Option<Hit>
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        let mut found: Option<Hit> = None;
+
+        self.traverse_ray(ray, max_t, |prim| match hit_prim(prim) {
+            Some(t) if t < max_t => {
+                found = Some(Hit { index: prim, t });
+                None
+            }
+            _ => Some(max_t),
+        });
+
+        found
+    }
+
+    /// Walk the nodes hit by the ray, calling `visit` for each primitive in the leaves.
+    /// `visit` returns the new maximum distance to look at, or None to stop
+    fn traverse_ray<F>(&self, ray: &Ray, max_t: f32, mut visit: F)
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        if self.is_empty() {
+            return;
+        }
+
+        let mut max_t = max_t;
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if ray.intersect_aabb(&node.aabb, max_t).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &prim in &self.prim_indices[node.first..node.first + node.count] {
+                    match visit(prim) {
+                        Some(t) => max_t = t,
+                        None => return,
+                    }
+                }
+            } else {
+                // Push the further child first so the nearer one is looked at first
+                let left = node.first;
+                let right = node.first + 1;
+                let t_left = ray.intersect_aabb(&self.nodes[left].aabb, max_t);
+                let t_right = ray.intersect_aabb(&self.nodes[right].aabb, max_t);
+                match (t_left, t_right) {
+                    (Some(l), Some(r)) if l < r => stack.extend([right, left]),
+                    (Some(_), Some(_)) => stack.extend([left, right]),
+                    (Some(_), None) => stack.push(left),
+                    (None, Some(_)) => stack.push(right),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+
+    /// Indices of all the primitives whose bounding box overlaps `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.query(|node_aabb| overlaps(node_aabb, aabb))
+    }
+
+    /// Indices of all the primitives whose bounding box is at least partly inside the frustum
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.query(|node_aabb| frustum.intersects_aabb(node_aabb))
+    }
+
+    fn query<F>(&self, test: F) -> Vec<usize>
+    where
+        F: Fn(&Aabb) -> bool,
+    {
+        let mut found = Vec::new();
+        if self.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !test(&node.aabb) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &prim in &self.prim_indices[node.first..node.first + node.count] {
+                    if test(&self.prim_aabbs[prim]) {
+                        found.push(prim);
+                    }
+                }
+            } else {
+                stack.extend([node.first, node.first + 1]);
+            }
+        }
+
+        found
+    }
+}
+
+pub fn overlaps(a: &Aabb, b: &Aabb) -> bool {
+    a.min.x <= b.max.x
+        && a.max.x >= b.min.x
+        && a.min.y <= b.max.y
+        && a.max.y >= b.min.y
+        && a.min.z <= b.max.z
+        && a.max.z >= b.min.z
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneHit {
+    pub object: usize,
+    pub tri: usize,
+    pub t: f32,
+}
+
+/// Top level hierarchy over the world space bounds of the objects in the scene.
+/// Each mesh has its own hierarchy in model space, so as objects move only this one needs refitting
+#[derive(Debug, Clone, Default)]
+pub struct SceneBvh {
+    bvh: Bvh,
+}
+
+impl SceneBvh {
+    pub fn build(objects: &[Object]) -> Self {
+        SceneBvh {
+            bvh: Bvh::build(&world_aabbs(objects)),
+        }
+    }
+
+    /// Call after object transforms have changed.
+    /// If objects have been added or removed the hierarchy is rebuilt instead
+    pub fn refit(&mut self, objects: &[Object]) {
+        let aabbs = world_aabbs(objects);
+        if aabbs.len() == self.bvh.prim_aabbs.len() {
+            self.bvh.refit(&aabbs);
+        } else {
+            self.bvh = Bvh::build(&aabbs);
+        }
+    }
+
+    pub fn intersect_ray_nearest(&self, objects: &[Object], ray: &Ray) -> Option<SceneHit> {
+        let mut nearest: Option<SceneHit> = None;
+
+        self.bvh.intersect_ray_nearest(ray, f32::MAX, |object| {
+            let hit = intersect_object(&objects[object], ray, None)?;
+            if nearest.is_none_or(|nearest| hit.t < nearest.t) {
+                nearest = Some(SceneHit {
+                    object,
+                    tri: hit.index,
+                    t: hit.t,
+                });
+            }
+            Some(hit.t)
+        });
+
+        nearest
+    }
+
+    pub fn intersect_ray_any(&self, objects: &[Object], ray: &Ray, max_t: f32) -> bool {
+        self.bvh
+            .intersect_ray_any(ray, max_t, |object| {
+                intersect_object(&objects[object], ray, Some(max_t)).map(|hit| hit.t)
+            })
+            .is_some()
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.bvh.query_aabb(aabb)
+    }
+
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.bvh.query_frustum(frustum)
+    }
+}
+
+fn world_aabbs(objects: &[Object]) -> Vec<Aabb> {
+    objects
+        .iter()
+        .map(|object| object.mesh.aabb.transform(&object.transform.model_matrix()))
+        .collect()
+}
+
+/// Move a world space ray into the object's model space and test it against the mesh hierarchy.
+/// With `any_within` it stops at the first hit closer than that, otherwise it finds the nearest.
+/// Distances along the ray are the same in both spaces, as its origin and direction are moved
+/// by the same matrix
+fn intersect_object(object: &Object, ray: &Ray, any_within: Option<f32>) -> Option<Hit> {
+    let inv_model_mat = quick_invert_mat4(object.transform.model_matrix());
+    let model_ray = ray.transform(&inv_model_mat);
+
+    if let Some(max_t) = any_within {
+        object.mesh.intersect_ray_any(&model_ray, max_t)
+    } else {
+        object.mesh.intersect_ray_nearest(&model_ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::*;
+    use crate::bvh::*;
+    use crate::colour::Colour;
+    use crate::mesh::Mesh;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_vert(rng: &mut StdRng, range: f32) -> Vert {
+        Vert {
+            x: rng.gen_range(-range..range),
+            y: rng.gen_range(-range..range),
+            z: rng.gen_range(-range..range),
+        }
+    }
+
+    /// A soup of small random triangles scattered through a cube
+    fn random_mesh(rng: &mut StdRng, num_tris: usize) -> Mesh {
+        let mut verts = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..num_tris {
+            let centre = random_vert(rng, 10.);
+            for _ in 0..3 {
+                verts.push(centre + random_vert(rng, 1.));
+            }
+            indices.push([3 * i, 3 * i + 1, 3 * i + 2]);
+        }
+        Mesh::new(verts, indices)
+    }
+
+    fn random_ray(rng: &mut StdRng) -> Ray {
+        let origin = random_vert(rng, 20.);
+        let target = random_vert(rng, 5.);
+        Ray {
+            origin,
+            dir: normalise_vec(&(target - origin)),
+        }
+    }
+
+    fn brute_force_nearest(mesh: &Mesh, ray: &Ray) -> Option<Hit> {
+        let mut nearest: Option<Hit> = None;
+        for i in 0..mesh.indices.len() {
+            if let Some(t) = ray.intersect_tri(&mesh.tri(i)) {
+                if nearest.is_none_or(|hit| t < hit.t) {
+                    nearest = Some(Hit { index: i, t });
+                }
+            }
+        }
+        nearest
+    }
+
+    fn tri_aabbs(mesh: &Mesh) -> Vec<Aabb> {
+        (0..mesh.indices.len())
+            .map(|i| {
+                let tri = mesh.tri(i);
+                Aabb::from_verts(&[tri.v1, tri.v2, tri.v3])
+            })
+            .collect()
+    }
+
+    fn sorted(mut v: Vec<usize>) -> Vec<usize> {
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn test_ray_nearest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mesh = random_mesh(&mut rng, 500);
+
+        let mut num_hits = 0;
+        for _ in 0..500 {
+            let ray = random_ray(&mut rng);
+            let expected = brute_force_nearest(&mesh, &ray);
+            let result = mesh.intersect_ray_nearest(&ray);
+            assert_eq!(expected, result);
+            if result.is_some() {
+                num_hits += 1;
+            }
+        }
+
+        // Make sure the test is actually hitting things
+        assert!(num_hits > 50);
+    }
+
+    #[test]
+    fn test_ray_any_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mesh = random_mesh(&mut rng, 500);
+
+        for _ in 0..500 {
+            let ray = random_ray(&mut rng);
+            let max_t = rng.gen_range(1f32..40.);
+            let expected = brute_force_nearest(&mesh, &ray).is_some_and(|hit| hit.t < max_t);
+
+            let result = mesh.intersect_ray_any(&ray, max_t);
+
+            assert_eq!(expected, result.is_some());
+            if let Some(hit) = result {
+                assert!(hit.t < max_t);
+                assert_eq!(Some(hit.t), ray.intersect_tri(&mesh.tri(hit.index)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_aabb_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mesh = random_mesh(&mut rng, 500);
+        let aabbs = tri_aabbs(&mesh);
+
+        for _ in 0..100 {
+            let a = random_vert(&mut rng, 10.);
+            let b = random_vert(&mut rng, 10.);
+            let query = Aabb::from_verts(&[a, b]);
+
+            let expected: Vec<usize> = (0..aabbs.len())
+                .filter(|&i| overlaps(&aabbs[i], &query))
+                .collect();
+
+            assert_eq!(expected, sorted(mesh.bvh.query_aabb(&query)));
+        }
+    }
+
+    #[test]
+    fn test_query_frustum_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mesh = random_mesh(&mut rng, 500);
+        let aabbs = tri_aabbs(&mesh);
+
+        for _ in 0..20 {
+            let camera = Camera {
+                fov: 60.,
+                near_plane: 0.1,
+                far_plane: rng.gen_range(5f32..30.),
+                position: random_vert(&mut rng, 15.),
+                yaw: rng.gen_range(0f32..360.),
+                fwd_speed: 1.,
+                yaw_speed: 5.,
+            };
+            let screen = Screen {
+                width: 800,
+                height: 600,
+            };
+            let view_proj = camera
+                .create_view_matrix()
+                .dot(&camera.create_projection_matrix(screen));
+            let frustum = Frustum::from_matrix(&view_proj);
+
+            let expected: Vec<usize> = (0..aabbs.len())
+                .filter(|&i| frustum.intersects_aabb(&aabbs[i]))
+                .collect();
+
+            assert_eq!(expected, sorted(mesh.bvh.query_frustum(&frustum)));
+        }
+    }
+
+    #[test]
+    fn test_teapot_ray_nearest_matches_brute_force() {
+        let mesh = Mesh::from_obj_file("Resource/Models/teapot.obj").unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+
+        for _ in 0..100 {
+            let origin = random_vert(&mut rng, 10.);
+            let target = random_vert(&mut rng, 1.);
+            let ray = Ray {
+                origin,
+                dir: normalise_vec(&(target - origin)),
+            };
+
+            assert_eq!(
+                brute_force_nearest(&mesh, &ray),
+                mesh.intersect_ray_nearest(&ray)
+            );
+        }
+    }
+
+    fn cube_object(x: f32, y: f32, z: f32) -> Object {
+        let mesh = Mesh::from_obj_file("Resource/Models/cube.obj").unwrap();
+        let transform = Transform {
+            position: vec3 { x, y, z },
+            rotation: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+        };
+        Object::new("cube".to_string(), mesh, transform, Colour::new(0, 0, 0))
+    }
+
+    #[test]
+    fn test_scene_refit() {
+        let mut objects = vec![
+            cube_object(0., 0., 10.),
+            cube_object(0., 0., 20.),
+            cube_object(5., 0., 10.),
+        ];
+        let mut scene_bvh = SceneBvh::build(&objects);
+
+        let ray = Ray {
+            origin: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            dir: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        };
+
+        let hit = scene_bvh.intersect_ray_nearest(&objects, &ray).unwrap();
+        assert_eq!(0, hit.object);
+        assert!((hit.t - 9.).abs() < 0.0001);
+
+        // Move the nearest cube out of the way, the one behind it should now be hit
+        objects[0].transform.position.x = -5.;
+        objects[0].transform.rotation.y = 30.;
+        scene_bvh.refit(&objects);
+
+        let hit = scene_bvh.intersect_ray_nearest(&objects, &ray).unwrap();
+        assert_eq!(1, hit.object);
+        assert!((hit.t - 19.).abs() < 0.0001);
+        assert!(scene_bvh.intersect_ray_any(&objects, &ray, 100.));
+        assert!(!scene_bvh.intersect_ray_any(&objects, &ray, 10.));
+
+        let query = Aabb {
+            min: vec3 {
+                x: -7.,
+                y: -1.,
+                z: 9.,
+            },
+            max: vec3 {
+                x: -4.,
+                y: 1.,
+                z: 11.,
+            },
+        };
+        assert_eq!(vec![0], scene_bvh.query_aabb(&query));
+    }
+
+    #[test]
+    fn test_scene_any_hit_before_max_t() {
+        let objects = vec![cube_object(0., 0., 10.)];
+        let scene_bvh = SceneBvh::build(&objects);
+        let ray = Ray {
+            origin: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            dir: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        };
+
+        // Ending between the front face at z = 9 and the back face at z = 11, only the front
+        // face is in reach
+        assert!(scene_bvh.intersect_ray_any(&objects, &ray, 10.));
+        assert!(!scene_bvh.intersect_ray_any(&objects, &ray, 8.5));
+    }
+}
@@ -0,0 +1,352 @@
+//! Lines drawn over the frame to show what the renderer is working with: normals, bounding boxes,
+//! axes, the light and other cameras. Everything is given in world space and goes through the
+//! same view and projection as the scene. Lines added to a `DebugDraw` are drawn over the next
+//! frame only, the overlays add theirs again every frame they are on.
+
+use crate::bounds::Aabb;
+use crate::line::LineStyle;
+use crate::mesh::Mesh;
+use crate::renderer::faces_camera;
+use crate::scene::Scene;
+use crate::threed::*;
+
+pub const AXIS_X_COLOUR: u32 = 0xff4040;
+pub const AXIS_Y_COLOUR: u32 = 0x40ff40;
+pub const AXIS_Z_COLOUR: u32 = 0x4080ff;
+const FACE_NORMAL_COLOUR: u32 = 0xffff00;
+/// Normals of faces that `process_tri` leaves out as facing away from the camera
+const CULLED_FACE_NORMAL_COLOUR: u32 = 0x804040;
+const VERTEX_NORMAL_COLOUR: u32 = 0x00ffff;
+const BOUNDS_COLOUR: u32 = 0xff80ff;
+const LIGHT_COLOUR: u32 = 0xffffa0;
+const FRUSTUM_COLOUR: u32 = 0xffffff;
+
+/// Straight lines making up each circle of a sphere
+const CIRCLE_SEGMENTS: usize = 24;
+/// Normals are drawn this fraction of their object's bounding radius long
+const NORMAL_LENGTH: f32 = 0.15;
+const WORLD_AXIS_LENGTH: f32 = 1.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+    pub from: vec3,
+    pub to: vec3,
+    pub colour: u32,
+}
+
+/// World space lines waiting to be drawn over the next frame
+#[derive(Debug, Clone)]
+pub struct DebugDraw {
+    pub lines: Vec<DebugLine>,
+    /// How every debug line is drawn. Depth tested by default, so the scene hides them
+    pub style: LineStyle,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        DebugDraw {
+            lines: Vec::new(),
+            style: LineStyle {
+                depth_test: true,
+                ..LineStyle::default()
+            },
+        }
+    }
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: vec3, to: vec3, colour: u32) {
+        self.lines.push(DebugLine { from, to, colour });
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, colour: u32) {
+        if !aabb.is_empty() {
+            self.box_corners(&aabb.corners(), colour);
+        }
+    }
+
+    /// The 12 edges of a box, with the corners in the order `Aabb::corners` gives them. The
+    /// corners can be transformed first to draw a box that isn't axis aligned
+    pub fn box_corners(&mut self, corners: &[vec3; 8], colour: u32) {
+        // Corner i has its x, y and z at the max when bits 0, 1 and 2 are set
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], colour);
+                }
+            }
+        }
+    }
+
+    /// A circle around each axis
+    pub fn sphere(&mut self, centre: vec3, radius: f32, colour: u32) {
+        let (x, y, z) = (axis(0), axis(1), axis(2));
+        for (u, v) in [(x, y), (y, z), (z, x)] {
+            self.circle(centre, u, v, radius, colour);
+        }
+    }
+
+    /// A line from `from` with a four sided head on the `to` end
+    pub fn arrow(&mut self, from: vec3, to: vec3, colour: u32) {
+        self.line(from, to, colour);
+
+        let shaft = to - from;
+        let length = dot_product(shaft, shaft).sqrt();
+        if length == 0. {
+            return;
+        }
+        let dir = scaled(shaft, 1. / length);
+        let (u, v) = perpendiculars(dir);
+        let head = 0.2 * length;
+        let base = to - scaled(dir, head);
+        for side in [u, v, scaled(u, -1.), scaled(v, -1.)] {
+            self.line(to, base + scaled(side, 0.5 * head), colour);
+        }
+    }
+
+    /// X, y and z arrows from the origin of `mat`, red, green and blue
+    pub fn axes(&mut self, mat: &ndarray::Array2<f32>, length: f32) {
+        let origin = mult_vec3_mat4(vec3::default(), mat);
+        for (i, colour) in [AXIS_X_COLOUR, AXIS_Y_COLOUR, AXIS_Z_COLOUR]
+            .into_iter()
+            .enumerate()
+        {
+            let dir = normalise_vec(&mult_dir_mat4(axis(i), mat));
+            self.arrow(origin, origin + scaled(dir, length), colour);
+        }
+    }
+
+    /// The volume a camera sees, out to its far plane. `aspect` is its view's width over height
+    pub fn frustum(&mut self, camera: &Camera, aspect: f32, colour: u32) {
+        // The same way round as the camera's view matrix
+        let forward = mult_dir_mat4(axis(2), &create_y_rotation_matrix(camera.yaw));
+        let up = axis(1);
+        let right = cross_product(up, forward);
+        let tan = (camera.fov / 2.).to_radians().tan();
+
+        let rect = |distance: f32| {
+            let centre = camera.position + scaled(forward, distance);
+            let (half_height, half_width) = (distance * tan, distance * tan * aspect);
+            [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+                .map(|(x, y)| centre + scaled(right, x * half_width) + scaled(up, y * half_height))
+        };
+        let near = rect(camera.near_plane);
+        let far = rect(camera.far_plane);
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(near[i], near[next], colour);
+            self.line(far[i], far[next], colour);
+            self.line(camera.position, far[i], colour);
+        }
+    }
+
+    fn circle(&mut self, centre: vec3, u: vec3, v: vec3, radius: f32, colour: u32) {
+        let point = |i: usize| {
+            let angle = i as f32 * std::f32::consts::TAU / CIRCLE_SEGMENTS as f32;
+            centre + scaled(u, radius * angle.cos()) + scaled(v, radius * angle.sin())
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), colour);
+        }
+    }
+}
+
+/// Which overlays are drawn over the scene
+#[derive(Clone, Default)]
+pub struct DebugOverlays {
+    /// One from the middle of each face, dim for faces culled as facing away
+    pub face_normals: bool,
+    /// From each vertex, the mesh's own normals if it has them, otherwise the average of the
+    /// faces around the vertex
+    pub vertex_normals: bool,
+    /// Each object's world space bounding box
+    pub bounds: bool,
+    pub world_axes: bool,
+    /// Each object's position and rotation
+    pub local_axes: bool,
+    /// Where the light shines from, outside the scene pointing in
+    pub light: bool,
+    /// Shows what another camera can see. Usually a copy of the scene camera, left behind to
+    /// look at from somewhere else
+    pub frustum_camera: Option<Camera>,
+}
+
+impl DebugOverlays {
+    /// Add the lines for every overlay that is on. `aspect` is the frame's width over height
+    pub fn draw(&self, scene: &Scene, aspect: f32, debug: &mut DebugDraw) {
+        if self.world_axes {
+            debug.axes(&ndarray::Array2::eye(4), WORLD_AXIS_LENGTH);
+        }
+
+        let mut scene_bounds = Aabb::empty();
+        for object in &scene.objects {
+            let model_mat = object.transform.model_matrix();
+            let mesh = object.active_mesh();
+            let radius = object.mesh.sphere.radius;
+            let world_bounds = object.mesh.aabb.transform(&model_mat);
+            scene_bounds.grow(world_bounds.min);
+            scene_bounds.grow(world_bounds.max);
+
+            if self.bounds {
+                debug.aabb(&world_bounds, BOUNDS_COLOUR);
+            }
+            if self.local_axes {
+                debug.axes(&model_mat, 1.25 * radius);
+            }
+            if self.face_normals {
+                for i in 0..mesh.indices.len() {
+                    let tri = mesh.tri(i);
+                    let tri = Tri {
+                        v1: mult_vec3_mat4(tri.v1, &model_mat),
+                        v2: mult_vec3_mat4(tri.v2, &model_mat),
+                        v3: mult_vec3_mat4(tri.v3, &model_mat),
+                    };
+                    let normal = normal(&tri);
+                    let centre = scaled(tri.v1 + tri.v2 + tri.v3, 1. / 3.);
+                    let colour = if faces_camera(&normal) {
+                        FACE_NORMAL_COLOUR
+                    } else {
+                        CULLED_FACE_NORMAL_COLOUR
+                    };
+                    debug.line(
+                        centre,
+                        centre + scaled(normal, NORMAL_LENGTH * radius),
+                        colour,
+                    );
+                }
+            }
+            if self.vertex_normals {
+                for (vert, normal) in mesh.verts.iter().zip(vertex_normals(mesh)) {
+                    let world = mult_vec3_mat4(*vert, &model_mat);
+                    let normal = normalise_vec(&mult_dir_mat4(normal, &model_mat));
+                    debug.line(
+                        world,
+                        world + scaled(normal, NORMAL_LENGTH * radius),
+                        VERTEX_NORMAL_COLOUR,
+                    );
+                }
+            }
+        }
+
+        if self.light && !scene_bounds.is_empty() {
+            let centre = scene_bounds.centre();
+            let half_size = scene_bounds.max - centre;
+            let radius = dot_product(half_size, half_size).sqrt().max(1.);
+            let position = centre + scaled(normalise_vec(&scene.light_dir), 1.5 * radius);
+            debug.sphere(position, 0.08 * radius, LIGHT_COLOUR);
+            debug.arrow(
+                position,
+                position + scaled(centre - position, 0.5),
+                LIGHT_COLOUR,
+            );
+        }
+
+        if let Some(camera) = &self.frustum_camera {
+            debug.frustum(camera, aspect, FRUSTUM_COLOUR);
+        }
+    }
+}
+
+/// The mesh's normals, or if it has none the normalised sum of the normals of the faces using
+/// each vertex
+fn vertex_normals(mesh: &Mesh) -> Vec<vec3> {
+    if mesh.normals.len() == mesh.verts.len() {
+        return mesh.normals.clone();
+    }
+
+    let mut sums = vec![vec3::default(); mesh.verts.len()];
+    for (i, index) in mesh.indices.iter().enumerate() {
+        let normal = normal(&mesh.tri(i));
+        for &vertex in index {
+            sums[vertex] = sums[vertex] + normal;
+        }
+    }
+    sums.iter()
+        .map(|sum| {
+            if dot_product(*sum, *sum) > 0. {
+                normalise_vec(sum)
+            } else {
+                *sum
+            }
+        })
+        .collect()
+}
+
+fn axis(i: usize) -> vec3 {
+    let mut v = [0.; 3];
+    v[i] = 1.;
+    vec3 {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    }
+}
+
+fn scaled(v: vec3, s: f32) -> vec3 {
+    vec3 {
+        x: v.x * s,
+        y: v.y * s,
+        z: v.z * s,
+    }
+}
+
+/// Two unit vectors at right angles to `dir` and each other
+fn perpendiculars(dir: vec3) -> (vec3, vec3) {
+    // Crossing with whichever axis is furthest from the direction keeps the result well sized
+    let other = if dir.x.abs() < 0.9 { axis(0) } else { axis(1) };
+    let u = normalise_vec(&cross_product(dir, other));
+    let v = cross_product(dir, u);
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug_draw::*;
+
+    #[test]
+    fn test_box_has_twelve_edges() {
+        let mut debug = DebugDraw::default();
+        let aabb = Aabb {
+            min: vec3 {
+                x: -1.,
+                y: -2.,
+                z: -3.,
+            },
+            max: vec3 {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+        };
+
+        debug.aabb(&aabb, 0xffffff);
+
+        assert_eq!(12, debug.lines.len());
+        // Every edge runs along exactly one axis, the full length of the box on that axis
+        for line in &debug.lines {
+            let d = line.to - line.from;
+            let lengths = [d.x, d.y, d.z].map(f32::abs);
+            let along: Vec<f32> = lengths.into_iter().filter(|l| *l > 0.).collect();
+            assert_eq!(1, along.len());
+            assert!([2., 4., 6.].contains(&along[0]));
+        }
+    }
+
+    #[test]
+    fn test_arrow_head_at_tip() {
+        let mut debug = DebugDraw::default();
+        let to = vec3 {
+            x: 0.,
+            y: 5.,
+            z: 0.,
+        };
+
+        debug.arrow(vec3::default(), to, 0xffffff);
+
+        assert_eq!(5, debug.lines.len());
+        for line in &debug.lines[1..] {
+            assert_eq!(to, line.from);
+            assert!(line.to.y < to.y && line.to.y > 3.);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Views of what the rasterizer is doing instead of the lit scene: how far away each pixel is,
+//! which way the surface faces, how many times each pixel was written and which triangle it
+//! came from.
+
+use serde::{Deserialize, Serialize};
+
+use crate::raster::{RasterTarget, Tri};
+use crate::renderer::{FrameBuffer, Projection};
+use crate::threed::*;
+
+/// Colours from cold to hot, spaced evenly
+const HEAT_STOPS: [u32; 6] = [0x30123b, 0x4686fb, 0x1be5b5, 0xa4fc3c, 0xfb8022, 0x7a0403];
+/// Writes to a pixel that show as the hottest colour in the overdraw view
+const MAX_OVERDRAW: u32 = 8;
+/// Top byte set, which no 0RGB colour has, so a pixel holding it was just written
+const WRITE_MARKER: u32 = 0xff000000;
+
+/// What the pixels show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DebugView {
+    /// The lit scene
+    #[default]
+    Off,
+    /// Distance from the camera, from red for the nearest pixel in the frame to blue for the
+    /// furthest
+    Depth,
+    /// The world space normal of the triangle under each pixel, x, y and z as red, green and
+    /// blue with -1 dark and 1 bright
+    Normals,
+    /// Times each pixel was written, from blue for once to red for 8 or more
+    Overdraw,
+    /// A different colour for each triangle of each object, the same from frame to frame
+    TriangleId,
+}
+
+impl DebugView {
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Off => "off",
+            DebugView::Depth => "depth",
+            DebugView::Normals => "normals",
+            DebugView::Overdraw => "overdraw",
+            DebugView::TriangleId => "triangle ids",
+        }
+    }
+}
+
+/// A colour from cold at 0 to hot at 1
+pub fn heat_colour(t: f32) -> u32 {
+    let scaled = t.clamp(0., 1.) * (HEAT_STOPS.len() - 1) as f32;
+    let i = (scaled as usize).min(HEAT_STOPS.len() - 2);
+    let f = scaled - i as f32;
+    let (a, b) = (HEAT_STOPS[i], HEAT_STOPS[i + 1]);
+    let channel = |shift: u32| {
+        let a = ((a >> shift) & 0xff) as f32;
+        let b = ((b >> shift) & 0xff) as f32;
+        ((a + (b - a) * f).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+pub fn normal_colour(normal: &vec3) -> u32 {
+    let channel = |v: f32| ((v.clamp(-1., 1.) * 0.5 + 0.5) * 255.).round() as u32;
+    (channel(normal.x) << 16) | (channel(normal.y) << 8) | channel(normal.z)
+}
+
+/// A bright colour picked by hashing the object and the triangle's place in its mesh
+pub fn triangle_id_colour(object: usize, triangle: usize) -> u32 {
+    // splitmix64's finaliser, so neighbouring triangles get unrelated colours
+    let mut h = ((object as u64) << 32 | triangle as u64).wrapping_add(0x9e3779b97f4a7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    // Kept away from black so no triangle disappears into the background
+    (h as u32 & 0xffffff) | 0x404040
+}
+
+/// Replace the frame with its depth, linearised back to view space distance and coloured from
+/// the nearest pixel to the furthest. Pixels nothing was drawn on are left as they are
+pub(crate) fn show_depth(framebuffer: &mut FrameBuffer, projection: &Projection) {
+    let distances: Vec<f32> = framebuffer
+        .depth
+        .iter()
+        .map(|z| projection.view_depth(*z))
+        .collect();
+    let (near, far) = distances
+        .iter()
+        .filter(|d| d.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(near, far), d| {
+            (near.min(*d), far.max(*d))
+        });
+    let range = (far - near).max(f32::EPSILON);
+
+    for (pixel, distance) in framebuffer.pixels.iter_mut().zip(distances) {
+        if distance.is_finite() {
+            *pixel = heat_colour(1. - (distance - near) / range);
+        }
+    }
+}
+
+/// Draw triangles with `draw`, counting the writes to each pixel, then colour the pixels that
+/// were written by how many times. `draw` is given the marker colour to draw each one in
+pub(crate) fn draw_overdraw<'a, T: RasterTarget>(
+    target: &mut T,
+    tris: impl Iterator<Item = &'a Tri>,
+    mut draw: impl FnMut(&mut T, &Tri, u32),
+) {
+    let rect = target.rect();
+    let mut writes = vec![0u32; rect.width * rect.height];
+
+    for tri in tris {
+        draw(target, tri, WRITE_MARKER);
+
+        // Only pixels around the triangle can have been written, a pixel out either way allows
+        // for the scanline rasterizer's rounding
+        let xs = [tri.p1.x, tri.p2.x, tri.p3.x];
+        let ys = [tri.p1.y, tri.p2.y, tri.p3.y];
+        let span = |vs: [f32; 3], start: usize, len: usize| {
+            let min = vs.iter().fold(f32::INFINITY, |m, v| m.min(*v));
+            let max = vs.iter().fold(f32::NEG_INFINITY, |m, v| m.max(*v));
+            let from = ((min.floor() - 1.).max(0.) as usize).max(start);
+            let to = ((max.ceil() + 2.).max(0.) as usize).min(start + len);
+            from..to.max(from)
+        };
+        let columns = span(xs, rect.x, rect.width);
+        let rows = span(ys, rect.y, rect.height);
+
+        let (pixels, _) = target.buffers();
+        for y in rows {
+            let row = (rect.y + rect.height - y - 1) * rect.width;
+            for x in columns.clone() {
+                let i = row + x - rect.x;
+                if pixels[i] == WRITE_MARKER {
+                    writes[i] += 1;
+                    pixels[i] = 0;
+                }
+            }
+        }
+    }
+
+    let (pixels, _) = target.buffers();
+    for (pixel, count) in pixels.iter_mut().zip(writes) {
+        if count > 0 {
+            *pixel = heat_colour((count - 1) as f32 / (MAX_OVERDRAW - 1) as f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug_view::*;
+
+    #[test]
+    fn test_heat_colour_ends() {
+        assert_eq!(HEAT_STOPS[0], heat_colour(0.));
+        assert_eq!(HEAT_STOPS[5], heat_colour(1.));
+        assert_eq!(HEAT_STOPS[5], heat_colour(7.));
+    }
+
+    #[test]
+    fn test_normal_colour() {
+        let up = vec3 {
+            x: 0.,
+            y: 1.,
+            z: -1.,
+        };
+        assert_eq!(0x80ff00, normal_colour(&up));
+    }
+
+    #[test]
+    fn test_triangle_ids_differ() {
+        let colours: Vec<u32> = (0..100).map(|i| triangle_id_colour(0, i)).collect();
+        for (i, a) in colours.iter().enumerate() {
+            assert!(colours[i + 1..].iter().all(|b| a != b));
+        }
+        assert_ne!(triangle_id_colour(0, 5), triangle_id_colour(1, 5));
+    }
+}
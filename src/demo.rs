@@ -0,0 +1,93 @@
+//! The built in demo scene, a cube and a teapot above the ground grid
+
+use crate::colour::*;
+use crate::lod::Lods;
+use crate::resources::model_path;
+use crate::scene::Scene;
+use crate::threed::*;
+
+/// The cube and teapot. There is no floor, the viewer shows them over the ground grid
+pub fn init_scene() -> Scene {
+    let cam_pos = vec3 {
+        x: 0.,
+        y: 5.,
+        z: -20.,
+    };
+
+    let camera = Camera {
+        fov: 60.,
+        near_plane: 0.1,
+        far_plane: 1000.,
+        position: cam_pos,
+        yaw: 0.,
+        fwd_speed: 1.,
+        yaw_speed: 5.,
+    };
+
+    let light_dir = vec3 {
+        x: 0.,
+        y: 10.,
+        z: -10.,
+    };
+
+    let objects = vec![
+        init_cube(),
+        init_teapot(0., 0., -8.),
+        // init_spaceship(-5., 2., 5.),
+    ];
+
+    Scene::new(objects, camera, light_dir)
+}
+
+// let transform = Transform { position, rotation };
+// // Object::create_from_file("cube".to_string(), path.to_string(), transform, albedo).unwrap()
+
+pub fn init_cube() -> Object {
+    let model_path = model_path("cube.obj");
+
+    let position = vec3 {
+        x: 3.,
+        y: 3.,
+        z: 3.,
+    };
+    let rotation = vec3 {
+        x: 45.,
+        y: 45.,
+        z: 45.,
+    };
+    let transform = Transform { position, rotation };
+    let albedo = Colour::new(42, 170, 255);
+    // Object::create_from_file("cube".to_string(), path.to_string(), transform, albedo).unwrap()
+    Object::create_from_file("cube".to_string(), model_path, transform, albedo).unwrap()
+}
+
+pub fn init_teapot(x: f32, y: f32, z: f32) -> Object {
+    let model_path = model_path("teapot.obj");
+
+    let position = vec3 { x, y, z };
+    let rotation = vec3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    let transform = Transform { position, rotation };
+    let albedo = Colour::new(1, 204, 3);
+    let mut teapot =
+        Object::create_from_file("teapot".to_string(), model_path, transform, albedo).unwrap();
+    teapot.lods = Lods::generate(&teapot.mesh, &[(0.5, 0.3), (0.2, 0.15), (0.05, 0.05)]);
+    teapot
+}
+
+pub fn _init_spaceship(x: f32, y: f32, z: f32) -> Object {
+    let model_path = model_path("Spaceship.obj");
+
+    let position = vec3 { x, y, z };
+    let rotation = vec3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    let transform = Transform { position, rotation };
+    let albedo = Colour::new(1, 204, 3);
+    Object::create_from_file("teapot".to_string(), model_path, transform, albedo).unwrap()
+}
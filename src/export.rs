@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::renderer::FrameBuffer;
+
+/// Save the colour buffer, the format is picked from the file extension (.ppm, .bmp or .png)
+pub fn save_image(path: &str, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    match extension(path).as_str() {
+        "ppm" => write_ppm(path, framebuffer),
+        "bmp" => write_bmp(path, framebuffer),
+        "png" => write_png(path, framebuffer),
+        _ => Err(unsupported_format(path)),
+    }
+}
+
+/// Save the depth buffer as a 16-bit greyscale image (.png or .pgm).
+/// Depths are stretched over the nearest to furthest covered pixel so the image uses the
+/// whole range, near is black and far is white. Empty pixels are white
+pub fn save_depth(path: &str, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    let grey = depth_to_grey(&framebuffer.depth);
+    match extension(path).as_str() {
+        "png" => write_grey16_png(path, framebuffer.width, framebuffer.height, &grey),
+        "pgm" => write_grey16_pgm(path, framebuffer.width, framebuffer.height, &grey),
+        _ => Err(unsupported_format(path)),
+    }
+}
+
+/// Write the colour buffer as a binary PPM (P6), rows are written top first as they are stored
+pub fn write_ppm(path: &str, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encode_ppm(framebuffer))?;
+    file.flush()
+}
+
+/// Write the colour buffer as an uncompressed 24-bit BMP
+pub fn write_bmp(path: &str, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encode_bmp(framebuffer))?;
+    file.flush()
+}
+
+pub fn write_png(path: &str, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    let file = BufWriter::new(File::create(path)?);
+    encode_png(file, framebuffer)
+}
+
+/// File name for a screenshot taken now, e.g. screenshot_20240131_235959.png (UTC)
+pub fn screenshot_name() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time_of_day = secs % 86400;
+    format!(
+        "screenshot_{year:04}{month:02}{day:02}_{:02}{:02}{:02}.png",
+        time_of_day / 3600,
+        (time_of_day / 60) % 60,
+        time_of_day % 60
+    )
+}
+
+fn encode_ppm(framebuffer: &FrameBuffer) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", framebuffer.width, framebuffer.height).into_bytes();
+    for pixel in &framebuffer.pixels {
+        let [_, r, g, b] = pixel.to_be_bytes();
+        data.extend_from_slice(&[r, g, b]);
+    }
+    data
+}
+
+/// BMP stores the bottom row first, as BGR, with each row padded to a multiple of 4 bytes
+fn encode_bmp(framebuffer: &FrameBuffer) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 14 + 40;
+
+    let width = framebuffer.width;
+    let row_size = (width * 3 + 3) & !3;
+    let image_size = (row_size * framebuffer.height) as u32;
+
+    let mut data = Vec::with_capacity((HEADER_SIZE + image_size) as usize);
+
+    // File header
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&(HEADER_SIZE + image_size).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+
+    // Info header
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(framebuffer.height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes()); // Planes
+    data.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+    data.extend_from_slice(&0u32.to_le_bytes()); // No compression
+    data.extend_from_slice(&image_size.to_le_bytes());
+    data.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI
+    data.extend_from_slice(&2835i32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    for row in framebuffer.pixels.chunks(width).rev() {
+        for pixel in row {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            data.extend_from_slice(&[b, g, r]);
+        }
+        data.resize(data.len() + row_size - width * 3, 0);
+    }
+
+    data
+}
+
+fn encode_png<W: Write>(writer: W, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+    let mut encoder =
+        png::Encoder::new(writer, framebuffer.width as u32, framebuffer.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(framebuffer.pixels.len() * 3);
+    for pixel in &framebuffer.pixels {
+        let [_, r, g, b] = pixel.to_be_bytes();
+        data.extend_from_slice(&[r, g, b]);
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_grey16_png(
+    path: &str,
+    width: usize,
+    height: usize,
+    grey: &[u16],
+) -> Result<(), io::Error> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+
+    // 16-bit PNG samples are big endian
+    let data: Vec<u8> = grey.iter().flat_map(|g| g.to_be_bytes()).collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_grey16_pgm(
+    path: &str,
+    width: usize,
+    height: usize,
+    grey: &[u16],
+) -> Result<(), io::Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P5\n{width} {height}\n65535\n")?;
+    for g in grey {
+        file.write_all(&g.to_be_bytes())?;
+    }
+    file.flush()
+}
+
+fn depth_to_grey(depth: &[f32]) -> Vec<u16> {
+    let mut near = f32::MAX;
+    let mut far = f32::MIN;
+    for z in depth.iter().filter(|z| z.is_finite()) {
+        near = near.min(*z);
+        far = far.max(*z);
+    }
+    let range = (far - near).max(f32::EPSILON);
+
+    depth
+        .iter()
+        .map(|z| {
+            if z.is_finite() {
+                (((z - near) / range) * (u16::MAX as f32)).round() as u16
+            } else {
+                u16::MAX
+            }
+        })
+        .collect()
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn unsupported_format(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Unsupported image format: {path}"),
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::*;
+
+    /// 2x2 image, red and green on the top row, blue and white on the bottom
+    fn test_framebuffer() -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(2, 2);
+        framebuffer.pixels = vec![0xff0000, 0x00ff00, 0x0000ff, 0xffffff];
+        framebuffer
+    }
+
+    #[test]
+    fn test_ppm_top_row_first() {
+        let data = encode_ppm(&test_framebuffer());
+
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(header, &data[..header.len()]);
+        assert_eq!(
+            [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255],
+            data[header.len()..]
+        );
+    }
+
+    #[test]
+    fn test_bmp_bottom_row_first() {
+        let data = encode_bmp(&test_framebuffer());
+
+        assert_eq!(b"BM", &data[..2]);
+        // Two 6 byte rows padded to 8
+        assert_eq!(54 + 16, data.len());
+        assert_eq!([255, 0, 0, 255, 255, 255, 0, 0], data[54..62]);
+        assert_eq!([0, 0, 255, 0, 255, 0, 0, 0], data[62..70]);
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let framebuffer = test_framebuffer();
+        let mut data = Vec::new();
+        encode_png(&mut data, &framebuffer).unwrap();
+
+        let decoder = png::Decoder::new(data.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+
+        assert_eq!((2, 2), (info.width, info.height));
+        assert_eq!(
+            [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255],
+            buf[..info.buffer_size()]
+        );
+    }
+
+    #[test]
+    fn test_depth_to_grey() {
+        let grey = depth_to_grey(&[0.5, 0.75, 1., f32::INFINITY]);
+
+        assert_eq!(vec![0, 32768, 65535, 65535], grey);
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2024, 12, 31), civil_from_days(20088));
+    }
+}
@@ -0,0 +1,149 @@
+//! Distance fog, blending what is drawn towards a fog colour with its view space depth so far
+//! away objects fade out rather than stopping dead at the far plane.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::colour::Colour;
+use crate::line::blend;
+use crate::renderer::{FrameBuffer, Projection};
+
+/// The exponential fogs are 1 - e^-4, about 98%, thick at the end of the range
+const EXP_AT_END: f32 = 4.;
+
+/// How the fog thickens with distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FogMode {
+    #[default]
+    Off,
+    /// Evenly from none at the start of the range to solid at the end
+    Linear,
+    /// Quickly at first then tailing off
+    Exp,
+    /// Slowly at first, with a sharper edge further out
+    Exp2,
+}
+
+impl FogMode {
+    pub fn next(self) -> Self {
+        match self {
+            FogMode::Off => FogMode::Linear,
+            FogMode::Linear => FogMode::Exp,
+            FogMode::Exp => FogMode::Exp2,
+            FogMode::Exp2 => FogMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FogMode::Off => "off",
+            FogMode::Linear => "linear",
+            FogMode::Exp => "exponential",
+            FogMode::Exp2 => "exponential squared",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub colour: Colour,
+    /// View space depth the fog starts at
+    pub start: f32,
+    /// View space depth the fog is solid, or nearly for the exponential modes
+    pub end: f32,
+    /// Work the fog out at each triangle's corners instead of at every pixel. Triangles are
+    /// filled in one colour, so each gets the average of its corners
+    pub per_vertex: bool,
+    /// Clear the frame to the fog colour, so fogged objects fade into the background
+    pub background: bool,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            mode: FogMode::Off,
+            colour: Colour::new(150, 160, 175),
+            start: 5.,
+            end: 50.,
+            per_vertex: false,
+            background: true,
+        }
+    }
+}
+
+impl Fog {
+    pub fn enabled(&self) -> bool {
+        self.mode != FogMode::Off
+    }
+
+    /// How much fog there is at a view space depth, from 0 for none to 1 for solid
+    pub fn amount(&self, depth: f32) -> f32 {
+        let t = ((depth - self.start) / (self.end - self.start).max(f32::EPSILON)).max(0.);
+        match self.mode {
+            FogMode::Off => 0.,
+            FogMode::Linear => t.min(1.),
+            FogMode::Exp => 1. - (-EXP_AT_END * t).exp(),
+            FogMode::Exp2 => 1. - (-EXP_AT_END * t * t).exp(),
+        }
+    }
+
+    /// `colour` seen through `amount` of fog
+    pub fn apply(&self, colour: u32, amount: f32) -> u32 {
+        blend(colour, self.colour.as_0rgb(), amount)
+    }
+
+    /// Fog every pixel something was drawn on by its depth
+    pub(crate) fn draw(&self, framebuffer: &mut FrameBuffer, projection: &Projection) {
+        framebuffer
+            .pixels
+            .par_iter_mut()
+            .zip(framebuffer.depth.par_iter())
+            .filter(|(_, z)| z.is_finite())
+            .for_each(|(pixel, z)| {
+                *pixel = self.apply(*pixel, self.amount(projection.view_depth(*z)));
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fog::*;
+
+    #[test]
+    fn test_fog_amount() {
+        let mut fog = Fog {
+            mode: FogMode::Linear,
+            start: 10.,
+            end: 20.,
+            ..Fog::default()
+        };
+        assert_eq!(0., fog.amount(5.));
+        assert_eq!(0.5, fog.amount(15.));
+        assert_eq!(1., fog.amount(100.));
+
+        // The exponential modes agree at the ends of the range, with exp ahead in between
+        fog.mode = FogMode::Exp;
+        let exp = fog.amount(15.);
+        assert_eq!(0., fog.amount(10.));
+        let exp_end = fog.amount(20.);
+        fog.mode = FogMode::Exp2;
+        assert!(exp > fog.amount(15.));
+        assert_eq!(exp_end, fog.amount(20.));
+        assert!(exp_end > 0.95);
+
+        fog.mode = FogMode::Off;
+        assert_eq!(0., fog.amount(100.));
+    }
+
+    #[test]
+    fn test_fog_apply() {
+        let fog = Fog {
+            colour: Colour::new(200, 100, 0),
+            ..Fog::default()
+        };
+        assert_eq!(0x000000, fog.apply(0x000000, 0.));
+        assert_eq!(0x643200, fog.apply(0x000000, 0.5));
+        assert_eq!(0xc86400, fog.apply(0x000000, 1.));
+    }
+}
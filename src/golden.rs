@@ -0,0 +1,555 @@
+//! Reference image tests. Each test renders a fixed scene headlessly and compares it against
+//! a checked in image in Resource/Golden. On a mismatch the actual image and a diff image are
+//! written to target/golden so the change can be inspected.
+//!
+//! After an intended change to the output, bless new references with
+//!     BLESS=1 cargo test golden
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::colour::Colour;
+use crate::debug_draw::DebugOverlays;
+use crate::debug_view::DebugView;
+use crate::demo::init_scene;
+use crate::export::save_image;
+use crate::fog::{Fog, FogMode};
+use crate::line::{draw_line_styled, Line, LineCap, LineStyle};
+use crate::raster::{draw_filled_triangle, draw_filled_triangle_edge, draw_line, Point, Tri};
+use crate::renderer::{AntiAliasing, Downsample, FrameBuffer, Rasterizer, Renderer, ViewMode};
+use crate::resources::model_path;
+use crate::scene::Scene;
+use crate::threed::*;
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 600;
+
+const REFERENCE_DIR: &str = "Resource/Golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+/// A pixel matches if no channel is further than this from the reference
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to be outside the channel tolerance, covers the odd edge pixel
+/// moving when the maths changes slightly
+const MAX_BAD_FRACTION: f32 = 0.001;
+
+/// Compare against the reference of the same name, or replace the reference when blessing
+fn check_golden(name: &str, framebuffer: &FrameBuffer) {
+    let reference_path = Path::new(REFERENCE_DIR).join(format!("{name}.png"));
+
+    if env::var("BLESS").is_ok() {
+        fs::create_dir_all(REFERENCE_DIR).unwrap();
+        save_image(reference_path.to_str().unwrap(), framebuffer).unwrap();
+        return;
+    }
+
+    let reference = match load_png(&reference_path) {
+        Some(reference) => reference,
+        None => {
+            let actual = write_output(name, "actual", framebuffer);
+            panic!(
+                "No reference image {}, actual image written to {}. Run with BLESS=1 to create it",
+                reference_path.display(),
+                actual.display()
+            );
+        }
+    };
+
+    assert_eq!(
+        (reference.width, reference.height),
+        (framebuffer.width, framebuffer.height),
+        "{name}: image size differs from the reference"
+    );
+
+    let mut diff = FrameBuffer::new(framebuffer.width, framebuffer.height);
+    let mut bad_pixels = 0;
+    for (i, (actual, expected)) in framebuffer.pixels.iter().zip(&reference.pixels).enumerate() {
+        if pixels_match(*actual, *expected) {
+            // Faded copy of the reference so the bad pixels stand out
+            diff.pixels[i] = Colour::from_u32(*expected).scale(0.25).as_0rgb();
+        } else {
+            diff.pixels[i] = 0xff0000;
+            bad_pixels += 1;
+        }
+    }
+
+    let max_bad_pixels = (framebuffer.pixels.len() as f32 * MAX_BAD_FRACTION) as usize;
+    if bad_pixels > max_bad_pixels {
+        let actual = write_output(name, "actual", framebuffer);
+        let diff = write_output(name, "diff", &diff);
+        panic!(
+            "{name}: {bad_pixels} pixels differ from the reference (max {max_bad_pixels}), see {} and {}",
+            actual.display(),
+            diff.display()
+        );
+    }
+}
+
+fn pixels_match(a: u32, b: u32) -> bool {
+    a.to_be_bytes()
+        .iter()
+        .zip(b.to_be_bytes().iter())
+        .all(|(a, b)| a.abs_diff(*b) <= CHANNEL_TOLERANCE)
+}
+
+fn write_output(name: &str, kind: &str, framebuffer: &FrameBuffer) -> PathBuf {
+    fs::create_dir_all(OUTPUT_DIR).unwrap();
+    let path = Path::new(OUTPUT_DIR).join(format!("{name}_{kind}.png"));
+    save_image(path.to_str().unwrap(), framebuffer).unwrap();
+    path
+}
+
+/// Read an 8-bit RGB PNG, as written by `save_image`, back into a framebuffer
+fn load_png(path: &Path) -> Option<FrameBuffer> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).ok()?;
+    assert_eq!(png::ColorType::Rgb, info.color_type);
+
+    let mut framebuffer = FrameBuffer::new(info.width as usize, info.height as usize);
+    for (pixel, rgb) in framebuffer.pixels.iter_mut().zip(buf.chunks(3)) {
+        *pixel = Colour::new(rgb[0], rgb[1], rgb[2]).as_0rgb();
+    }
+    Some(framebuffer)
+}
+
+fn camera_at(x: f32, y: f32, z: f32, yaw: f32) -> Camera {
+    Camera {
+        fov: 60.,
+        near_plane: 0.1,
+        far_plane: 1000.,
+        position: vec3 { x, y, z },
+        yaw,
+        fwd_speed: 1.,
+        yaw_speed: 5.,
+    }
+}
+
+fn light_dir() -> vec3 {
+    vec3 {
+        x: 0.,
+        y: 10.,
+        z: -10.,
+    }
+}
+
+fn single_object_scene(file: &str, rotation: vec3, albedo: Colour, camera: Camera) -> Scene {
+    let transform = Transform {
+        position: vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        },
+        rotation,
+    };
+    let object =
+        Object::create_from_file(file.to_string(), model_path(file), transform, albedo).unwrap();
+    Scene::new(vec![object], camera, light_dir())
+}
+
+fn render(scene: &mut Scene, view_mode: ViewMode) -> FrameBuffer {
+    render_with(scene, view_mode, Rasterizer::Scanline)
+}
+
+fn render_with(scene: &mut Scene, view_mode: ViewMode, rasterizer: Rasterizer) -> FrameBuffer {
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.view_mode = view_mode;
+    renderer.rasterizer = rasterizer;
+    renderer.render(scene);
+    renderer.framebuffer
+}
+
+fn render_aa(
+    scene: &mut Scene,
+    anti_aliasing: AntiAliasing,
+    downsample: Downsample,
+) -> FrameBuffer {
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.anti_aliasing = anti_aliasing;
+    renderer.downsample = downsample;
+    renderer.render(scene);
+    renderer.framebuffer
+}
+
+/// The floor the demo scene used to stand on, 400 plane objects in a checkerboard
+fn checkerboard_floor() -> Vec<Object> {
+    let mut objs = Vec::new();
+
+    let model_path = model_path("Plane 1m.obj");
+
+    let rotation = vec3 {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+
+    let num = 20;
+    let num_div2 = (num as f32) / 2.;
+
+    for z in 0..num {
+        let z_f32 = z as f32;
+        for x in 0..num {
+            let x_f32 = x as f32;
+            let position = vec3 {
+                x: x_f32 - num_div2,
+                y: 0.,
+                z: z_f32 - num_div2,
+            };
+
+            let transform = Transform { position, rotation };
+
+            let colour = (x_f32 + z_f32) % 2.;
+
+            let mut albedo = Colour::new(255, 255, 255);
+            if colour == 0. {
+            } else {
+                albedo.r = 0;
+                albedo.g = 0;
+                albedo.b = 0;
+            }
+            let obj =
+                Object::create_from_file("cube".to_string(), model_path.clone(), transform, albedo)
+                    .unwrap();
+            objs.push(obj)
+        }
+    }
+
+    objs
+}
+
+/// Overlapping triangles at different depths, covering the flat top and flat bottom cases
+fn raster_test_tris() -> Vec<(Tri, u32)> {
+    let point = |x: u32, y: u32, z| Point {
+        x: x as f32,
+        y: y as f32,
+        z,
+    };
+    vec![
+        // Far triangle drawn last, the near ones must stay in front of it
+        (
+            Tri {
+                p1: point(100, 100, 0.3),
+                p2: point(400, 150, 0.3),
+                p3: point(200, 450, 0.3),
+            },
+            0xff0000,
+        ),
+        // Flat top and flat bottom cases
+        (
+            Tri {
+                p1: point(450, 100, 0.5),
+                p2: point(700, 100, 0.5),
+                p3: point(575, 300, 0.5),
+            },
+            0x00ff00,
+        ),
+        (
+            Tri {
+                p1: point(450, 550, 0.5),
+                p2: point(700, 550, 0.5),
+                p3: point(575, 350, 0.5),
+            },
+            0x0000ff,
+        ),
+        // Sloped in depth, cuts through the red triangle
+        (
+            Tri {
+                p1: point(50, 300, 0.1),
+                p2: point(450, 250, 0.6),
+                p3: point(250, 50, 0.1),
+            },
+            0xffff00,
+        ),
+        (
+            Tri {
+                p1: point(0, 0, 0.9),
+                p2: point(799, 0, 0.9),
+                p3: point(400, 599, 0.9),
+            },
+            0x404040,
+        ),
+    ]
+}
+
+#[test]
+fn golden_raster_triangles() {
+    let mut framebuffer = FrameBuffer::new(WIDTH, HEIGHT);
+    framebuffer.clear(Colour::new(0, 0, 0));
+
+    for (tri, colour) in &raster_test_tris() {
+        draw_filled_triangle(&mut framebuffer, tri, *colour);
+    }
+
+    check_golden("raster_triangles", &framebuffer);
+}
+
+#[test]
+fn golden_raster_triangles_edge() {
+    let mut framebuffer = FrameBuffer::new(WIDTH, HEIGHT);
+    framebuffer.clear(Colour::new(0, 0, 0));
+
+    for (tri, colour) in &raster_test_tris() {
+        draw_filled_triangle_edge(&mut framebuffer, tri, *colour);
+    }
+
+    check_golden("raster_triangles_edge", &framebuffer);
+}
+
+#[test]
+fn golden_raster_lines() {
+    let mut framebuffer = FrameBuffer::new(WIDTH, HEIGHT);
+    framebuffer.clear(Colour::new(0, 0, 0));
+
+    // A star of lines from the centre covering every octant
+    for i in 0..32 {
+        let angle = (i as f32) * std::f32::consts::PI / 16.;
+        let x = 400. + 250. * angle.cos();
+        let y = 300. + 250. * angle.sin();
+        draw_line(
+            &mut framebuffer,
+            400,
+            300,
+            x.round() as u32,
+            y.round() as u32,
+            0xffffff,
+        );
+    }
+
+    check_golden("raster_lines", &framebuffer);
+}
+
+#[test]
+fn golden_styled_lines() {
+    let mut framebuffer = FrameBuffer::new(WIDTH, HEIGHT);
+    framebuffer.clear(Colour::new(0, 0, 0));
+
+    // A fan of lines in each quarter of the screen, one per style
+    let styles = [
+        LineStyle::default(),
+        LineStyle {
+            anti_aliased: true,
+            ..LineStyle::default()
+        },
+        LineStyle {
+            width: 6.,
+            ..LineStyle::default()
+        },
+        LineStyle {
+            width: 6.,
+            cap: LineCap::Round,
+            anti_aliased: true,
+            ..LineStyle::default()
+        },
+    ];
+    for (quarter, style) in styles.iter().enumerate() {
+        let centre_x = 200. + 400. * (quarter % 2) as f32;
+        let centre_y = 150. + 300. * (quarter / 2) as f32;
+        for i in 0..12 {
+            let angle = (i as f32 + 0.3) * std::f32::consts::PI / 6.;
+            let line = Line {
+                p1: Point {
+                    x: centre_x + 20. * angle.cos(),
+                    y: centre_y + 20. * angle.sin(),
+                    z: 0.,
+                },
+                p2: Point {
+                    x: centre_x + 130. * angle.cos(),
+                    y: centre_y + 130. * angle.sin(),
+                    z: 0.,
+                },
+            };
+            draw_line_styled(&mut framebuffer, &line, 0xffffff, style);
+        }
+    }
+
+    check_golden("styled_lines", &framebuffer);
+}
+
+#[test]
+fn golden_cube() {
+    let rotation = vec3 {
+        x: 30.,
+        y: 45.,
+        z: 15.,
+    };
+    let mut scene = single_object_scene(
+        "cube.obj",
+        rotation,
+        Colour::new(42, 170, 255),
+        camera_at(0., 0., -6., 0.),
+    );
+
+    check_golden("cube", &render(&mut scene, ViewMode::Shaded));
+    check_golden("cube_wireframe", &render(&mut scene, ViewMode::Wireframe));
+}
+
+#[test]
+fn golden_debug_overlays() {
+    let rotation = vec3 {
+        x: 30.,
+        y: 45.,
+        z: 15.,
+    };
+    let mut scene = single_object_scene(
+        "cube.obj",
+        rotation,
+        Colour::new(42, 170, 255),
+        camera_at(1., 2., -10., 5.),
+    );
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.overlays = DebugOverlays {
+        face_normals: true,
+        vertex_normals: true,
+        bounds: true,
+        world_axes: true,
+        local_axes: true,
+        light: true,
+        // Off to the side looking across at the cube, cut short to stay in view
+        frustum_camera: Some(Camera {
+            far_plane: 4.,
+            ..camera_at(-5., 0., -3., -60.)
+        }),
+    };
+
+    renderer.render(&mut scene);
+
+    check_golden("debug_overlays", &renderer.framebuffer);
+}
+
+#[test]
+fn golden_teapot() {
+    let rotation = vec3 {
+        x: 0.,
+        y: 30.,
+        z: 0.,
+    };
+    let mut scene = single_object_scene(
+        "teapot.obj",
+        rotation,
+        Colour::new(1, 204, 3),
+        camera_at(0., 2., -8., 0.),
+    );
+
+    check_golden("teapot", &render(&mut scene, ViewMode::Shaded));
+    check_golden(
+        "teapot_hidden_line",
+        &render(&mut scene, ViewMode::HiddenLine),
+    );
+    check_golden(
+        "teapot_shaded_wireframe",
+        &render(&mut scene, ViewMode::ShadedWireframe),
+    );
+    check_golden("teapot_points", &render(&mut scene, ViewMode::Points));
+}
+
+#[test]
+fn golden_ground_grid() {
+    let rotation = vec3 {
+        x: 0.,
+        y: 30.,
+        z: 0.,
+    };
+    let mut scene = single_object_scene(
+        "teapot.obj",
+        rotation,
+        Colour::new(1, 204, 3),
+        camera_at(3., 4., -10., -10.),
+    );
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.grid.enabled = true;
+
+    renderer.render(&mut scene);
+    check_golden("ground_grid", &renderer.framebuffer);
+
+    // High enough up for the lines to have spread out
+    scene.camera.position.y = 60.;
+    renderer.render(&mut scene);
+    check_golden("ground_grid_high", &renderer.framebuffer);
+}
+
+#[test]
+fn golden_debug_views() {
+    let mut scene = init_scene();
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+
+    for (name, view) in [
+        ("debug_depth", DebugView::Depth),
+        ("debug_normals", DebugView::Normals),
+        ("debug_overdraw", DebugView::Overdraw),
+        ("debug_triangle_id", DebugView::TriangleId),
+    ] {
+        renderer.debug_view = view;
+        renderer.render(&mut scene);
+        check_golden(name, &renderer.framebuffer);
+    }
+}
+
+#[test]
+fn golden_fog() {
+    let mut scene = init_scene();
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.grid.enabled = true;
+    scene.fog = Fog {
+        mode: FogMode::Linear,
+        start: 5.,
+        end: 25.,
+        ..Fog::default()
+    };
+    renderer.render(&mut scene);
+    check_golden("fog_linear", &renderer.framebuffer);
+
+    scene.fog.mode = FogMode::Exp2;
+    scene.fog.per_vertex = true;
+    scene.fog.background = false;
+    renderer.render(&mut scene);
+    check_golden("fog_exp2_vertex", &renderer.framebuffer);
+
+    // Lines are fogged at their ends, as the triangles are at their corners
+    renderer.view_mode = ViewMode::Wireframe;
+    renderer.render(&mut scene);
+    check_golden("fog_wireframe_vertex", &renderer.framebuffer);
+}
+
+#[test]
+fn golden_floor() {
+    let mut scene = Scene::new(
+        checkerboard_floor(),
+        camera_at(-4., 6., -14., 15.),
+        light_dir(),
+    );
+
+    check_golden("floor", &render(&mut scene, ViewMode::Shaded));
+    check_golden(
+        "floor_edge",
+        &render_with(&mut scene, ViewMode::Shaded, Rasterizer::EdgeFunction),
+    );
+    check_golden(
+        "floor_ssaa2x_tent",
+        &render_aa(&mut scene, AntiAliasing::Ssaa2x, Downsample::Tent),
+    );
+    check_golden(
+        "floor_msaa4x",
+        &render_aa(&mut scene, AntiAliasing::Msaa4x, Downsample::Box),
+    );
+}
+
+#[test]
+fn golden_default_scene() {
+    let mut scene = init_scene();
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.grid.enabled = true;
+
+    renderer.render(&mut scene);
+    check_golden("default_scene", &renderer.framebuffer);
+
+    renderer.rasterizer = Rasterizer::EdgeFunction;
+    renderer.render(&mut scene);
+    check_golden("default_scene_edge", &renderer.framebuffer);
+
+    renderer.rasterizer = Rasterizer::Scanline;
+    renderer.post.set_enabled("fxaa", true);
+    renderer.render(&mut scene);
+    check_golden("default_scene_fxaa", &renderer.framebuffer);
+}
@@ -0,0 +1,226 @@
+//! An endless ground grid on the y = 0 plane, drawn over the finished frame a pixel at a time
+//! rather than built from objects, so it costs the same however much of it is in view and
+//! picking never finds it. Each pixel works out where its ray meets the ground and how far
+//! across the ground one pixel reaches there, which gives lines an even pixel width at any
+//! distance and lets them fade out before they get close enough together to shimmer.
+
+use rayon::prelude::*;
+
+use crate::debug_draw::{AXIS_X_COLOUR, AXIS_Z_COLOUR};
+use crate::fog::Fog;
+use crate::line::blend;
+use crate::renderer::{FrameBuffer, Projection};
+use crate::threed::*;
+
+/// Minor lines are this many times their spacing below the camera before they move apart
+const ADAPT_HEIGHT: f32 = 10.;
+/// Widths in pixels
+const LINE_WIDTH: f32 = 1.;
+const AXIS_WIDTH: f32 = 1.5;
+
+pub struct GroundGrid {
+    pub enabled: bool,
+    /// World units between minor lines with the camera near the ground. Higher up the lines
+    /// spread out, by `major_every` at a time
+    pub spacing: f32,
+    /// Minor lines between each major line
+    pub major_every: u32,
+    pub minor_colour: u32,
+    pub major_colour: u32,
+    /// Lines are gone at this many major spacings from the camera
+    pub fade_distance: f32,
+}
+
+impl Default for GroundGrid {
+    fn default() -> Self {
+        GroundGrid {
+            enabled: false,
+            spacing: 1.,
+            major_every: 10,
+            minor_colour: 0x606060,
+            major_colour: 0x8c8c8c,
+            fade_distance: 5.,
+        }
+    }
+}
+
+impl GroundGrid {
+    /// Blend the grid into the frame wherever nothing nearer has been drawn, through `fog` as
+    /// the objects are
+    pub(crate) fn draw(&self, framebuffer: &mut FrameBuffer, projection: &Projection, fog: &Fog) {
+        let cam_to_world = quick_invert_mat4(projection.view_mat.clone());
+        let origin = mult_vec3_mat4(vec3::default(), &cam_to_world);
+        if origin.y == 0. {
+            return;
+        }
+
+        // A ray's direction is linear in the pixel position. With a view space z of 1 the
+        // distance along it is the view space depth
+        let (m00, m11) = (projection.proj_mat[[0, 0]], projection.proj_mat[[1, 1]]);
+        let world = |x: f32, y: f32, z: f32| mult_dir_mat4(vec3 { x, y, z }, &cam_to_world);
+        let step_x = world(2. / (projection.width * m00), 0., 0.);
+        let step_y = world(0., 2. / (projection.height * m11), 0.);
+        let corner = world(-1. / m00, -1. / m11, 1.);
+
+        let base = self.major_every.max(2) as f32;
+        let level = (origin.y.abs() / (self.spacing * ADAPT_HEIGHT))
+            .max(1.)
+            .log(base);
+        let minor = self.spacing * base.powf(level.floor());
+        let levels = GridLevels {
+            minor,
+            major: minor * base,
+            // The minor lines fade out as the camera rises, until they are the next level's
+            // major lines
+            minor_alpha: 1. - level.fract(),
+            fade_distance: self.fade_distance * self.spacing * base.powf(level + 1.),
+        };
+
+        let width = framebuffer.width.max(1);
+        let height = framebuffer.height;
+        framebuffer
+            .pixels
+            .par_chunks_mut(width)
+            .zip(framebuffer.depth.par_chunks(width))
+            .enumerate()
+            .for_each(|(row, (pixels, depths))| {
+                // The top row is stored first, screen y is up
+                let y = (height - 1 - row) as f32 + 0.5;
+                for (x, (pixel, depth)) in pixels.iter_mut().zip(depths).enumerate() {
+                    let dir = corner + scaled(step_x, x as f32 + 0.5) + scaled(step_y, y);
+                    let Some((colour, coverage, view_z)) =
+                        self.shade(origin, dir, step_x, step_y, &levels)
+                    else {
+                        continue;
+                    };
+
+                    let z = projection
+                        .view_to_screen(vec3 {
+                            x: 0.,
+                            y: 0.,
+                            z: view_z,
+                        })
+                        .z;
+                    if z <= *depth {
+                        let colour = fog.apply(colour, fog.amount(view_z));
+                        *pixel = blend(*pixel, colour, coverage);
+                    }
+                }
+            });
+    }
+
+    /// The line colour and coverage where a ray hits the ground, and the depth of the hit.
+    /// `None` if the ray misses or no line is near
+    fn shade(
+        &self,
+        origin: vec3,
+        dir: vec3,
+        step_x: vec3,
+        step_y: vec3,
+        levels: &GridLevels,
+    ) -> Option<(u32, f32, f32)> {
+        let t = -origin.y / dir.y;
+        if !t.is_finite() || t <= 0. {
+            return None;
+        }
+        let hit = origin + scaled(dir, t);
+
+        let distance = (sq(hit.x - origin.x) + sq(hit.z - origin.z)).sqrt();
+        let fade = 1. - distance / levels.fade_distance;
+        if fade <= 0. {
+            return None;
+        }
+
+        // How far the hit point moves across the ground for a pixel step either way
+        let across = |step: vec3| scaled(step - scaled(dir, step.y / dir.y), t);
+        let (dx, dy) = (across(step_x), across(step_y));
+        let footprint = (dx.x.abs() + dy.x.abs(), dx.z.abs() + dy.z.abs());
+
+        // Axes go over major lines which go over minor lines
+        let axis_x = line_coverage(hit.z, f32::INFINITY, footprint.1, AXIS_WIDTH);
+        let axis_z = line_coverage(hit.x, f32::INFINITY, footprint.0, AXIS_WIDTH);
+        let (colour, coverage) = if axis_x > 0. || axis_z > 0. {
+            if axis_x >= axis_z {
+                (AXIS_X_COLOUR, axis_x)
+            } else {
+                (AXIS_Z_COLOUR, axis_z)
+            }
+        } else {
+            let grid = |spacing| {
+                line_coverage(hit.x, spacing, footprint.0, LINE_WIDTH).max(line_coverage(
+                    hit.z,
+                    spacing,
+                    footprint.1,
+                    LINE_WIDTH,
+                ))
+            };
+            let major = grid(levels.major);
+            let minor = grid(levels.minor) * levels.minor_alpha;
+            if major >= minor {
+                (self.major_colour, major)
+            } else {
+                (self.minor_colour, minor)
+            }
+        };
+
+        let coverage = coverage * fade;
+        (coverage > 0.).then_some((colour, coverage, t))
+    }
+}
+
+/// Spacings worked out once a frame from the camera height
+struct GridLevels {
+    minor: f32,
+    major: f32,
+    minor_alpha: f32,
+    fade_distance: f32,
+}
+
+/// How much of a pixel is covered by the nearest of a set of lines `spacing` apart, one of them
+/// through 0. An infinite spacing gives the single line through 0. `footprint` is how far the
+/// pixel reaches across the lines in world units
+fn line_coverage(coord: f32, spacing: f32, footprint: f32, width: f32) -> f32 {
+    if footprint <= 0. {
+        return 0.;
+    }
+    let distance = if spacing.is_finite() {
+        (coord - spacing * (coord / spacing).round()).abs()
+    } else {
+        coord.abs()
+    };
+    let coverage = (0.5 * width + 0.5 - distance / footprint).clamp(0., 1.);
+
+    // Lines only a few pixels apart would alias into patterns, so they fade out before that
+    let density = footprint / spacing.min(f32::MAX);
+    coverage * (1.5 - 4. * density).clamp(0., 1.)
+}
+
+fn scaled(v: vec3, s: f32) -> vec3 {
+    vec3 {
+        x: v.x * s,
+        y: v.y * s,
+        z: v.z * s,
+    }
+}
+
+fn sq(v: f32) -> f32 {
+    v * v
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::*;
+
+    #[test]
+    fn test_line_coverage() {
+        // A pixel a tenth of a unit across, on, beside and away from a line every unit
+        assert_eq!(1., line_coverage(3., 1., 0.1, 1.));
+        assert!((line_coverage(3.05, 1., 0.1, 1.) - 0.5).abs() < 1e-4);
+        assert_eq!(0., line_coverage(3.5, 1., 0.1, 1.));
+        // Lines too close together on screen are faded out
+        assert_eq!(0., line_coverage(3., 1., 0.5, 1.));
+        // A single line through 0
+        assert_eq!(1., line_coverage(0., f32::INFINITY, 0.1, 1.));
+        assert_eq!(0., line_coverage(100., f32::INFINITY, 0.1, 1.));
+    }
+}
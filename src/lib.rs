@@ -0,0 +1,55 @@
+//! Software 3D renderer. Load meshes into a `Scene`, draw it with a `Renderer` into its
+//! `FrameBuffer`, then show the frame with a `Presenter` or save it with `export`
+
+pub mod threed;
+
+pub mod mesh;
+
+pub mod bounds;
+
+pub mod bvh;
+
+pub mod lod;
+
+pub mod simplify;
+
+pub mod scene;
+
+pub mod scene_file;
+
+pub mod reload;
+
+pub mod demo;
+
+pub mod resources;
+
+pub mod renderer;
+
+pub mod present;
+
+pub mod export;
+
+pub mod raster;
+
+pub mod line;
+
+pub mod span;
+
+pub mod tile;
+
+pub mod antialias;
+
+pub mod post;
+
+pub mod debug_draw;
+
+pub mod grid;
+
+pub mod debug_view;
+
+pub mod fog;
+
+pub mod colour;
+
+#[cfg(test)]
+mod golden;
@@ -0,0 +1,386 @@
+//! Lines with a width, end caps, anti-aliasing and depth testing, for wireframes and overlays.
+//! Every pixel is worked out from the line itself rather than by stepping from one end, so a
+//! line split across tiles joins up exactly and ends far off screen cost nothing.
+//! `raster::draw_line` is still there for plain lines between whole pixel positions
+
+use crate::raster::{two_d_to_1d, Point, RasterTarget, Rect};
+
+/// How the ends of a line wider than a pixel are finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineCap {
+    /// Squared off half the width past each end
+    #[default]
+    Square,
+    /// A half circle on each end
+    Round,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    /// In pixels, anything up to 1 is a single pixel wide line
+    pub width: f32,
+    pub cap: LineCap,
+    /// Blend the pixels along the edges of the line by how much of them it covers
+    pub anti_aliased: bool,
+    /// Leave out the parts behind what is already in the depth buffer. Lines never write depth
+    pub depth_test: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            width: 1.,
+            cap: LineCap::Square,
+            anti_aliased: false,
+            depth_test: false,
+        }
+    }
+}
+
+/// A line segment in screen space, z is depth as for triangles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub p1: Point,
+    pub p2: Point,
+}
+
+/// Depth tested lines still pass when they are this fraction of their distance from the camera
+/// behind the depth buffer, so edges lying on a surface that has been drawn aren't hidden by it
+const DEPTH_TOLERANCE: f32 = 0.002;
+
+pub fn draw_line_styled<T: RasterTarget>(
+    target: &mut T,
+    line: &Line,
+    colour: u32,
+    style: &LineStyle,
+) {
+    let rect = target.rect();
+    let (pixels, depths) = target.buffers();
+    let mut plotter = Plotter {
+        rect,
+        pixels,
+        depths,
+        colour,
+        depth_test: style.depth_test,
+    };
+
+    if style.width > 1. {
+        draw_wide(&mut plotter, line, style);
+    } else if style.anti_aliased {
+        draw_wu(&mut plotter, line);
+    } else {
+        draw_thin(&mut plotter, line);
+    }
+}
+
+struct Plotter<'a> {
+    rect: Rect,
+    pixels: &'a mut [u32],
+    depths: &'a mut [f32],
+    colour: u32,
+    depth_test: bool,
+}
+
+impl Plotter<'_> {
+    /// Blend the line colour into a pixel by `coverage`, 0 to 1
+    fn plot(&mut self, x: i64, y: i64, z: f32, coverage: f32) {
+        if coverage <= 0. || x < 0 || y < 0 || !self.rect.contains(x as i32, y as i32) {
+            return;
+        }
+
+        let index = two_d_to_1d(&self.rect, x as i32, y as i32);
+        if self.depth_test && !passes_depth(z, self.depths[index]) {
+            return;
+        }
+
+        self.pixels[index] = if coverage >= 1. {
+            self.colour
+        } else {
+            blend(self.pixels[index], self.colour, coverage)
+        };
+    }
+
+    /// The range of rows or columns in the target, inclusive
+    fn span(&self, steep: bool) -> (i64, i64) {
+        let (start, len) = if steep {
+            (self.rect.y, self.rect.height)
+        } else {
+            (self.rect.x, self.rect.width)
+        };
+        (start as i64, (start + len) as i64 - 1)
+    }
+}
+
+fn passes_depth(z: f32, stored: f32) -> bool {
+    // 1 - z is close to proportional to 1 / distance, so a fixed fraction of the distance
+    // is a fixed fraction of 1 - z
+    z <= stored + (1. - stored).max(0.) * DEPTH_TOLERANCE
+}
+
+/// `coverage` of the way from `dst` to `src`, per channel
+pub(crate) fn blend(dst: u32, src: u32, coverage: f32) -> u32 {
+    let channel = |shift: u32| {
+        let d = ((dst >> shift) & 0xff) as f32;
+        let s = ((src >> shift) & 0xff) as f32;
+        ((d + (s - d) * coverage).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+/// The line with x and y swapped if it is steep, so it always runs along the first coordinate,
+/// in increasing order
+fn along_major(
+    p1: (f32, f32, f32),
+    p2: (f32, f32, f32),
+) -> (bool, (f32, f32, f32), (f32, f32, f32)) {
+    let steep = (p2.1 - p1.1).abs() > (p2.0 - p1.0).abs();
+    let swap = |p: (f32, f32, f32)| if steep { (p.1, p.0, p.2) } else { p };
+    let (a, b) = (swap(p1), swap(p2));
+    if a.0 <= b.0 {
+        (steep, a, b)
+    } else {
+        (steep, b, a)
+    }
+}
+
+/// One pixel for every step along the line, with the vertices rounded to whole pixels the same
+/// way as the scanline rasterizer
+fn draw_thin(plotter: &mut Plotter, line: &Line) {
+    let round = |p: Point| (p.x.round(), p.y.round(), p.z);
+    let (steep, a, b) = along_major(round(line.p1), round(line.p2));
+
+    let length = b.0 - a.0;
+    let slope = if length > 0. {
+        (b.1 - a.1) / length
+    } else {
+        0.
+    };
+    let (first, last) = plotter.span(steep);
+    for major in (a.0 as i64).max(first)..=(b.0 as i64).min(last) {
+        let t = if length > 0. {
+            (major as f32 - a.0) / length
+        } else {
+            0.
+        };
+        let minor = (a.1 + slope * (major as f32 - a.0)).round() as i64;
+        let z = a.2 + (b.2 - a.2) * t;
+        if steep {
+            plotter.plot(minor, major, z, 1.);
+        } else {
+            plotter.plot(major, minor, z, 1.);
+        }
+    }
+}
+
+/// Xiaolin Wu's line, two pixels across the line at each step sharing the coverage between them
+/// by how close each is to the line
+fn draw_wu(plotter: &mut Plotter, line: &Line) {
+    // Pixel centres on whole numbers
+    let centred = |p: Point| (p.x - 0.5, p.y - 0.5, p.z);
+    let (steep, a, b) = along_major(centred(line.p1), centred(line.p2));
+
+    let length = b.0 - a.0;
+    let gradient = if length > 0. {
+        (b.1 - a.1) / length
+    } else {
+        0.
+    };
+    let (first_step, last_step) = (a.0.round() as i64, b.0.round() as i64);
+    let (first, last) = plotter.span(steep);
+    for major in first_step.max(first)..=last_step.min(last) {
+        // The end pixels are only partly covered along the line
+        let gap = if first_step == last_step {
+            length
+        } else if major == first_step {
+            1. - (a.0 + 0.5).rem_euclid(1.)
+        } else if major == last_step {
+            (b.0 + 0.5).rem_euclid(1.)
+        } else {
+            1.
+        };
+
+        let t = if length > 0. {
+            ((major as f32 - a.0) / length).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let minor = a.1 + gradient * (major as f32 - a.0);
+        let z = a.2 + (b.2 - a.2) * t;
+        let below = minor.floor();
+        let fraction = minor - below;
+        let below = below as i64;
+
+        for (minor, coverage) in [(below, 1. - fraction), (below + 1, fraction)] {
+            if steep {
+                plotter.plot(minor, major, z, coverage * gap);
+            } else {
+                plotter.plot(major, minor, z, coverage * gap);
+            }
+        }
+    }
+}
+
+/// Every pixel centre near the line is tested against its outline, so the width and caps are exact
+fn draw_wide(plotter: &mut Plotter, line: &Line, style: &LineStyle) {
+    let half_width = style.width / 2.;
+    let (a, b) = (line.p1, line.p2);
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = (dx * dx + dy * dy).sqrt();
+    let (dir_x, dir_y) = if length > 0. {
+        (dx / length, dy / length)
+    } else {
+        (1., 0.)
+    };
+
+    // Pixels the line could touch, cut down to the target
+    let rect = plotter.rect;
+    let reach = half_width + 1.;
+    let clamp_x = |v: f32| v.clamp(rect.x as f32, (rect.x + rect.width) as f32) as i64;
+    let clamp_y = |v: f32| v.clamp(rect.y as f32, (rect.y + rect.height) as f32) as i64;
+    let min_x = clamp_x((a.x.min(b.x) - reach).floor());
+    let max_x = clamp_x((a.x.max(b.x) + reach).ceil());
+    let min_y = clamp_y((a.y.min(b.y) - reach).floor());
+    let max_y = clamp_y((a.y.max(b.y) + reach).ceil());
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let (rel_x, rel_y) = (x as f32 + 0.5 - a.x, y as f32 + 0.5 - a.y);
+            let along = rel_x * dir_x + rel_y * dir_y;
+            let across = (rel_x * dir_y - rel_y * dir_x).abs();
+
+            // How far inside the outline the pixel centre is, in pixels
+            let inside = match style.cap {
+                LineCap::Round => {
+                    let nearest = along.clamp(0., length);
+                    let (off_x, off_y) = (rel_x - dir_x * nearest, rel_y - dir_y * nearest);
+                    half_width - (off_x * off_x + off_y * off_y).sqrt()
+                }
+                LineCap::Square => {
+                    let past_end = (-along).max(along - length);
+                    (half_width - across).min(half_width - past_end)
+                }
+            };
+
+            let coverage = if style.anti_aliased {
+                (inside + 0.5).clamp(0., 1.)
+            } else if inside >= 0. {
+                1.
+            } else {
+                0.
+            };
+            let t = if length > 0. {
+                (along / length).clamp(0., 1.)
+            } else {
+                0.
+            };
+            plotter.plot(x, y, a.z + (b.z - a.z) * t, coverage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::colour::Colour;
+    use crate::line::*;
+    use crate::renderer::FrameBuffer;
+
+    fn line(x1: f32, y1: f32, x2: f32, y2: f32, z: f32) -> Line {
+        Line {
+            p1: Point { x: x1, y: y1, z },
+            p2: Point { x: x2, y: y2, z },
+        }
+    }
+
+    fn pixel(framebuffer: &FrameBuffer, x: i32, y: i32) -> u32 {
+        framebuffer.pixels[two_d_to_1d(&framebuffer.rect(), x, y)]
+    }
+
+    #[test]
+    fn test_thin_line_ends_off_screen() {
+        let mut framebuffer = FrameBuffer::new(64, 48);
+
+        draw_line_styled(
+            &mut framebuffer,
+            &line(-1000., 10., 5000., 10., 0.5),
+            0xffffff,
+            &LineStyle::default(),
+        );
+
+        let row: Vec<u32> = (0..64).map(|x| pixel(&framebuffer, x, 10)).collect();
+        assert!(row.iter().all(|p| *p == 0xffffff));
+        assert_eq!(0, pixel(&framebuffer, 10, 11));
+    }
+
+    #[test]
+    fn test_depth_tested_line_hidden_behind() {
+        let mut framebuffer = FrameBuffer::new(32, 32);
+        framebuffer.depth.fill(0.9);
+        let style = LineStyle {
+            depth_test: true,
+            ..LineStyle::default()
+        };
+
+        draw_line_styled(&mut framebuffer, &line(0., 5., 31., 5., 0.95), 1, &style);
+        // On the surface, give or take rounding
+        draw_line_styled(
+            &mut framebuffer,
+            &line(0., 10., 31., 10., 0.9001),
+            2,
+            &style,
+        );
+
+        assert_eq!(0, pixel(&framebuffer, 16, 5));
+        assert_eq!(2, pixel(&framebuffer, 16, 10));
+    }
+
+    #[test]
+    fn test_wu_line_coverage() {
+        let mut framebuffer = FrameBuffer::new(64, 32);
+        let style = LineStyle {
+            anti_aliased: true,
+            ..LineStyle::default()
+        };
+
+        draw_line_styled(
+            &mut framebuffer,
+            &line(2., 4.5, 60., 20.3, 0.5),
+            0xffffff,
+            &style,
+        );
+
+        // Away from the ends each column is covered once in total, split over two pixels
+        for x in 10..50 {
+            let total: u32 = (0..32)
+                .map(|y| Colour::from_u32(pixel(&framebuffer, x, y)).r as u32)
+                .sum();
+            assert!(total.abs_diff(255) <= 2, "column {x} adds up to {total}");
+        }
+    }
+
+    #[test]
+    fn test_wide_line_caps() {
+        let draw = |cap| {
+            let mut framebuffer = FrameBuffer::new(40, 40);
+            let style = LineStyle {
+                width: 8.,
+                cap,
+                ..LineStyle::default()
+            };
+            draw_line_styled(&mut framebuffer, &line(10., 20., 30., 20., 0.5), 1, &style);
+            framebuffer
+        };
+        let square = draw(LineCap::Square);
+        let round = draw(LineCap::Round);
+
+        // Four pixels either side of the line and past its ends
+        for framebuffer in [&square, &round] {
+            assert_eq!(1, pixel(framebuffer, 20, 16));
+            assert_eq!(0, pixel(framebuffer, 20, 15));
+            assert_eq!(1, pixel(framebuffer, 6, 20));
+            assert_eq!(0, pixel(framebuffer, 5, 20));
+        }
+        // Only the square cap fills the corners
+        assert_eq!(1, pixel(&square, 6, 16));
+        assert_eq!(0, pixel(&round, 6, 16));
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+
+use crate::bounds::Sphere;
+use crate::mesh::Mesh;
+use crate::simplify::{simplify, SimplifyOptions};
+use crate::threed::*;
+
+/// How far past a threshold the screen size has to go before switching level, as a fraction of
+/// the threshold. Stops an object sat right on a threshold from flickering between two levels
+const HYSTERESIS: f32 = 0.15;
+
+pub struct LodLevel {
+    pub mesh: Mesh,
+    /// This level is used once the object covers less than this fraction of the screen height
+    pub threshold: f32,
+}
+
+/// The lower detail versions of an object's mesh, ordered from most to least detailed.
+/// The object's own mesh is LOD 0, `levels[0]` is LOD 1 and so on.
+#[derive(Default)]
+pub struct Lods {
+    pub levels: Vec<LodLevel>,
+    pub current: usize,
+}
+
+impl Lods {
+    /// Load each level from its own OBJ file, `levels` is a list of (path, threshold)
+    pub fn from_files(levels: &[(String, f32)]) -> Result<Lods, io::Error> {
+        let mut lods = Lods::default();
+        for (path, threshold) in levels {
+            lods.levels.push(LodLevel {
+                mesh: Mesh::from_obj_file(path)?,
+                threshold: *threshold,
+            });
+        }
+        Ok(lods)
+    }
+
+    /// Build the levels by simplifying the full detail mesh, `levels` is a list of (fraction of triangles to keep, threshold)
+    pub fn generate(mesh: &Mesh, levels: &[(f32, f32)]) -> Lods {
+        let mut lods = Lods::default();
+        for (fraction, threshold) in levels {
+            let options = SimplifyOptions {
+                target_tris: ((mesh.indices.len() as f32) * fraction) as usize,
+                ..SimplifyOptions::default()
+            };
+            lods.levels.push(LodLevel {
+                mesh: simplify(mesh, &options),
+                threshold: *threshold,
+            });
+        }
+        lods
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// Pick the level for an object covering `screen_size` of the screen height.
+    /// Only moves one way or the other once the size is clear of the threshold by the hysteresis margin
+    pub fn select(&mut self, screen_size: f32) -> usize {
+        while self.current < self.levels.len()
+            && screen_size < self.levels[self.current].threshold * (1. - HYSTERESIS)
+        {
+            self.current += 1;
+        }
+
+        while self.current > 0
+            && screen_size > self.levels[self.current - 1].threshold * (1. + HYSTERESIS)
+        {
+            self.current -= 1;
+        }
+
+        self.current
+    }
+}
+
+/// Fraction of the screen height covered by a world space bounding sphere
+pub fn screen_size(sphere: &Sphere, camera: &Camera) -> f32 {
+    let d = sphere.centre - camera.position;
+    let distance = dot_product(d, d).sqrt();
+
+    if distance <= sphere.radius {
+        // The camera is inside the sphere
+        return f32::MAX;
+    }
+
+    let half_fov_tan = (camera.fov / 2.).to_radians().tan();
+    sphere.radius / (distance * half_fov_tan)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::Sphere;
+    use crate::lod::*;
+
+    fn test_lods() -> Lods {
+        let mesh = Mesh::from_obj_file("Resource/Models/cube.obj").unwrap();
+        Lods {
+            levels: vec![
+                LodLevel {
+                    mesh: mesh.clone(),
+                    threshold: 0.5,
+                },
+                LodLevel {
+                    mesh,
+                    threshold: 0.2,
+                },
+            ],
+            current: 0,
+        }
+    }
+
+    #[test]
+    fn test_select_by_size() {
+        let mut lods = test_lods();
+
+        assert_eq!(0, lods.select(1.));
+        assert_eq!(1, lods.select(0.3));
+        assert_eq!(2, lods.select(0.1));
+        assert_eq!(0, lods.select(0.9));
+    }
+
+    #[test]
+    fn test_select_hysteresis() {
+        let mut lods = test_lods();
+
+        // Just under the threshold isn't enough to switch down
+        assert_eq!(0, lods.select(0.48));
+        assert_eq!(1, lods.select(0.4));
+        // Just over the threshold isn't enough to switch back up
+        assert_eq!(1, lods.select(0.52));
+        assert_eq!(0, lods.select(0.6));
+    }
+
+    #[test]
+    fn test_screen_size() {
+        let camera = Camera {
+            fov: 90.,
+            near_plane: 0.1,
+            far_plane: 1000.,
+            position: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            yaw: 0.,
+            fwd_speed: 1.,
+            yaw_speed: 5.,
+        };
+
+        // With a 90 degree fov the screen is 20 units high at a distance of 10
+        let sphere = Sphere {
+            centre: vec3 {
+                x: 0.,
+                y: 0.,
+                z: 10.,
+            },
+            radius: 5.,
+        };
+
+        assert!((screen_size(&sphere, &camera) - 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_from_files() {
+        let lods = Lods::from_files(&[
+            ("Resource/Models/cube.obj".to_string(), 0.4),
+            ("Resource/Models/Plane 1m.obj".to_string(), 0.1),
+        ])
+        .unwrap();
+
+        assert_eq!(2, lods.levels.len());
+        assert_eq!(12, lods.levels[0].mesh.indices.len());
+        assert_eq!(2, lods.levels[1].mesh.indices.len());
+        assert_eq!(0.1, lods.levels[1].threshold);
+    }
+
+    #[test]
+    fn test_generate_reduces_tris() {
+        let mesh = Mesh::from_obj_file("Resource/Models/teapot.obj").unwrap();
+
+        let lods = Lods::generate(&mesh, &[(0.5, 0.3), (0.2, 0.15), (0.05, 0.05)]);
+
+        let mut prev_tris = mesh.indices.len();
+        for level in &lods.levels {
+            let tris = level.mesh.indices.len();
+            assert!(tris > 0);
+            assert!(tris < prev_tris);
+            prev_tris = tris;
+        }
+    }
+}
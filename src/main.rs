@@ -14,50 +14,521 @@
 // Object scaling
 // Textures!
 
-use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+use clap::{Parser, ValueEnum};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale};
 use noto_sans_mono_bitmap::{get_raster, FontWeight, RasterHeight};
-use raster::draw_outlined_triangle;
-use std::env;
-use std::time::Instant;
-use threed::*;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+
+use threedengine::colour::*;
+use threedengine::debug_draw::DebugOverlays;
+use threedengine::debug_view::DebugView;
+use threedengine::demo::init_scene;
+use threedengine::export::{save_depth, save_image, screenshot_name};
+use threedengine::fog::FogMode;
+use threedengine::line::LineCap;
+use threedengine::present::{render_frames, ImagePresenter, Presenter, WindowPresenter};
+use threedengine::reload::SceneReloader;
+use threedengine::renderer::{
+    AntiAliasing, Downsample, Rasterizer, Renderer, ShadingMode, ViewMode,
+};
+use threedengine::scene::Scene;
+use threedengine::scene_file::{load_scene, save_scene};
+use threedengine::threed::*;
+
+/// How often the loaded files are checked for changes
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a status message is shown for, reload errors stay up until the next reload
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+/// Widest the wireframe lines can be made from the keyboard
+const MAX_LINE_WIDTH: f32 = 16.;
+/// Size of the on screen text
+const TEXT_HEIGHT: RasterHeight = RasterHeight::Size20;
+/// The keys, a page at a time so each fits in the default window above the status message
+const HELP_PAGES: [&[&str]; 2] = [
+    &[
+        "LMB   Select object",
+        "RMB   Rotate object",
+        "MMB   Pan object (XZ) plane",
+        "Wheel Translate object (Y axis)",
+        "-------------------------------",
+        "W     Move Forwards",
+        "A     Move Left",
+        "S     Move Backwards",
+        "D     Move Right",
+        "<-    Yaw CCW",
+        "->    Yaw CW",
+        "-------------------------------",
+        "H     Next Help Page",
+        "L     Cycle View Mode",
+        "J     Toggle Smooth Lines",
+        "[ ]   Line Width",
+        "R     Toggle Rasterizer",
+        "X     Cycle Anti-Aliasing",
+        "T     Toggle Supersampling Filter",
+        "F     Toggle FXAA",
+        "K     Cycle Pixel Scale",
+        "B     Toggle Back Face Culling",
+    ],
+    &[
+        "H     Hide Help",
+        "P     Toggle Stats",
+        "O     Toggle LOD Overlay",
+        "G     Toggle Ground Grid",
+        "M     Cycle Fog",
+        "U     Toggle Per Vertex Fog",
+        "-------------------------------",
+        "1-2   Toggle Face/Vertex Normals",
+        "3     Toggle Bounding Boxes",
+        "4-5   Toggle World/Object Axes",
+        "6     Toggle Light Gizmo",
+        "7     Leave Camera Frustum Here",
+        "F1-F2 Toggle Depth/Normals View",
+        "F3-F4 Toggle Overdraw/Triangle View",
+        "-------------------------------",
+        "F5    Save Scene",
+        "F12   Save Screenshot",
+    ],
+];
+
+/// Interactive software 3D renderer
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// OBJ models to view side by side, or a single .ron scene file. Opens the demo scene if empty
+    files: Vec<String>,
+
+    /// Framebuffer size, e.g. 1280x720
+    #[arg(long, default_value = "800x600", value_parser = parse_resolution)]
+    resolution: (usize, usize),
+
+    /// Screen pixels per framebuffer pixel
+    #[arg(long, default_value_t = 1, value_parser = parse_scale)]
+    scale: usize,
+
+    /// Camera position, e.g. 0,5,-20
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    camera_pos: Option<vec3>,
+
+    /// Camera yaw in degrees
+    #[arg(long, allow_hyphen_values = true)]
+    yaw: Option<f32>,
+
+    /// Vertical field of view in degrees
+    #[arg(long)]
+    fov: Option<f32>,
+
+    #[arg(long, value_enum)]
+    shading: Option<Shading>,
+
+    /// What is drawn of each triangle
+    #[arg(long, value_enum)]
+    view: Option<ViewKind>,
+
+    /// Same as --view wireframe
+    #[arg(long)]
+    wireframe: bool,
+
+    /// Draw a grid on the ground
+    #[arg(long)]
+    grid: bool,
+
+    /// Show depth, normals, overdraw or triangle ids instead of the lit scene
+    #[arg(long, value_enum)]
+    debug_view: Option<DebugViewKind>,
+
+    /// Distance fog
+    #[arg(long, value_enum)]
+    fog: Option<FogKind>,
+
+    /// Fog colour, e.g. 150,160,175
+    #[arg(long, value_parser = parse_colour)]
+    fog_colour: Option<Colour>,
+
+    /// Depths the fog starts and is solid at, e.g. 5,50
+    #[arg(long, value_parser = parse_range)]
+    fog_range: Option<(f32, f32)>,
+
+    /// Work the fog out per vertex instead of per pixel
+    #[arg(long)]
+    vertex_fog: bool,
+
+    /// Keep the background colour instead of clearing to the fog colour
+    #[arg(long)]
+    fog_keep_background: bool,
+
+    /// Wireframe line width in pixels
+    #[arg(long)]
+    line_width: Option<f32>,
+
+    /// How the ends of wide lines are finished
+    #[arg(long, value_enum)]
+    line_cap: Option<LineCapKind>,
+
+    /// Anti-alias wireframe lines
+    #[arg(long)]
+    smooth_lines: bool,
+
+    /// Triangle filling algorithm
+    #[arg(long, value_enum)]
+    rasterizer: Option<RasterizerKind>,
+
+    /// Anti-aliasing
+    #[arg(long, value_enum)]
+    aa: Option<AaKind>,
+
+    /// Filter for shrinking supersampled frames
+    #[arg(long, value_enum)]
+    downsample: Option<DownsampleKind>,
+
+    /// Debug overlays to switch on, comma separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    overlay: Vec<OverlayKind>,
+
+    /// Post-process effects to switch on, comma separated: fxaa
+    #[arg(long, value_delimiter = ',')]
+    post: Vec<String>,
+
+    /// Threads to rasterise on, 0 for one per core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Start with the first page of help showing
+    #[arg(long)]
+    show_help: bool,
 
-use crate::raster::{draw_filled_triangle, Point};
+    /// Start with the stats hidden
+    #[arg(long)]
+    hide_stats: bool,
 
-use crate::colour::*;
+    /// Render to image files instead of opening a window
+    #[arg(long)]
+    headless: bool,
 
-mod threed;
+    /// Number of frames to render when headless
+    #[arg(long, default_value_t = 1)]
+    frames: usize,
 
-mod raster;
+    /// Directory for headless frames
+    #[arg(long, default_value = "frames")]
+    output: String,
 
-mod colour;
+    /// Degrees to turn the camera between headless frames
+    #[arg(long, default_value_t = 0., allow_hyphen_values = true)]
+    yaw_step: f32,
 
-const WIDTH: usize = 800;
-const HEIGHT: usize = 600;
-const NUM_PIXELS: usize = HEIGHT * WIDTH;
+    /// Image format for headless frames: ppm, bmp or png
+    #[arg(long, default_value = "png")]
+    format: String,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shading {
+    Flat,
+    Unlit,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RasterizerKind {
+    Scanline,
+    Edge,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ViewKind {
+    Shaded,
+    Wireframe,
+    HiddenLine,
+    ShadedWireframe,
+    Points,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DebugViewKind {
+    Off,
+    Depth,
+    Normals,
+    Overdraw,
+    TriangleId,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FogKind {
+    Off,
+    Linear,
+    Exp,
+    Exp2,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OverlayKind {
+    FaceNormals,
+    VertexNormals,
+    Bounds,
+    WorldAxes,
+    LocalAxes,
+    Light,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LineCapKind {
+    Square,
+    Round,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AaKind {
+    Off,
+    Ssaa2x,
+    Ssaa4x,
+    Msaa2x,
+    Msaa4x,
+    Msaa8x,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DownsampleKind {
+    Box,
+    Tent,
+}
+
+fn parse_resolution(s: &str) -> Result<(usize, usize), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {s}"))?;
+    let width: usize = width.parse().map_err(|e| format!("{e}"))?;
+    let height: usize = height.parse().map_err(|e| format!("{e}"))?;
+    if width == 0 || height == 0 {
+        return Err("resolution must be at least 1x1".to_string());
+    }
+    Ok((width, height))
+}
+
+fn parse_scale(s: &str) -> Result<usize, String> {
+    match s {
+        "1" | "2" | "4" | "8" => Ok(s.parse().unwrap()),
+        _ => Err("scale must be 1, 2, 4 or 8".to_string()),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<vec3, String> {
+    let parts: Vec<f32> = s
+        .split(',')
+        .map(|p| p.trim().parse::<f32>().map_err(|e| format!("{e}")))
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [x, y, z] => Ok(vec3 { x, y, z }),
+        _ => Err(format!("expected x,y,z, got {s}")),
+    }
+}
+
+fn parse_colour(s: &str) -> Result<Colour, String> {
+    let parts: Vec<u8> = s
+        .split(',')
+        .map(|p| p.trim().parse::<u8>().map_err(|e| format!("{e}")))
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [r, g, b] => Ok(Colour::new(r, g, b)),
+        _ => Err(format!("expected r,g,b, got {s}")),
+    }
+}
+
+fn parse_range(s: &str) -> Result<(f32, f32), String> {
+    let (start, end) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected start,end, got {s}"))?;
+    let start: f32 = start.trim().parse().map_err(|e| format!("{e}"))?;
+    let end: f32 = end.trim().parse().map_err(|e| format!("{e}"))?;
+    if start < 0. || end <= start {
+        return Err("the range must start at 0 or more and end after it starts".to_string());
+    }
+    Ok((start, end))
+}
+
+fn to_minifb_scale(scale: usize) -> Scale {
+    match scale {
+        2 => Scale::X2,
+        4 => Scale::X4,
+        8 => Scale::X8,
+        _ => Scale::X1,
+    }
+}
+
+/// Build the scene the command line asks for and apply the render options on top of it
+fn build_scene(args: &Args, renderer: &mut Renderer) -> Result<Scene, io::Error> {
+    let mut scene = match args.files.as_slice() {
+        [] => {
+            renderer.grid.enabled = true;
+            init_scene()
+        }
+        [path] if path.ends_with(".ron") => load_scene(path, renderer)?,
+        paths => {
+            if paths.iter().any(|p| p.ends_with(".ron")) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a scene file can't be opened together with other files",
+                ));
+            }
+            Scene::from_models(paths)?
+        }
+    };
+
+    if let Some(position) = args.camera_pos {
+        scene.camera.position = position;
+    }
+    if let Some(yaw) = args.yaw {
+        scene.camera.yaw = yaw;
+    }
+    if let Some(fov) = args.fov {
+        scene.camera.fov = fov;
+    }
+    if let Some(fog) = args.fog {
+        scene.fog.mode = match fog {
+            FogKind::Off => FogMode::Off,
+            FogKind::Linear => FogMode::Linear,
+            FogKind::Exp => FogMode::Exp,
+            FogKind::Exp2 => FogMode::Exp2,
+        };
+    }
+    if let Some(colour) = args.fog_colour {
+        scene.fog.colour = colour;
+    }
+    if let Some((start, end)) = args.fog_range {
+        scene.fog.start = start;
+        scene.fog.end = end;
+    }
+    if args.vertex_fog {
+        scene.fog.per_vertex = true;
+    }
+    if args.fog_keep_background {
+        scene.fog.background = false;
+    }
+    if let Some(shading) = args.shading {
+        renderer.shading = match shading {
+            Shading::Flat => ShadingMode::Flat,
+            Shading::Unlit => ShadingMode::Unlit,
+        };
+    }
+    if let Some(rasterizer) = args.rasterizer {
+        renderer.rasterizer = match rasterizer {
+            RasterizerKind::Scanline => Rasterizer::Scanline,
+            RasterizerKind::Edge => Rasterizer::EdgeFunction,
+        };
+    }
+    if let Some(aa) = args.aa {
+        renderer.anti_aliasing = match aa {
+            AaKind::Off => AntiAliasing::Off,
+            AaKind::Ssaa2x => AntiAliasing::Ssaa2x,
+            AaKind::Ssaa4x => AntiAliasing::Ssaa4x,
+            AaKind::Msaa2x => AntiAliasing::Msaa2x,
+            AaKind::Msaa4x => AntiAliasing::Msaa4x,
+            AaKind::Msaa8x => AntiAliasing::Msaa8x,
+        };
+    }
+    if let Some(downsample) = args.downsample {
+        renderer.downsample = match downsample {
+            DownsampleKind::Box => Downsample::Box,
+            DownsampleKind::Tent => Downsample::Tent,
+        };
+    }
+    for overlay in &args.overlay {
+        *overlay_flag(&mut renderer.overlays, *overlay) = true;
+    }
+    for name in &args.post {
+        if !renderer.post.set_enabled(name, true) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "no post-process effect called {name}, choose from {}",
+                    renderer.post.names().join(", ")
+                ),
+            ));
+        }
+    }
+    if let Some(view) = args.view {
+        renderer.view_mode = match view {
+            ViewKind::Shaded => ViewMode::Shaded,
+            ViewKind::Wireframe => ViewMode::Wireframe,
+            ViewKind::HiddenLine => ViewMode::HiddenLine,
+            ViewKind::ShadedWireframe => ViewMode::ShadedWireframe,
+            ViewKind::Points => ViewMode::Points,
+        };
+    }
+    if args.wireframe {
+        renderer.view_mode = ViewMode::Wireframe;
+    }
+    if args.grid {
+        renderer.grid.enabled = true;
+    }
+    if let Some(debug_view) = args.debug_view {
+        renderer.debug_view = match debug_view {
+            DebugViewKind::Off => DebugView::Off,
+            DebugViewKind::Depth => DebugView::Depth,
+            DebugViewKind::Normals => DebugView::Normals,
+            DebugViewKind::Overdraw => DebugView::Overdraw,
+            DebugViewKind::TriangleId => DebugView::TriangleId,
+        };
+    }
+    if let Some(width) = args.line_width {
+        renderer.line_style.width = width.max(1.);
+    }
+    if let Some(cap) = args.line_cap {
+        renderer.line_style.cap = match cap {
+            LineCapKind::Square => LineCap::Square,
+            LineCapKind::Round => LineCap::Round,
+        };
+    }
+    if args.smooth_lines {
+        renderer.line_style.anti_aliased = true;
+    }
+    renderer.threads = args.threads;
+
+    Ok(scene)
+}
+
+fn overlay_flag(overlays: &mut DebugOverlays, overlay: OverlayKind) -> &mut bool {
+    match overlay {
+        OverlayKind::FaceNormals => &mut overlays.face_normals,
+        OverlayKind::VertexNormals => &mut overlays.vertex_normals,
+        OverlayKind::Bounds => &mut overlays.bounds,
+        OverlayKind::WorldAxes => &mut overlays.world_axes,
+        OverlayKind::LocalAxes => &mut overlays.local_axes,
+        OverlayKind::Light => &mut overlays.light,
+    }
+}
 
 struct Stats {
     frame_rate: f32,
-    trans_and_proj_time: f32,
-    raster_time: f32,
     present_time: f32,
-    vis_tris: usize,
+}
+
+/// A line of text shown along the bottom of the screen
+struct StatusMessage {
+    text: String,
+    /// `None` to keep showing it until it is replaced
+    expires: Option<Instant>,
 }
 
 struct Core {
-    view_mat: ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    proj_mat: ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    camera: Camera,
-    light_dir: vec3,
-    window: Window,
-    pixel_buffer: Vec<u32>,
-    objects: Vec<Object>,
+    renderer: Renderer,
+    scene: Scene,
+    /// Where the scene was loaded from and is saved back to
+    scene_path: Option<String>,
+    reloader: SceneReloader,
+    last_reload_poll: Instant,
+    message: Option<StatusMessage>,
+    presenter: WindowPresenter,
     should_shutdown: bool,
     mouse_button_held: MouseButtonHeld,
     selected_object: usize,
+    transforms_dirty: bool,
     prev_mouse_pos: Option<(f32, f32)>,
-    wireframe_enabled: bool,
-    help_enabled: bool,
+    /// The page of help showing, if any
+    help_page: Option<usize>,
     stats_enabled: bool,
+    lod_overlay_enabled: bool,
+    screenshot_requested: bool,
     stats: Stats,
 }
 
@@ -68,212 +539,447 @@ struct _MousePos {
 
 enum MouseButtonHeld {
     None,
-    _Left,
+    Left,
     Middle,
     Right,
 }
 
-fn init() -> Core {
-    let screen = Screen {
-        width: 800,
-        height: 600,
-    };
+fn init(args: &Args) -> Core {
+    let (width, height) = args.resolution;
+    let mut renderer = Renderer::new(width, height);
 
-    let cam_pos = vec3 {
-        x: 0.,
-        y: 5.,
-        z: -20.,
-    };
+    let scene = build_scene(args, &mut renderer).unwrap_or_else(|e| {
+        println!("Unable to open scene: {e}");
+        process::exit(1);
+    });
 
-    let camera = Camera {
-        fov: 60.,
-        near_plane: 0.1,
-        far_plane: 1000.,
-        position: cam_pos,
-        yaw: 0.,
-        fwd_speed: 1.,
-        yaw_speed: 5.,
+    // Only a scene file can be saved back to where it came from
+    let scene_path = match args.files.as_slice() {
+        [path] if path.ends_with(".ron") => Some(path.clone()),
+        _ => None,
     };
 
-    let proj_mat = camera.create_projection_matrix(screen);
-
-    let view_mat = camera.create_view_matrix();
-
-    let light_dir = vec3 {
-        x: 0.,
-        y: 10.,
-        z: -10.,
+    let reloader = match &scene_path {
+        Some(path) => SceneReloader::for_scene_file(path, &scene),
+        None => SceneReloader::for_objects(&scene),
     };
 
-    let window = Window::new(
-        "3D Renderer",
-        WIDTH,
-        HEIGHT,
-        WindowOptions {
-            resize: false,
-            scale: Scale::X1,
-            ..WindowOptions::default()
-        },
-    )
-    .expect("Unable to create window");
-
-    // Limit to max ~100 fps update rate
-    // window.limit_update_rate(Some(std::time::Duration::from_millis(10)));
-
-    let pixel_buffer: Vec<u32> = vec![0; NUM_PIXELS];
-
-    let mut objects = vec![
-        init_cube(),
-        init_teapot(0., 0., -8.),
-        // init_spaceship(-5., 2., 5.),
-    ];
-
-    let floor = init_checkerboard_floor();
-
-    for obj in floor {
-        objects.push(obj);
-    }
+    let presenter = WindowPresenter::new("3D Renderer", width, height, to_minifb_scale(args.scale));
 
     let stats = Stats {
         frame_rate: 0.,
-        trans_and_proj_time: 0.,
-        raster_time: 0.,
         present_time: 0.,
-        vis_tris: 0,
     };
 
     Core {
-        view_mat,
-        proj_mat,
-        camera,
-        light_dir,
-        window,
-        pixel_buffer,
-        objects,
+        selected_object: 1.min(scene.objects.len().saturating_sub(1)),
+        renderer,
+        scene,
+        scene_path,
+        reloader,
+        last_reload_poll: Instant::now(),
+        message: None,
+        presenter,
         should_shutdown: false,
         mouse_button_held: MouseButtonHeld::None,
-        selected_object: 1,
+        transforms_dirty: false,
         prev_mouse_pos: None,
-        wireframe_enabled: false,
-        help_enabled: false,
-        stats_enabled: true,
+        help_page: args.show_help.then_some(0),
+        stats_enabled: !args.hide_stats,
+        lod_overlay_enabled: false,
+        screenshot_requested: false,
         stats,
     }
 }
 
 fn main() {
-    let mut core = init();
-    main_loop(&mut core);
-    if core.should_shutdown {
+    let args = Args::parse();
+
+    if args.headless {
+        run_headless(&args);
         return;
     }
+
+    let mut core = init(&args);
+    main_loop(&mut core);
+}
+
+/// Render the requested frames straight to image files, no window is created
+fn run_headless(args: &Args) {
+    let (width, height) = args.resolution;
+    let mut renderer = Renderer::new(width, height);
+
+    let result = build_scene(args, &mut renderer).and_then(|mut scene| {
+        let mut presenter = ImagePresenter::new(&args.output, &args.format)?;
+        render_frames(
+            &mut renderer,
+            &mut scene,
+            &mut presenter,
+            args.frames,
+            args.yaw_step,
+        )
+    });
+
+    match result {
+        Ok(()) => println!("Rendered {} frames to {}", args.frames, args.output),
+        Err(e) => {
+            println!("Headless render failed: {e}");
+            process::exit(1);
+        }
+    }
 }
 
 fn handle_keys(core: &mut Core) {
-    if core.window.is_key_down(Key::Escape) {
+    if core.presenter.window.is_key_down(Key::Escape) {
         core.should_shutdown = true;
     }
 
-    if core.window.is_key_pressed(Key::L, KeyRepeat::No) {
-        core.wireframe_enabled = !core.wireframe_enabled;
+    if core.presenter.window.is_key_pressed(Key::L, KeyRepeat::No) {
+        core.renderer.view_mode = core.renderer.view_mode.next();
+        core.message = Some(StatusMessage {
+            text: format!("View: {}", core.renderer.view_mode.name()),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::J, KeyRepeat::No) {
+        let style = &mut core.renderer.line_style;
+        style.anti_aliased = !style.anti_aliased;
+        core.message = Some(StatusMessage {
+            text: format!(
+                "Smooth lines: {}",
+                if style.anti_aliased { "on" } else { "off" }
+            ),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    for (key, step) in [(Key::LeftBracket, -1.), (Key::RightBracket, 1.)] {
+        if core.presenter.window.is_key_pressed(key, KeyRepeat::Yes) {
+            let style = &mut core.renderer.line_style;
+            style.width = (style.width + step).clamp(1., MAX_LINE_WIDTH);
+            core.message = Some(StatusMessage {
+                text: format!("Line width: {}", style.width),
+                expires: Some(Instant::now() + MESSAGE_TIME),
+            });
+        }
+    }
+
+    if core.presenter.window.is_key_pressed(Key::R, KeyRepeat::No) {
+        let (rasterizer, name) = match core.renderer.rasterizer {
+            Rasterizer::Scanline => (Rasterizer::EdgeFunction, "edge function"),
+            Rasterizer::EdgeFunction => (Rasterizer::Scanline, "scanline"),
+        };
+        core.renderer.rasterizer = rasterizer;
+        core.message = Some(StatusMessage {
+            text: format!("Rasterizer: {name}"),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::X, KeyRepeat::No) {
+        core.renderer.anti_aliasing = core.renderer.anti_aliasing.next();
+        core.message = Some(StatusMessage {
+            text: format!("Anti-aliasing: {}", core.renderer.anti_aliasing.name()),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::T, KeyRepeat::No) {
+        let (downsample, name) = match core.renderer.downsample {
+            Downsample::Box => (Downsample::Tent, "tent"),
+            Downsample::Tent => (Downsample::Box, "box"),
+        };
+        core.renderer.downsample = downsample;
+        core.message = Some(StatusMessage {
+            text: format!("Supersampling filter: {name}"),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::F, KeyRepeat::No) {
+        let enabled = core.renderer.post.toggle("fxaa").unwrap_or(false);
+        core.message = Some(StatusMessage {
+            text: format!("FXAA: {}", if enabled { "on" } else { "off" }),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
     }
 
-    if core.window.is_key_pressed(Key::H, KeyRepeat::No) {
-        core.help_enabled = !core.help_enabled;
+    let overlay_keys = [
+        (Key::Key1, OverlayKind::FaceNormals, "Face normals"),
+        (Key::Key2, OverlayKind::VertexNormals, "Vertex normals"),
+        (Key::Key3, OverlayKind::Bounds, "Bounding boxes"),
+        (Key::Key4, OverlayKind::WorldAxes, "World axes"),
+        (Key::Key5, OverlayKind::LocalAxes, "Object axes"),
+        (Key::Key6, OverlayKind::Light, "Light"),
+    ];
+    for (key, overlay, name) in overlay_keys {
+        if core.presenter.window.is_key_pressed(key, KeyRepeat::No) {
+            let flag = overlay_flag(&mut core.renderer.overlays, overlay);
+            *flag = !*flag;
+            core.message = Some(StatusMessage {
+                text: format!("{name}: {}", if *flag { "on" } else { "off" }),
+                expires: Some(Instant::now() + MESSAGE_TIME),
+            });
+        }
     }
 
-    if core.window.is_key_pressed(Key::P, KeyRepeat::No) {
+    if core
+        .presenter
+        .window
+        .is_key_pressed(Key::Key7, KeyRepeat::No)
+    {
+        // Leave a copy of the camera where it is, to fly away from and see what it sees
+        let frustum_camera = &mut core.renderer.overlays.frustum_camera;
+        let text = if frustum_camera.is_some() {
+            *frustum_camera = None;
+            "Camera frustum: off"
+        } else {
+            *frustum_camera = Some(core.scene.camera.clone());
+            "Camera frustum: left at the current view"
+        };
+        core.message = Some(StatusMessage {
+            text: text.to_string(),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::G, KeyRepeat::No) {
+        let grid = &mut core.renderer.grid;
+        grid.enabled = !grid.enabled;
+        core.message = Some(StatusMessage {
+            text: format!("Ground grid: {}", if grid.enabled { "on" } else { "off" }),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    // Pressing the key for the view already showing goes back to the lit scene
+    for (key, view) in [
+        (Key::F1, DebugView::Depth),
+        (Key::F2, DebugView::Normals),
+        (Key::F3, DebugView::Overdraw),
+        (Key::F4, DebugView::TriangleId),
+    ] {
+        if core.presenter.window.is_key_pressed(key, KeyRepeat::No) {
+            let debug_view = &mut core.renderer.debug_view;
+            *debug_view = if *debug_view == view {
+                DebugView::Off
+            } else {
+                view
+            };
+            core.message = Some(StatusMessage {
+                text: format!("Debug view: {}", debug_view.name()),
+                expires: Some(Instant::now() + MESSAGE_TIME),
+            });
+        }
+    }
+
+    if core.presenter.window.is_key_pressed(Key::M, KeyRepeat::No) {
+        let fog = &mut core.scene.fog;
+        fog.mode = fog.mode.next();
+        core.message = Some(StatusMessage {
+            text: format!("Fog: {}", fog.mode.name()),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::U, KeyRepeat::No) {
+        let fog = &mut core.scene.fog;
+        fog.per_vertex = !fog.per_vertex;
+        core.message = Some(StatusMessage {
+            text: format!(
+                "Fog: {}",
+                if fog.per_vertex {
+                    "per vertex"
+                } else {
+                    "per pixel"
+                }
+            ),
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        });
+    }
+
+    if core.presenter.window.is_key_pressed(Key::H, KeyRepeat::No) {
+        core.help_page = match core.help_page {
+            None => Some(0),
+            Some(page) if page + 1 < HELP_PAGES.len() => Some(page + 1),
+            Some(_) => None,
+        };
+    }
+
+    if core.presenter.window.is_key_pressed(Key::P, KeyRepeat::No) {
         core.stats_enabled = !core.stats_enabled;
     }
 
-    if core.window.is_key_pressed(Key::W, KeyRepeat::Yes) {
-        core.camera.move_forwards();
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::O, KeyRepeat::No) {
+        core.lod_overlay_enabled = !core.lod_overlay_enabled;
+    }
+
+    if core.presenter.window.is_key_pressed(Key::K, KeyRepeat::No) {
+        // Cycle through chunkier pixels, keeping the window the same size
+        let scale = match core.presenter.scale {
+            Scale::X1 => Scale::X2,
+            Scale::X2 => Scale::X4,
+            _ => Scale::X1,
+        };
+        core.presenter.set_scale(scale);
+    }
+
+    if core
+        .presenter
+        .window
+        .is_key_pressed(Key::F12, KeyRepeat::No)
+    {
+        core.screenshot_requested = true;
     }
 
-    if core.window.is_key_pressed(Key::S, KeyRepeat::Yes) {
-        core.camera.move_backwards();
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::F5, KeyRepeat::No) {
+        save_current_scene(core);
     }
 
-    if core.window.is_key_pressed(Key::A, KeyRepeat::Yes) {
-        core.camera.move_left();
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::W, KeyRepeat::Yes) {
+        core.scene.camera.move_forwards();
     }
 
-    if core.window.is_key_pressed(Key::D, KeyRepeat::Yes) {
-        core.camera.move_right();
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::S, KeyRepeat::Yes) {
+        core.scene.camera.move_backwards();
     }
 
-    if core.window.is_key_pressed(Key::Left, KeyRepeat::Yes) {
-        core.camera.yaw = core.camera.yaw + 5.;
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::A, KeyRepeat::Yes) {
+        core.scene.camera.move_left();
     }
 
-    if core.window.is_key_pressed(Key::Right, KeyRepeat::Yes) {
-        core.camera.yaw = core.camera.yaw - 5.;
-        core.view_mat = core.camera.create_view_matrix();
+    if core.presenter.window.is_key_pressed(Key::D, KeyRepeat::Yes) {
+        core.scene.camera.move_right();
+    }
+
+    if core
+        .presenter
+        .window
+        .is_key_pressed(Key::Left, KeyRepeat::Yes)
+    {
+        core.scene.camera.yaw += core.scene.camera.yaw_speed;
+    }
+
+    if core
+        .presenter
+        .window
+        .is_key_pressed(Key::Right, KeyRepeat::Yes)
+    {
+        core.scene.camera.yaw -= core.scene.camera.yaw_speed;
     }
 }
 
 fn handle_mouse(core: &mut Core) {
-    if core.window.get_mouse_down(MouseButton::Middle) {
+    if core.scene.objects.is_empty() {
+        return;
+    }
+
+    let was_left_held = matches!(core.mouse_button_held, MouseButtonHeld::Left);
+
+    if core.presenter.window.get_mouse_down(MouseButton::Left) {
+        core.mouse_button_held = MouseButtonHeld::Left;
+    } else if core.presenter.window.get_mouse_down(MouseButton::Middle) {
         core.mouse_button_held = MouseButtonHeld::Middle;
-    } else if core.window.get_mouse_down(MouseButton::Right) {
+    } else if core.presenter.window.get_mouse_down(MouseButton::Right) {
         core.mouse_button_held = MouseButtonHeld::Right;
     } else {
         core.mouse_button_held = MouseButtonHeld::None;
     }
 
     match core.mouse_button_held {
+        MouseButtonHeld::Left => {
+            // Only pick on the initial click, not while the button is held
+            if !was_left_held {
+                pick_object(core);
+            }
+            core.prev_mouse_pos = None;
+        }
+
         MouseButtonHeld::Middle => match core.prev_mouse_pos {
             Some(prev_pos) => {
-                let curr = core.window.get_mouse_pos(MouseMode::Clamp).unwrap();
+                let curr = core
+                    .presenter
+                    .window
+                    .get_mouse_pos(MouseMode::Clamp)
+                    .unwrap();
                 let delta_x = prev_pos.0 - curr.0;
                 let delta_y = prev_pos.1 - curr.1;
 
-                core.objects[core.selected_object].transform.position.x -= delta_x / 3.;
-                core.objects[core.selected_object].transform.position.z += delta_y / 3.;
-                core.prev_mouse_pos = core.window.get_mouse_pos(MouseMode::Clamp);
+                core.scene.objects[core.selected_object]
+                    .transform
+                    .position
+                    .x -= delta_x / 3.;
+                core.scene.objects[core.selected_object]
+                    .transform
+                    .position
+                    .z += delta_y / 3.;
+                core.transforms_dirty = true;
+                core.prev_mouse_pos = core.presenter.window.get_mouse_pos(MouseMode::Clamp);
             }
             None => {
-                core.prev_mouse_pos = core.window.get_mouse_pos(MouseMode::Clamp);
+                core.prev_mouse_pos = core.presenter.window.get_mouse_pos(MouseMode::Clamp);
             }
         },
 
         MouseButtonHeld::Right => match core.prev_mouse_pos {
             Some(prev_pos) => {
-                let curr = core.window.get_mouse_pos(MouseMode::Clamp).unwrap();
+                let curr = core
+                    .presenter
+                    .window
+                    .get_mouse_pos(MouseMode::Clamp)
+                    .unwrap();
                 let delta_x = prev_pos.0 - curr.0;
                 let delta_y = prev_pos.1 - curr.1;
 
-                core.objects[core.selected_object].transform.rotation.y -= delta_x;
-                core.objects[core.selected_object].transform.rotation.z += delta_y;
-                core.prev_mouse_pos = core.window.get_mouse_pos(MouseMode::Clamp);
+                core.scene.objects[core.selected_object]
+                    .transform
+                    .rotation
+                    .y -= delta_x;
+                core.scene.objects[core.selected_object]
+                    .transform
+                    .rotation
+                    .z += delta_y;
+                core.transforms_dirty = true;
+                core.prev_mouse_pos = core.presenter.window.get_mouse_pos(MouseMode::Clamp);
             }
             None => {
-                core.prev_mouse_pos = core.window.get_mouse_pos(MouseMode::Clamp);
+                core.prev_mouse_pos = core.presenter.window.get_mouse_pos(MouseMode::Clamp);
             }
         },
         _ => core.prev_mouse_pos = None,
     }
 
-    let delta_y = core.window.get_scroll_wheel();
+    let delta_y = core.presenter.window.get_scroll_wheel();
 
-    match delta_y {
-        Some(val) => core.objects[core.selected_object].transform.position.y += val.1 / 20.,
-        None => {}
-    };
+    if let Some(val) = delta_y {
+        core.scene.objects[core.selected_object]
+            .transform
+            .position
+            .y += val.1 / 20.;
+        core.transforms_dirty = true;
+    }
+}
+
+/// Select the object under the mouse cursor, if there is one
+fn pick_object(core: &mut Core) {
+    if let Some((x, y)) = core.presenter.window.get_mouse_pos(MouseMode::Discard) {
+        let ray = core.renderer.screen_ray(&core.scene.camera, x, y);
+        if let Some(hit) = core
+            .scene
+            .bvh
+            .intersect_ray_nearest(&core.scene.objects, &ray)
+        {
+            core.selected_object = hit.object;
+        }
+    }
 }
 
 fn main_loop(core: &mut Core) {
     let mut prev = Instant::now();
-    let mut _rot_y = 0f32;
 
     let font_weight = FontWeight::Regular;
-    let raster_height = RasterHeight::Size20;
-    let fill_colour = Colour::new(59, 59, 59);
+    let raster_height = TEXT_HEIGHT;
 
     loop {
         handle_keys(core);
@@ -282,97 +988,49 @@ fn main_loop(core: &mut Core) {
             return;
         }
 
-        core.pixel_buffer[0..NUM_PIXELS].fill(fill_colour.as_0rgb());
-
-        let now = Instant::now();
-        let delta_time = (now - prev).as_secs_f32();
-        prev = now;
-        core.stats.frame_rate = 1. / delta_time;
-
-        // let degrees_per_second = 36.;
-        //rot_y += delta_time * degrees_per_second;
-
-        let mut tris: Vec<(raster::Tri, vec3, Colour)> = Vec::new();
-
-        //Start of Transform and project
-        let trans_and_proj_time_start = Instant::now();
-
-        for object in &core.objects {
-            let rot_x_mat = create_x_rotation_matrix(object.transform.rotation.x);
-            let rot_y_mat = create_y_rotation_matrix(object.transform.rotation.y);
-            let rot_z_mat = create_z_rotation_matrix(object.transform.rotation.z);
-            let trans_mat = create_translation_matrix(
-                object.transform.position.x,
-                object.transform.position.y,
-                object.transform.position.z,
-            );
-
-            for tri in &object.tris {
-                let proc_tri = process_tri(
-                    &core,
-                    tri,
-                    &rot_z_mat,
-                    &rot_y_mat,
-                    &rot_x_mat,
-                    &trans_mat,
-                    object.albedo,
-                );
-
-                match proc_tri {
-                    Some(tri2) => tris.push(tri2),
-                    None => (),
-                }
-            }
+        // Follow the window size, the projection is rebuilt from the framebuffer size each frame
+        let (width, height) = core.presenter.framebuffer_size();
+        let framebuffer = &core.renderer.framebuffer;
+        if (width, height) != (framebuffer.width, framebuffer.height) {
+            core.renderer.resize(width, height);
         }
 
-        let mut z_vals = Vec::new();
-        for tri in &tris {
-            let z = tri.0.p1.z + tri.0.p2.z + tri.0.p3.z;
-            z_vals.push((z * 1000000.) as u32); //This weird multiplication is just to be able to sort by z
+        if core.last_reload_poll.elapsed() >= RELOAD_POLL_INTERVAL {
+            reload_changed_files(core);
+            core.last_reload_poll = Instant::now();
         }
 
-        let mut indices = (0..tris.len()).collect::<Vec<_>>();
-        indices.sort_by_key(|&i| z_vals[i]);
-        indices.reverse();
-
-        let trans_and_proj_time_end = Instant::now();
-
-        core.stats.trans_and_proj_time =
-            (trans_and_proj_time_end - trans_and_proj_time_start).as_secs_f32();
-        //End of Transform and Project
-
-        //Start of Raster
-        let raster_time_start = Instant::now();
-
-        for index in 0..tris.len() {
-            let tri = &tris[indices[index]];
-
-            let colour = calc_tri_illum(&core.light_dir, &tri.1, tri.2);
-            if core.wireframe_enabled {
-                draw_outlined_triangle(&mut core.pixel_buffer, &tri.0, colour.as_0rgb());
-            } else {
-                draw_filled_triangle(&mut core.pixel_buffer, &tri.0, colour.as_0rgb());
-            }
+        if core.transforms_dirty {
+            core.scene.refit();
+            core.transforms_dirty = false;
         }
 
-        let raster_time_end = Instant::now();
+        let now = Instant::now();
+        let delta_time = (now - prev).as_secs_f32();
+        prev = now;
+        core.stats.frame_rate = 1. / delta_time;
 
-        core.stats.raster_time = (raster_time_end - raster_time_start).as_secs_f32();
-        //End of Raster
+        core.renderer.render(&mut core.scene);
 
-        core.stats.vis_tris = tris.len();
+        // Saved before the text overlays are drawn on top
+        if core.screenshot_requested {
+            save_screenshot(core);
+            core.screenshot_requested = false;
+        }
 
         if core.stats_enabled {
             draw_stats(core, font_weight, raster_height);
         };
+        if core.lod_overlay_enabled {
+            draw_lod_overlay(core, font_weight, raster_height);
+        }
         draw_help(core, font_weight, raster_height);
+        draw_message(core, font_weight, raster_height);
 
         //Start of Present
         let present_time_start = Instant::now();
 
-        core.window
-            .update_with_buffer(&core.pixel_buffer, WIDTH, HEIGHT)
-            .unwrap();
+        core.presenter.present(&core.renderer.framebuffer).unwrap();
 
         let present_time_end = Instant::now();
         core.stats.present_time = (present_time_end - present_time_start).as_secs_f32();
@@ -380,13 +1038,65 @@ fn main_loop(core: &mut Core) {
     }
 }
 
+/// Pick up any edits to the loaded files, keeping the same object selected
+fn reload_changed_files(core: &mut Core) {
+    let selected_name = core
+        .scene
+        .objects
+        .get(core.selected_object)
+        .map(|o| o.name.clone());
+
+    let Some(result) = core.reloader.poll(&mut core.scene, &mut core.renderer) else {
+        return;
+    };
+
+    // A scene file reload can add, remove or reorder objects
+    let objects = &core.scene.objects;
+    core.selected_object = objects
+        .iter()
+        .position(|o| Some(&o.name) == selected_name.as_ref())
+        .unwrap_or(core.selected_object.min(objects.len().saturating_sub(1)));
+
+    core.message = Some(match result {
+        Ok(text) => StatusMessage {
+            text,
+            expires: Some(Instant::now() + MESSAGE_TIME),
+        },
+        Err(e) => StatusMessage {
+            text: format!("Reload failed: {e}"),
+            expires: None,
+        },
+    });
+}
+
+/// Write the scene as it is now back to the file it came from, or to scene.ron for the built in demo
+fn save_current_scene(core: &Core) {
+    let path = core.scene_path.as_deref().unwrap_or("scene.ron");
+    match save_scene(path, &core.scene, &core.renderer) {
+        Ok(()) => println!("Saved scene {path}"),
+        Err(e) => println!("Unable to save scene: {e}"),
+    }
+}
+
+/// Save the colour buffer and a 16-bit depth image alongside it in the working directory
+fn save_screenshot(core: &Core) {
+    let path = screenshot_name();
+    let depth_path = path.replace(".png", "_depth.png");
+
+    let framebuffer = &core.renderer.framebuffer;
+    match save_image(&path, framebuffer).and_then(|_| save_depth(&depth_path, framebuffer)) {
+        Ok(()) => println!("Saved screenshot {path}"),
+        Err(e) => println!("Unable to save screenshot: {e}"),
+    }
+}
+
 fn draw_stats(core: &mut Core, font_weight: FontWeight, raster_height: RasterHeight) {
     let x_pos = 520;
     let frame_rate = core.stats.frame_rate;
     let msg = format!("Frame Rate       {frame_rate:.0} FPS");
     draw_string(msg.as_str(), x_pos, 0, font_weight, raster_height, core);
 
-    let trans_and_proj_time_ms = core.stats.trans_and_proj_time * 1000.;
+    let trans_and_proj_time_ms = core.renderer.stats.trans_and_proj_time * 1000.;
     let msg = format!("Trans. & Proj      {trans_and_proj_time_ms:.0} ms");
     draw_string(
         msg.as_str(),
@@ -397,7 +1107,7 @@ fn draw_stats(core: &mut Core, font_weight: FontWeight, raster_height: RasterHei
         core,
     );
 
-    let raster_time_ms = core.stats.raster_time * 1000.;
+    let raster_time_ms = core.renderer.stats.raster_time * 1000.;
     let msg = format!("Raster             {raster_time_ms:.0} ms");
     draw_string(
         msg.as_str(),
@@ -408,8 +1118,8 @@ fn draw_stats(core: &mut Core, font_weight: FontWeight, raster_height: RasterHei
         core,
     );
 
-    let present_time_ms = core.stats.present_time * 1000.;
-    let msg = format!("Present            {present_time_ms:.0} us");
+    let present_time_us = core.stats.present_time * 1_000_000.;
+    let msg = format!("Present            {present_time_us:.0} us");
     draw_string(
         msg.as_str(),
         x_pos,
@@ -419,7 +1129,7 @@ fn draw_stats(core: &mut Core, font_weight: FontWeight, raster_height: RasterHei
         core,
     );
 
-    let vis_tris = core.stats.vis_tris;
+    let vis_tris = core.renderer.stats.vis_tris;
     let msg = format!("Visible tris.   {vis_tris}");
     draw_string(
         msg.as_str(),
@@ -429,47 +1139,114 @@ fn draw_stats(core: &mut Core, font_weight: FontWeight, raster_height: RasterHei
         raster_height,
         core,
     );
+
+    let trans_verts = core.renderer.stats.trans_verts;
+    let msg = format!("Trans. verts    {trans_verts}");
+    draw_string(
+        msg.as_str(),
+        x_pos,
+        5 * raster_height as u32,
+        font_weight,
+        raster_height,
+        core,
+    );
+
+    let vis_objects = core.renderer.stats.vis_objects;
+    let culled_objects = core.renderer.stats.culled_objects;
+    let msg = format!("Objects  {vis_objects} vis. {culled_objects} cull.");
+    draw_string(
+        msg.as_str(),
+        x_pos,
+        6 * raster_height as u32,
+        font_weight,
+        raster_height,
+        core,
+    );
+
+    let aa = core.renderer.anti_aliasing.name();
+    let aa_time_ms = core.renderer.stats.aa_time * 1000.;
+    let msg = format!("AA {aa:<8}        {aa_time_ms:.0} ms");
+    draw_string(
+        msg.as_str(),
+        x_pos,
+        7 * raster_height as u32,
+        font_weight,
+        raster_height,
+        core,
+    );
+
+    let post_time_ms = core.renderer.stats.post_time * 1000.;
+    let msg = format!("Post               {post_time_ms:.0} ms");
+    draw_string(
+        msg.as_str(),
+        x_pos,
+        8 * raster_height as u32,
+        font_weight,
+        raster_height,
+        core,
+    );
+}
+
+/// List the active level of detail for each object that has more than one, from the bottom of the screen up
+fn draw_lod_overlay(core: &mut Core, font_weight: FontWeight, raster_height: RasterHeight) {
+    let mut msgs = Vec::new();
+    for object in &core.scene.objects {
+        if !object.lods.is_empty() {
+            let name = &object.name;
+            let lod = object.lods.current;
+            let tris = object.active_mesh().indices.len();
+            msgs.push(format!("{name}  LOD {lod}  {tris} tris"));
+        }
+    }
+
+    // Kept clear of the status message on the bottom line
+    let first_line = if core.message.is_some() { 2 } else { 1 };
+    for (i, msg) in msgs.iter().enumerate() {
+        let height = core.renderer.framebuffer.height as u32;
+        let Some(y) = height.checked_sub((i as u32 + first_line) * raster_height as u32) else {
+            break;
+        };
+        draw_string(msg.as_str(), 0, y, font_weight, raster_height, core);
+    }
+}
+
+/// Show the latest status message on the bottom line until it expires
+fn draw_message(core: &mut Core, font_weight: FontWeight, raster_height: RasterHeight) {
+    let Some(message) = &core.message else {
+        return;
+    };
+    if message
+        .expires
+        .is_some_and(|expires| Instant::now() >= expires)
+    {
+        core.message = None;
+        return;
+    }
+
+    let text = message.text.clone();
+    let height = core.renderer.framebuffer.height as u32;
+    if let Some(y) = height.checked_sub(raster_height as u32) {
+        draw_string(&text, 0, y, font_weight, raster_height, core);
+    }
 }
 
 fn draw_help(core: &mut Core, font_weight: FontWeight, raster_height: RasterHeight) {
     let x_pos = 0;
 
-    let msg: Vec<&str>;
-
-    if core.help_enabled {
-        msg = vec![
-            "LMB   Select object",
-            "RMB   Rotate object",
-            "MMB   Pan object (XZ) plane",
-            "Wheel Translate object (Y axis)",
-            "-------------------------------",
-            "W     Move Forwards",
-            "A     Move Left",
-            "S     Move Backwards",
-            "D     Move Right",
-            "<-    Yaw CCW",
-            "->    Yaw CW",
-            "-------------------------------",
-            "H     Toggle Help",
-            "L     Toggle Wireframe Mode",
-            "P     Toggle Stats",
-            "B     Toggle Back Face Culling",
-        ];
-    } else {
-        msg = vec!["Press H to toggle Help"];
-    }
+    let msg: &[&str] = match core.help_page {
+        Some(page) => HELP_PAGES[page],
+        None => &["Press H to toggle Help"],
+    };
 
-    let mut i = 0;
-    for msg in msg {
+    for (i, msg) in msg.iter().enumerate() {
         draw_string(
             msg,
             x_pos,
-            i * raster_height as u32,
+            i as u32 * raster_height as u32,
             font_weight,
             raster_height,
             core,
         );
-        i += 1;
     }
 }
 
@@ -481,215 +1258,46 @@ fn draw_string(
     raster_height: RasterHeight,
     core: &mut Core,
 ) {
+    let framebuffer = &mut core.renderer.framebuffer;
     for (char_i, char) in msg.chars().enumerate() {
-        let char_raster = get_raster(char, font_weight, raster_height).expect("unknown char");
+        // Messages can hold any text, such as paths from a load error
+        let char_raster = get_raster(char, font_weight, raster_height)
+            .or_else(|| get_raster('?', font_weight, raster_height))
+            .expect("? is in the font");
         for (row_i, row) in char_raster.raster().iter().enumerate() {
             for (col_i, intensity) in row.iter().enumerate() {
-                let index = char_i * char_raster.width()
-                    + col_i
-                    + row_i * WIDTH
-                    + (x as usize)
-                    + (y as usize * WIDTH);
+                let pixel_x = char_i * char_raster.width() + col_i + (x as usize);
+                let pixel_y = row_i + (y as usize);
 
-                let mut curr_pixel = Colour::from_u32(core.pixel_buffer[index]);
+                // Text that doesn't fit in a small window is cut off
+                if pixel_x >= framebuffer.width || pixel_y >= framebuffer.height {
+                    continue;
+                }
+
+                let index = pixel_x + pixel_y * framebuffer.width;
+
+                let mut curr_pixel = Colour::from_u32(framebuffer.pixels[index]);
 
                 curr_pixel.add_intensity(*intensity);
 
-                core.pixel_buffer[index] = curr_pixel.as_0rgb();
+                framebuffer.pixels[index] = curr_pixel.as_0rgb();
             }
         }
     }
 }
 
-fn model_path(model_name: String) -> String {
-    let curr_dir = env::current_dir().unwrap();
-    let mut path = curr_dir.join("Resource\\Models");
-    path = path.join(model_name);
-    let path_str = path.into_os_string().to_str().unwrap().to_string();
-    println!("Model path: {path_str}");
-    path_str
-}
-
-fn init_checkerboard_floor() -> Vec<Object> {
-    let mut objs = Vec::new();
-
-    let model_path = model_path("Plane 1m.obj".to_string());
-
-    let rotation = vec3 {
-        x: 0.,
-        y: 0.,
-        z: 0.,
-    };
-
-    let num = 20;
-    let num_div2 = (num as f32) / 2.;
-
-    for z in 0..num {
-        let z_f32 = z as f32;
-        for x in 0..num {
-            let x_f32 = x as f32;
-            let position = vec3 {
-                x: x_f32 - num_div2,
-                y: 0.,
-                z: z_f32 - num_div2,
-            };
-
-            let transform = Transform { position, rotation };
+#[cfg(test)]
+mod tests {
+    use crate::*;
 
-            let colour = (x_f32 + z_f32) % 2.;
+    #[test]
+    fn test_help_pages_fit_default_window() {
+        let (_, height) = Args::parse_from(["threedengine"]).resolution;
+        // The bottom row is kept for status messages
+        let rows = height / TEXT_HEIGHT as usize - 1;
 
-            let mut albedo = Colour::new(255, 255, 255);
-            if colour == 0. {
-            } else {
-                albedo.r = 0;
-                albedo.g = 0;
-                albedo.b = 0;
-            }
-            let obj =
-                Object::create_from_file("cube".to_string(), model_path.clone(), transform, albedo)
-                    .unwrap();
-            objs.push(obj)
+        for page in HELP_PAGES {
+            assert!(page.len() <= rows, "{} lines of help", page.len());
         }
     }
-
-    objs
-}
-// let transform = Transform { position, rotation };
-// // Object::create_from_file("cube".to_string(), path.to_string(), transform, albedo).unwrap()
-
-fn init_cube() -> Object {
-    let model_path = model_path("cube.obj".to_string());
-
-    let position = vec3 {
-        x: 3.,
-        y: 3.,
-        z: 3.,
-    };
-    let rotation = vec3 {
-        x: 45.,
-        y: 45.,
-        z: 45.,
-    };
-    let transform = Transform { position, rotation };
-    let albedo = Colour::new(42, 170, 255);
-    // Object::create_from_file("cube".to_string(), path.to_string(), transform, albedo).unwrap()
-    Object::create_from_file("cube".to_string(), model_path, transform, albedo).unwrap()
-}
-
-fn init_teapot(x: f32, y: f32, z: f32) -> Object {
-    let model_path = model_path("teapot.obj".to_string());
-
-    let position = vec3 { x, y, z };
-    let rotation = vec3 {
-        x: 0.,
-        y: 0.,
-        z: 0.,
-    };
-    let transform = Transform { position, rotation };
-    let albedo = Colour::new(1, 204, 3);
-    Object::create_from_file("teapot".to_string(), model_path, transform, albedo).unwrap()
-}
-
-fn _init_spaceship(x: f32, y: f32, z: f32) -> Object {
-    let model_path = model_path("spaceship.obj".to_string());
-
-    let position = vec3 { x, y, z };
-    let rotation = vec3 {
-        x: 0.,
-        y: 0.,
-        z: 0.,
-    };
-    let transform = Transform { position, rotation };
-    let albedo = Colour::new(1, 204, 3);
-    Object::create_from_file("teapot".to_string(), model_path, transform, albedo).unwrap()
-}
-
-fn process_tri(
-    core: &Core,
-    tri: &Tri,
-    rot_z_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    rot_y_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    rot_x_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    trans_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    albedo: Colour,
-) -> Option<(raster::Tri, Vert, Colour)> {
-    let mut tri = transform_tri(tri, rot_z_mat, rot_y_mat, rot_x_mat, trans_mat);
-
-    let normal = normal(&tri);
-
-    if normal.z <= 0. {
-        tri.v1 = mult_vec3_mat4(tri.v1, &core.view_mat);
-        tri.v1 = mult_vec3_mat4(tri.v1, &core.proj_mat);
-
-        tri.v2 = mult_vec3_mat4(tri.v2, &core.view_mat);
-        tri.v2 = mult_vec3_mat4(tri.v2, &core.proj_mat);
-
-        tri.v3 = mult_vec3_mat4(tri.v3, &core.view_mat);
-        tri.v3 = mult_vec3_mat4(tri.v3, &core.proj_mat);
-
-        tri.v1.x += 1.;
-        tri.v1.x *= 0.5 * (WIDTH as f32);
-        tri.v1.y += 1.;
-        tri.v1.y *= 0.5 * (HEIGHT as f32);
-        tri.v1.z += 1.;
-        tri.v1.z *= 0.5;
-
-        tri.v2.x += 1.;
-        tri.v2.x *= 0.5 * (WIDTH as f32);
-        tri.v2.y += 1.;
-        tri.v2.y *= 0.5 * (HEIGHT as f32);
-        tri.v2.z += 1.;
-        tri.v2.z *= 0.5;
-
-        tri.v3.x += 1.;
-        tri.v3.x *= 0.5 * (WIDTH as f32);
-        tri.v3.y += 1.;
-        tri.v3.y *= 0.5 * (HEIGHT as f32);
-        tri.v3.z += 1.;
-        tri.v3.z *= 0.5;
-
-        let p1 = Point {
-            x: tri.v1.x.round() as u32,
-            y: tri.v1.y.round() as u32,
-            z: tri.v1.z,
-        };
-
-        let p2 = Point {
-            x: tri.v2.x.round() as u32,
-            y: tri.v2.y.round() as u32,
-            z: tri.v2.z,
-        };
-
-        let p3 = Point {
-            x: tri.v3.x.round() as u32,
-            y: tri.v3.y.round() as u32,
-            z: tri.v3.z,
-        };
-
-        Some((raster::Tri { p1, p2, p3 }, normal, albedo))
-    } else {
-        None
-    }
-}
-
-fn transform_tri(
-    tri: &Tri,
-    rot_z_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    rot_y_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    rot_x_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-    trans_mat: &ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>>,
-) -> Tri {
-    let mut v1 = mult_vec3_mat4(tri.v1, rot_z_mat);
-    v1 = mult_vec3_mat4(v1, rot_y_mat);
-    v1 = mult_vec3_mat4(v1, rot_x_mat);
-    v1 = mult_vec3_mat4(v1, trans_mat);
-    let mut v2 = mult_vec3_mat4(tri.v2, rot_z_mat);
-    v2 = mult_vec3_mat4(v2, rot_y_mat);
-    v2 = mult_vec3_mat4(v2, rot_x_mat);
-    v2 = mult_vec3_mat4(v2, trans_mat);
-    let mut v3 = mult_vec3_mat4(tri.v3, rot_z_mat);
-    v3 = mult_vec3_mat4(v3, rot_y_mat);
-    v3 = mult_vec3_mat4(v3, rot_x_mat);
-    v3 = mult_vec3_mat4(v3, trans_mat);
-    Tri { v1, v2, v3 }
 }
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::bounds::{Aabb, Sphere};
+use crate::bvh::{Bvh, Hit, Ray};
+use crate::threed::*;
+
+/// Corner of an OBJ face, 0-based indices of the position and optional texture coordinate and normal
+type FaceCorner = (usize, Option<usize>, Option<usize>);
+
+/// An indexed triangle mesh.
+/// Every unique vertex is stored once in `verts` and each triangle refers to its three corners
+/// by index into that buffer, so a vertex shared by several triangles only needs transforming once.
+/// `normals` and `uvs` are optional per-vertex attributes, they are either empty or the same length as `verts`.
+/// The bounding volumes and triangle hierarchy are in model space and are built once when the mesh is created.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub verts: Vec<Vert>,
+    pub normals: Vec<Vert>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<[usize; 3]>,
+    /// For each vertex the first one at the same position, so vertices split at texture or
+    /// normal seams can be told apart from ones that are really separate
+    pub welded: Vec<usize>,
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+    pub bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(verts: Vec<Vert>, indices: Vec<[usize; 3]>) -> Self {
+        let aabb = Aabb::from_verts(&verts);
+        let sphere = Sphere::from_verts(&verts);
+
+        let tri_aabbs: Vec<Aabb> = indices
+            .iter()
+            .map(|[i1, i2, i3]| Aabb::from_verts(&[verts[*i1], verts[*i2], verts[*i3]]))
+            .collect();
+        let bvh = Bvh::build(&tri_aabbs);
+
+        let mut first_at: HashMap<[u32; 3], usize> = HashMap::new();
+        let welded = verts
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                *first_at
+                    .entry([v.x.to_bits(), v.y.to_bits(), v.z.to_bits()])
+                    .or_insert(i)
+            })
+            .collect();
+
+        Self {
+            verts,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices,
+            welded,
+            aabb,
+            sphere,
+            bvh,
+        }
+    }
+
+    pub fn with_attributes(mut self, normals: Vec<Vert>, uvs: Vec<[f32; 2]>) -> Self {
+        assert!(normals.is_empty() || normals.len() == self.verts.len());
+        assert!(uvs.is_empty() || uvs.len() == self.verts.len());
+        self.normals = normals;
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn from_obj_file(obj_path: &str) -> Result<Mesh, io::Error> {
+        let content = fs::read_to_string(obj_path)?;
+        Mesh::from_obj_str(&content)
+    }
+
+    /// Build a mesh from the contents of an OBJ file.
+    /// Only `v`, `vt`, `vn` and `f` lines are understood, everything else is ignored.
+    /// Faces with more than three corners are split into a fan of triangles.
+    /// OBJ indexes positions, texture coordinates and normals separately, so when a face uses
+    /// texture coordinates or normals a vertex is made for each unique combination.
+    /// Malformed numbers and out of range indices are errors, naming the line they are on
+    pub fn from_obj_str(content: &str) -> Result<Mesh, io::Error> {
+        let mut positions: Vec<Vert> = Vec::new();
+        let mut obj_normals: Vec<Vert> = Vec::new();
+        let mut obj_uvs: Vec<[f32; 2]> = Vec::new();
+        let mut faces: Vec<Vec<FaceCorner>> = Vec::new();
+
+        for (line_i, line) in content.lines().enumerate() {
+            let invalid = |msg: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {msg}", line_i + 1),
+                )
+            };
+
+            let chunks: Vec<&str> = line.split_whitespace().collect();
+            match chunks.first() {
+                Some(&"v") => {
+                    let [x, y, z] = parse_floats(&chunks, line).map_err(invalid)?;
+                    positions.push(Vert { x, y, z });
+                }
+                Some(&"vn") => {
+                    let [x, y, z] = parse_floats(&chunks, line).map_err(invalid)?;
+                    obj_normals.push(Vert { x, y, z });
+                }
+                Some(&"vt") => obj_uvs.push(parse_floats(&chunks, line).map_err(invalid)?),
+                Some(&"f") => faces.push(
+                    Mesh::face_from_chunks(&chunks, &positions, &obj_uvs, &obj_normals)
+                        .map_err(invalid)?,
+                ),
+                _ => (),
+            }
+        }
+
+        let has_uvs = faces.iter().flatten().any(|corner| corner.1.is_some());
+        let has_normals = faces.iter().flatten().any(|corner| corner.2.is_some());
+
+        if !has_uvs && !has_normals {
+            // Positions only, so they can be used as the vertex buffer directly
+            let mut indices = Vec::new();
+            for face in faces {
+                for i in 1..face.len() - 1 {
+                    indices.push([face[0].0, face[i].0, face[i + 1].0]);
+                }
+            }
+            return Ok(Mesh::new(positions, indices));
+        }
+
+        let mut unique: HashMap<FaceCorner, usize> = HashMap::new();
+        let mut verts = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut corner_index = |corner: FaceCorner| {
+            *unique.entry(corner).or_insert_with(|| {
+                verts.push(positions[corner.0]);
+                if has_normals {
+                    normals.push(corner.2.map_or(Vert::default(), |n| obj_normals[n]));
+                }
+                if has_uvs {
+                    uvs.push(corner.1.map_or([0., 0.], |t| obj_uvs[t]));
+                }
+                verts.len() - 1
+            })
+        };
+
+        let mut indices = Vec::new();
+        for face in faces {
+            for i in 1..face.len() - 1 {
+                indices.push([
+                    corner_index(face[0]),
+                    corner_index(face[i]),
+                    corner_index(face[i + 1]),
+                ]);
+            }
+        }
+
+        Ok(Mesh::new(verts, indices).with_attributes(normals, uvs))
+    }
+
+    /// The corners of an `f` line, indices are checked against what has been read so far
+    fn face_from_chunks(
+        chunks: &[&str],
+        positions: &[Vert],
+        uvs: &[[f32; 2]],
+        normals: &[Vert],
+    ) -> Result<Vec<FaceCorner>, String> {
+        if chunks.len() < 4 {
+            return Err("a face needs at least three corners".to_string());
+        }
+
+        // Empty means not given, as in 1//3. Negative indices count back from the latest element
+        let resolve = |index: &str, len: usize| -> Result<Option<usize>, String> {
+            if index.is_empty() {
+                return Ok(None);
+            }
+            let index: i64 = index
+                .parse()
+                .map_err(|_| format!("bad face index {index}"))?;
+            let resolved = if index < 0 {
+                len as i64 + index
+            } else {
+                index - 1
+            };
+            if resolved < 0 || resolved >= len as i64 {
+                return Err(format!("face index {index} out of range"));
+            }
+            Ok(Some(resolved as usize))
+        };
+
+        chunks[1..]
+            .iter()
+            .map(|chunk| {
+                let mut parts = chunk.split('/');
+                let v = resolve(parts.next().unwrap_or_default(), positions.len())?
+                    .ok_or_else(|| format!("face corner {chunk} has no position"))?;
+                let vt = match parts.next() {
+                    Some(vt) => resolve(vt, uvs.len())?,
+                    None => None,
+                };
+                let vn = match parts.next() {
+                    Some(vn) => resolve(vn, normals.len())?,
+                    None => None,
+                };
+                Ok((v, vt, vn))
+            })
+            .collect()
+    }
+
+    /// Write the mesh out in OBJ format, including any normals and texture coordinates
+    pub fn to_obj_string(&self) -> String {
+        let mut obj = String::new();
+        let num_tris = self.indices.len();
+        obj.push_str(&format!("# threedengine mesh, {num_tris} triangles\n"));
+
+        for v in &self.verts {
+            obj.push_str(&format!("v {:.6} {:.6} {:.6}\n", v.x, v.y, v.z));
+        }
+        for uv in &self.uvs {
+            obj.push_str(&format!("vt {:.6} {:.6}\n", uv[0], uv[1]));
+        }
+        for n in &self.normals {
+            obj.push_str(&format!("vn {:.6} {:.6} {:.6}\n", n.x, n.y, n.z));
+        }
+
+        let has_uvs = !self.uvs.is_empty();
+        let has_normals = !self.normals.is_empty();
+        for tri in &self.indices {
+            obj.push('f');
+            for i in tri {
+                let i = i + 1;
+                let corner = match (has_uvs, has_normals) {
+                    (false, false) => format!(" {i}"),
+                    (true, false) => format!(" {i}/{i}"),
+                    (false, true) => format!(" {i}//{i}"),
+                    (true, true) => format!(" {i}/{i}/{i}"),
+                };
+                obj.push_str(&corner);
+            }
+            obj.push('\n');
+        }
+
+        obj
+    }
+
+    pub fn write_obj_file(&self, obj_path: &str) -> Result<(), io::Error> {
+        fs::write(obj_path, self.to_obj_string())
+    }
+
+    /// Assemble triangle `i` from the vertex buffer
+    pub fn tri(&self, i: usize) -> Tri {
+        let [i1, i2, i3] = self.indices[i];
+        Tri {
+            v1: self.verts[i1],
+            v2: self.verts[i2],
+            v3: self.verts[i3],
+        }
+    }
+
+    /// Closest triangle hit by a model space ray
+    pub fn intersect_ray_nearest(&self, ray: &Ray) -> Option<Hit> {
+        self.bvh
+            .intersect_ray_nearest(ray, f32::MAX, |i| ray.intersect_tri(&self.tri(i)))
+    }
+
+    /// Any triangle hit by a model space ray closer than `max_t`
+    pub fn intersect_ray_any(&self, ray: &Ray, max_t: f32) -> Option<Hit> {
+        self.bvh
+            .intersect_ray_any(ray, max_t, |i| ray.intersect_tri(&self.tri(i)))
+    }
+}
+
+/// The first N numbers after the keyword of an OBJ line, anything after them (such as w) is ignored
+fn parse_floats<const N: usize>(chunks: &[&str], line: &str) -> Result<[f32; N], String> {
+    let mut values = [0.; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = chunks
+            .get(i + 1)
+            .and_then(|chunk| chunk.parse().ok())
+            .ok_or_else(|| format!("expected {N} numbers in {line:?}"))?;
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::*;
+
+    #[test]
+    fn test_from_obj_str_shares_verts() {
+        let obj = "# quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n";
+
+        let mesh = Mesh::from_obj_str(obj).unwrap();
+
+        assert_eq!(4, mesh.verts.len());
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3]], mesh.indices);
+    }
+
+    #[test]
+    fn test_from_obj_str_with_attributes() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+                   f 1/1/1 2/2/1 3/3/1 4/4/1\n";
+
+        let mesh = Mesh::from_obj_str(obj).unwrap();
+
+        assert_eq!(4, mesh.verts.len());
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3]], mesh.indices);
+        assert_eq!(vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]], mesh.uvs);
+        assert_eq!(4, mesh.normals.len());
+    }
+
+    #[test]
+    fn test_from_obj_str_splits_seams() {
+        // The shared edge uses different texture coordinates either side, so its vertices are duplicated
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\n\
+                   f 1/1 2/1 3/1\nf 1/2 3/2 4/2\n";
+
+        let mesh = Mesh::from_obj_str(obj).unwrap();
+
+        assert_eq!(6, mesh.verts.len());
+        assert_eq!(2, mesh.indices.len());
+        // Both copies of the shared corners weld back to the first
+        let [a, _, b] = mesh.indices[0];
+        let [c, d, _] = mesh.indices[1];
+        assert_eq!(
+            (mesh.welded[a], mesh.welded[b]),
+            (mesh.welded[c], mesh.welded[d])
+        );
+        assert_ne!((a, b), (c, d));
+    }
+
+    #[test]
+    fn test_obj_round_trip() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   f 1/1 2/2 3/3\nf 1/1 3/3 4/4\n";
+        let mesh = Mesh::from_obj_str(obj).unwrap();
+
+        let result = Mesh::from_obj_str(&mesh.to_obj_string()).unwrap();
+
+        assert_eq!(mesh.verts, result.verts);
+        assert_eq!(mesh.uvs, result.uvs);
+        assert_eq!(mesh.indices, result.indices);
+    }
+
+    #[test]
+    fn test_from_obj_str_errors() {
+        let bad_number = "v 0 0 0\nv 1 x 0\n";
+        let out_of_range = "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n";
+
+        let err = Mesh::from_obj_str(bad_number).unwrap_err();
+        assert!(err.to_string().starts_with("line 2"));
+        assert!(Mesh::from_obj_str(out_of_range).is_err());
+    }
+
+    #[test]
+    fn test_load_cube() {
+        let mesh = Mesh::from_obj_file("Resource/Models/cube.obj").unwrap();
+
+        assert_eq!(8, mesh.verts.len());
+        assert_eq!(12, mesh.indices.len());
+        assert_eq!(
+            Vert {
+                x: -1.,
+                y: -1.,
+                z: -1.
+            },
+            mesh.aabb.min
+        );
+        assert_eq!(
+            Vert {
+                x: 1.,
+                y: 1.,
+                z: 1.
+            },
+            mesh.aabb.max
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! Screen space effects run over the finished frame, after rasterising and before it is presented
+//! or saved. Effects are registered in a `PostChain` by name and switched on and off there.
+
+use rayon::prelude::*;
+
+use crate::renderer::FrameBuffer;
+
+/// A full screen effect. Effects only see the colour buffer as it was left by the ones before
+pub trait PostEffect: Send {
+    /// Used to switch the effect on and off, and in scene files
+    fn name(&self) -> &str;
+    fn apply(&mut self, framebuffer: &mut FrameBuffer);
+}
+
+struct PostEntry {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+/// Effects run one after another in the order they were added, skipping any that are off
+#[derive(Default)]
+pub struct PostChain {
+    effects: Vec<PostEntry>,
+}
+
+impl PostChain {
+    /// The effects the renderer comes with, all off
+    pub fn standard() -> Self {
+        let mut chain = PostChain::default();
+        chain.add(Box::new(Fxaa::default()));
+        chain
+    }
+
+    /// Add an effect to the end of the chain, switched off
+    pub fn add(&mut self, effect: Box<dyn PostEffect>) {
+        self.effects.push(PostEntry {
+            effect,
+            enabled: false,
+        });
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.effects
+            .iter()
+            .map(|e| e.effect.name().to_string())
+            .collect()
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.effects
+            .iter()
+            .any(|e| e.enabled && e.effect.name() == name)
+    }
+
+    /// Returns false if there is no effect of that name
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.effects.iter_mut().find(|e| e.effect.name() == name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Flip an effect on or off, giving its new state. `None` if there is no effect of that name
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let enabled = !self.is_enabled(name);
+        self.set_enabled(name, enabled).then_some(enabled)
+    }
+
+    /// Names of the effects that are on, in chain order
+    pub fn enabled(&self) -> Vec<String> {
+        self.effects
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| e.effect.name().to_string())
+            .collect()
+    }
+
+    /// Switch on exactly the named effects. Names with no effect are ignored
+    pub fn set_enabled_only(&mut self, names: &[String]) {
+        for entry in &mut self.effects {
+            entry.enabled = names.iter().any(|n| n == entry.effect.name());
+        }
+    }
+
+    pub fn apply(&mut self, framebuffer: &mut FrameBuffer) {
+        for entry in &mut self.effects {
+            if entry.enabled {
+                entry.effect.apply(framebuffer);
+            }
+        }
+    }
+}
+
+/// Pixels searched along an edge in each direction to find where it ends
+const FXAA_SEARCH_STEPS: isize = 12;
+
+/// Fast approximate anti-aliasing. Looks for sharp changes in brightness, works out which way
+/// each edge runs and how far along it the pixel is, then blends the pixel with its neighbour
+/// across the edge by how much the edge would have covered it. Much cheaper than supersampling,
+/// but it can't recover detail smaller than a pixel and it softens text and thin lines
+pub struct Fxaa {
+    /// Smallest brightness change, relative to the brightest pixel nearby, that counts as an edge
+    pub edge_threshold: f32,
+    /// Brightness changes below this are never edges, which keeps dark areas from being blurred
+    pub edge_threshold_min: f32,
+    /// How much lone pixels are smoothed into their neighbours, 0 to 1
+    pub subpixel: f32,
+    /// The frame before filtering and the brightness of each pixel, kept to save reallocating
+    input: Vec<u32>,
+    luma: Vec<f32>,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Fxaa {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+            input: Vec::new(),
+            luma: Vec::new(),
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(&mut self, framebuffer: &mut FrameBuffer) {
+        self.input.clear();
+        self.input.extend_from_slice(&framebuffer.pixels);
+        self.luma.clear();
+        self.luma
+            .extend(framebuffer.pixels.iter().map(|p| luma(*p)));
+
+        let fxaa = &*self;
+        let image = Image {
+            width: framebuffer.width as isize,
+            height: framebuffer.height as isize,
+            pixels: &fxaa.input,
+            luma: &fxaa.luma,
+        };
+        let width = framebuffer.width.max(1);
+        framebuffer
+            .pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = fxaa.filter(&image, x as isize, y as isize);
+                }
+            });
+    }
+}
+
+impl Fxaa {
+    fn filter(&self, image: &Image, x: isize, y: isize) -> u32 {
+        let m = image.luma(x, y);
+        let n = image.luma(x, y - 1);
+        let s = image.luma(x, y + 1);
+        let w = image.luma(x - 1, y);
+        let e = image.luma(x + 1, y);
+
+        let max = m.max(n).max(s).max(w).max(e);
+        let min = m.min(n).min(s).min(w).min(e);
+        let range = max - min;
+        if range < self.edge_threshold_min.max(max * self.edge_threshold) {
+            return image.pixel(x, y);
+        }
+
+        let nw = image.luma(x - 1, y - 1);
+        let ne = image.luma(x + 1, y - 1);
+        let sw = image.luma(x - 1, y + 1);
+        let se = image.luma(x + 1, y + 1);
+
+        // A horizontal edge changes brightness going up and down
+        let change_vertically =
+            2. * (n + s - 2. * m).abs() + (ne + se - 2. * e).abs() + (nw + sw - 2. * w).abs();
+        let change_horizontally =
+            2. * (w + e - 2. * m).abs() + (nw + ne - 2. * n).abs() + (sw + se - 2. * s).abs();
+        let horizontal = change_vertically >= change_horizontally;
+
+        // The edge lies between this pixel and whichever neighbour across it differs the most
+        let (before, after) = if horizontal { (n, s) } else { (w, e) };
+        let (across, neighbour) = if (before - m).abs() >= (after - m).abs() {
+            (-1, before)
+        } else {
+            (1, after)
+        };
+        let gradient = 0.25 * (neighbour - m).abs();
+        let local_average = 0.5 * (m + neighbour);
+
+        let (across_x, across_y, along_x, along_y) = if horizontal {
+            (0, across, 1, 0)
+        } else {
+            (across, 0, 0, 1)
+        };
+        // Brightness half way between the pixel k steps along the edge and its neighbour across it
+        let edge_luma = |k: isize| {
+            let (ex, ey) = (x + along_x * k, y + along_y * k);
+            0.5 * (image.luma(ex, ey) + image.luma(ex + across_x, ey + across_y))
+        };
+        // Walk along the edge until the brightness no longer matches it
+        let search = |direction: isize| {
+            for k in 1..=FXAA_SEARCH_STEPS {
+                let luma = edge_luma(direction * k);
+                if (luma - local_average).abs() >= gradient {
+                    return (k as f32, luma);
+                }
+            }
+            (
+                FXAA_SEARCH_STEPS as f32,
+                edge_luma(direction * FXAA_SEARCH_STEPS),
+            )
+        };
+        let (back, back_luma) = search(-1);
+        let (forward, forward_luma) = search(1);
+
+        // Only the nearer end decides, and only if the edge crosses this pixel's side of it
+        let (distance, end_luma) = if back < forward {
+            (back, back_luma)
+        } else {
+            (forward, forward_luma)
+        };
+        let edge_blend = if (end_luma < local_average) != (m < local_average) {
+            0.5 - distance / (back + forward)
+        } else {
+            0.
+        };
+
+        // Pixels standing out from everything around them get blended whatever the edge says
+        let average = (2. * (n + s + w + e) + nw + ne + sw + se) / 12.;
+        let contrast = ((average - m).abs() / range).clamp(0., 1.);
+        let smooth = (3. - 2. * contrast) * contrast * contrast;
+        let subpixel_blend = smooth * smooth * self.subpixel;
+
+        let blend = edge_blend.max(subpixel_blend);
+        mix(
+            image.pixel(x, y),
+            image.pixel(x + across_x, y + across_y),
+            blend,
+        )
+    }
+}
+
+/// The frame being filtered, reads off the edges repeat the nearest pixel
+struct Image<'a> {
+    width: isize,
+    height: isize,
+    pixels: &'a [u32],
+    luma: &'a [f32],
+}
+
+impl Image<'_> {
+    fn index(&self, x: isize, y: isize) -> usize {
+        let x = x.clamp(0, self.width - 1);
+        let y = y.clamp(0, self.height - 1);
+        (y * self.width + x) as usize
+    }
+
+    fn pixel(&self, x: isize, y: isize) -> u32 {
+        self.pixels[self.index(x, y)]
+    }
+
+    fn luma(&self, x: isize, y: isize) -> f32 {
+        self.luma[self.index(x, y)]
+    }
+}
+
+/// Perceived brightness of a 0RGB colour, 0 to 1
+fn luma(colour: u32) -> f32 {
+    let r = ((colour >> 16) & 0xff) as f32;
+    let g = ((colour >> 8) & 0xff) as f32;
+    let b = (colour & 0xff) as f32;
+    (0.299 * r + 0.587 * g + 0.114 * b) / 255.
+}
+
+/// `t` of the way from `a` to `b`, per channel
+fn mix(a: u32, b: u32, t: f32) -> u32 {
+    let channel = |shift: u32| {
+        let a = ((a >> shift) & 0xff) as f32;
+        let b = ((b >> shift) & 0xff) as f32;
+        ((a + (b - a) * t).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::post::*;
+
+    /// Counts how often it runs
+    struct Counter(usize);
+
+    impl PostEffect for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn apply(&mut self, framebuffer: &mut FrameBuffer) {
+            self.0 += 1;
+            framebuffer.pixels[0] = self.0 as u32;
+        }
+    }
+
+    #[test]
+    fn test_chain_runs_enabled_effects() {
+        let mut chain = PostChain::standard();
+        chain.add(Box::new(Counter(0)));
+        let mut framebuffer = FrameBuffer::new(4, 4);
+
+        chain.apply(&mut framebuffer);
+        assert_eq!(0, framebuffer.pixels[0]);
+
+        assert_eq!(Some(true), chain.toggle("counter"));
+        chain.apply(&mut framebuffer);
+        chain.apply(&mut framebuffer);
+        assert_eq!(2, framebuffer.pixels[0]);
+        assert_eq!(vec!["counter".to_string()], chain.enabled());
+
+        assert_eq!(None, chain.toggle("missing"));
+        chain.set_enabled_only(&["fxaa".to_string()]);
+        assert_eq!(vec!["fxaa".to_string()], chain.enabled());
+    }
+
+    #[test]
+    fn test_fxaa_leaves_flat_areas() {
+        let mut framebuffer = FrameBuffer::new(16, 16);
+        framebuffer.pixels.fill(0x336699);
+        let before = framebuffer.pixels.clone();
+
+        Fxaa::default().apply(&mut framebuffer);
+
+        assert_eq!(before, framebuffer.pixels);
+    }
+
+    #[test]
+    fn test_fxaa_smooths_staircase() {
+        // A shallow black on white staircase, one pixel step every 4 columns
+        let (width, height) = (32, 16);
+        let mut framebuffer = FrameBuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let white = y > 4 + x / 4;
+                framebuffer.pixels[y * width + x] = if white { 0xffffff } else { 0 };
+            }
+        }
+
+        Fxaa::default().apply(&mut framebuffer);
+
+        let blended = framebuffer
+            .pixels
+            .iter()
+            .filter(|p| **p != 0 && **p != 0xffffff)
+            .count();
+        assert!(blended > width / 2, "only {blended} pixels blended");
+        // Well away from the edge nothing changes
+        assert_eq!(0, framebuffer.pixels[0]);
+        assert_eq!(0xffffff, framebuffer.pixels[width * height - 1]);
+    }
+}
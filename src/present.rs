@@ -0,0 +1,147 @@
+use minifb::{Scale, Window, WindowOptions};
+use std::io;
+use std::path::PathBuf;
+
+use crate::export::save_image;
+use crate::renderer::{FrameBuffer, Renderer};
+use crate::scene::Scene;
+
+/// Somewhere for finished frames to go
+pub trait Presenter {
+    fn present(&mut self, framebuffer: &FrameBuffer) -> Result<(), io::Error>;
+}
+
+/// Shows frames in a desktop window, the window is also where the viewer reads its input from.
+/// The window can be resized, and `scale` blows each framebuffer pixel up into a block of screen pixels
+pub struct WindowPresenter {
+    pub window: Window,
+    pub title: String,
+    pub scale: Scale,
+}
+
+impl WindowPresenter {
+    /// `width` and `height` are the framebuffer size, the window is `scale` times bigger
+    pub fn new(title: &str, width: usize, height: usize, scale: Scale) -> Self {
+        WindowPresenter {
+            window: create_window(title, width, height, scale),
+            title: title.to_string(),
+            scale,
+        }
+    }
+
+    /// The framebuffer size that fills the window at the current scale
+    pub fn framebuffer_size(&self) -> (usize, usize) {
+        let (width, height) = self.window.get_size();
+        let factor = scale_factor(self.scale);
+        ((width / factor).max(1), (height / factor).max(1))
+    }
+
+    /// minifb fixes the scale when the window is created, so changing it means a new window
+    /// of the same size
+    pub fn set_scale(&mut self, scale: Scale) {
+        let (width, height) = self.window.get_size();
+        let factor = scale_factor(scale);
+        self.window = create_window(
+            &self.title,
+            (width / factor).max(1),
+            (height / factor).max(1),
+            scale,
+        );
+        self.scale = scale;
+    }
+}
+
+fn create_window(title: &str, width: usize, height: usize, scale: Scale) -> Window {
+    let window = Window::new(
+        title,
+        width,
+        height,
+        WindowOptions {
+            resize: true,
+            scale,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("Unable to create window");
+
+    // Limit to max ~100 fps update rate
+    // window.limit_update_rate(Some(std::time::Duration::from_millis(10)));
+
+    window
+}
+
+/// Screen pixels per framebuffer pixel along each axis
+pub fn scale_factor(scale: Scale) -> usize {
+    match scale {
+        Scale::X2 => 2,
+        Scale::X4 => 4,
+        Scale::X8 => 8,
+        Scale::X16 => 16,
+        Scale::X32 => 32,
+        _ => 1,
+    }
+}
+
+impl Presenter for WindowPresenter {
+    fn present(&mut self, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+        self.window
+            .update_with_buffer(&framebuffer.pixels, framebuffer.width, framebuffer.height)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+/// Writes each frame to a numbered image file in a directory, no display needed.
+/// `extension` picks the image format, see `save_image`
+pub struct ImagePresenter {
+    pub dir: PathBuf,
+    pub extension: String,
+    pub frame: usize,
+}
+
+impl ImagePresenter {
+    pub fn new(dir: &str, extension: &str) -> Result<Self, io::Error> {
+        std::fs::create_dir_all(dir)?;
+        Ok(ImagePresenter {
+            dir: PathBuf::from(dir),
+            extension: extension.to_string(),
+            frame: 0,
+        })
+    }
+}
+
+impl Presenter for ImagePresenter {
+    fn present(&mut self, framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+        let path = self
+            .dir
+            .join(format!("frame_{:04}.{}", self.frame, self.extension));
+        save_image(path.to_str().unwrap(), framebuffer)?;
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+/// Throws frames away, for rendering only to measure it
+pub struct NullPresenter;
+
+impl Presenter for NullPresenter {
+    fn present(&mut self, _framebuffer: &FrameBuffer) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Render and present a fixed number of frames, turning the camera by `yaw_step` degrees
+/// after each one. Used for batch rendering with an `ImagePresenter`
+pub fn render_frames(
+    renderer: &mut Renderer,
+    scene: &mut Scene,
+    presenter: &mut dyn Presenter,
+    frames: usize,
+    yaw_step: f32,
+) -> Result<(), io::Error> {
+    for _ in 0..frames {
+        renderer.render(scene);
+        presenter.present(&renderer.framebuffer)?;
+        scene.camera.yaw += yaw_step;
+    }
+    Ok(())
+}
@@ -1,57 +1,153 @@
 use std::mem;
+use std::ops::Range;
+
+use crate::renderer::FrameBuffer;
+use crate::span;
+
+/// Screen space rectangle of pixels, from its bottom left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub(crate) fn contains(&self, x: i32, y: i32) -> bool {
+        let (x, y) = (x as usize, y as usize);
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Somewhere the raster functions can draw. Coordinates are always whole screen pixels
+/// with y up, anything outside the target's rectangle is clipped. The framebuffer is a
+/// target covering the screen, a tile is a target covering part of it
+pub trait RasterTarget {
+    fn rect(&self) -> Rect;
+    /// Colour and depth for the rectangle, one element per pixel with the top row first
+    fn buffers(&mut self) -> (&mut [u32], &mut [f32]);
+}
+
+impl RasterTarget for FrameBuffer {
+    fn rect(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
 
-use crate::HEIGHT;
-use crate::WIDTH;
+    fn buffers(&mut self) -> (&mut [u32], &mut [f32]) {
+        (&mut self.pixels, &mut self.depth)
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Tri {
     pub p1: Point,
     pub p2: Point,
     pub p3: Point,
 }
 
+/// Screen space position with y up, x and y keep their fractional part for sub-pixel precision
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Point {
-    pub x: u32,
-    pub y: u32,
+    pub x: f32,
+    pub y: f32,
     pub z: f32,
 }
 
-fn two_d_to_1d(x: i32, y: i32) -> usize {
-    (HEIGHT - (y as usize) - 1) * WIDTH + (x as usize)
+impl Tri {
+    /// Rounded to whole pixels the way the scanline rasterizer has always seen them. Points off
+    /// the screen stay where they are, the spans are clipped to the target instead
+    fn snapped(&self) -> Tri {
+        let snap = |p: Point| Point {
+            x: p.x.clamp(-MAX_COORD, MAX_COORD).round(),
+            y: p.y.clamp(-MAX_COORD, MAX_COORD).round(),
+            z: p.z,
+        };
+        Tri {
+            p1: snap(self.p1),
+            p2: snap(self.p2),
+            p3: snap(self.p3),
+        }
+    }
+}
+
+/// Screen space depth across a triangle, z = dzdx * x + dzdy * y + z0.
+/// Depth after the perspective divide varies linearly in screen space so a plane is exact
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DepthPlane {
+    pub(crate) dzdx: f32,
+    pub(crate) dzdy: f32,
+    pub(crate) z0: f32,
+}
+
+impl DepthPlane {
+    fn from_tri(tri: &Tri) -> Self {
+        let (x1, y1, z1) = (tri.p1.x, tri.p1.y, tri.p1.z);
+        let (ax, ay, az) = (tri.p2.x - x1, tri.p2.y - y1, tri.p2.z - z1);
+        let (bx, by, bz) = (tri.p3.x - x1, tri.p3.y - y1, tri.p3.z - z1);
+
+        // Normal of the plane through the three points
+        let nx = ay * bz - az * by;
+        let ny = az * bx - ax * bz;
+        let nz = ax * by - ay * bx;
+
+        if nz == 0. {
+            // Degenerate triangle with no area, any pixels it covers get its nearest depth
+            return DepthPlane {
+                dzdx: 0.,
+                dzdy: 0.,
+                z0: z1.min(tri.p2.z).min(tri.p3.z),
+            };
+        }
+
+        let dzdx = -nx / nz;
+        let dzdy = -ny / nz;
+        DepthPlane {
+            dzdx,
+            dzdy,
+            z0: z1 - dzdx * x1 - dzdy * y1,
+        }
+    }
+
+    pub(crate) fn at(&self, x: f32, y: f32) -> f32 {
+        self.dzdx * x + self.dzdy * y + self.z0
+    }
+}
+
+/// Screen y is up but targets store the top row first
+pub(crate) fn two_d_to_1d(rect: &Rect, x: i32, y: i32) -> usize {
+    (rect.y + rect.height - (y as usize) - 1) * rect.width + (x as usize - rect.x)
 }
 
 /// This is an implementation of Bresenahms fast line drawing routine
-pub fn draw_line(buffer: &mut Vec<u32>, x1: u32, y1: u32, x2: u32, y2: u32, colour: u32) {
+pub fn draw_line<T: RasterTarget>(target: &mut T, x1: u32, y1: u32, x2: u32, y2: u32, colour: u32) {
     let mut x1 = x1 as i32;
     let mut y1 = y1 as i32;
     let x2 = x2 as i32;
     let y2 = y2 as i32;
 
-    let sign_x: i32;
-    let sign_y: i32;
-
-    if x2 > x1 {
-        sign_x = 1;
-    } else {
-        sign_x = -1;
-    }
-
-    if y2 > y1 {
-        sign_y = 1;
-    } else {
-        sign_y = -1;
-    }
+    let sign_x: i32 = if x2 > x1 { 1 } else { -1 };
+    let sign_y: i32 = if y2 > y1 { 1 } else { -1 };
 
     let dx = (x2 - x1).abs();
     let dy = -(y2 - y1).abs();
 
-    let mut err = (dx + dy) as i32;
+    let mut err = dx + dy;
     let mut e2: i32;
 
+    let rect = target.rect();
+    let (pixels, _) = target.buffers();
+
     loop {
-        let index = two_d_to_1d(x1, y1);
-        buffer[index] = colour;
+        // Lines can run off the edges of the target, those pixels are skipped
+        if rect.contains(x1, y1) {
+            pixels[two_d_to_1d(&rect, x1, y1)] = colour;
+        }
 
         if x1 == x2 && y1 == y2 {
             return;
@@ -70,44 +166,39 @@ pub fn draw_line(buffer: &mut Vec<u32>, x1: u32, y1: u32, x2: u32, y2: u32, colo
     }
 }
 
-pub fn draw_horiz_line(buffer: &mut Vec<u32>, x1: u32, x2: u32, y: u32, colour: u32) {
-    if check_bounds(x1, x2, y) {
-        let y = y as usize;
-        let y_offset = (HEIGHT - y - 1) * WIDTH;
-
-        let range;
-        //Note in the above range we must include the final value
-        if x1 > x2 {
-            range = x2 as usize..=x1 as usize;
-        } else {
-            range = x1 as usize..=x2 as usize;
-        }
-
-        // println!("Range: {range:?}");
-
-        // This niaive way also seems to be fastest
-        for i in range {
-            let index = y_offset + i;
-
-            if index < buffer.len() {
-                buffer[y_offset + i] = colour;
-            }
-        }
+/// Draw a horizontal line, only writing the pixels that are nearer than what is already in the depth buffer.
+/// The line is cut down to the part inside the target
+fn draw_horiz_line<T: RasterTarget>(
+    target: &mut T,
+    x1: i32,
+    x2: i32,
+    y: i32,
+    depth: &DepthPlane,
+    colour: u32,
+) {
+    let rect = target.rect();
+    let (from, to) = if x1 > x2 { (x2, x1) } else { (x1, x2) };
+    let from = from.max(rect.x as i32);
+    let to = to.min((rect.x + rect.width) as i32 - 1);
+    if from > to || !rect.contains(from, y) {
+        return;
     }
 
-    //  buffer[range].fill(colour);
-    // for i in &mut buffer[range] {
-    //     *i = colour
-    // }
-
-    // for i in range {
-    //     buffer[i] = colour;
-    // }
-
-    // Trying with map, it seems slower
-    // let _dontcare: _ = (x1..x2)
-    //     .map(|x| buffer[y_offset + (x as usize)] = colour)
-    //     .collect::<()>();
+    let row_start = two_d_to_1d(&rect, from, y);
+    let row_end = row_start + (to - from) as usize + 1;
+    let from = from as usize;
+    let (pixels, depths) = target.buffers();
+
+    // Depth is worked out from the plane for every pixel, so the value doesn't depend on where the span was clipped
+    span::fill_span(
+        &mut pixels[row_start..row_end],
+        &mut depths[row_start..row_end],
+        from,
+        y as usize,
+        0.,
+        depth,
+        colour,
+    );
 }
 
 /// Sort three points p1, p2, p3 such that the output is ordered by decreasing y
@@ -134,10 +225,33 @@ fn sort_points_by_y(p1: Point, p2: Point, p3: Point) -> (Point, Point, Point) {
     (pmax, pmid, pmin)
 }
 
-pub fn draw_outlined_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
-    draw_line(buffer, tri.p1.x, tri.p1.y, tri.p2.x, tri.p2.y, colour);
-    draw_line(buffer, tri.p2.x, tri.p2.y, tri.p3.x, tri.p3.y, colour);
-    draw_line(buffer, tri.p3.x, tri.p3.y, tri.p1.x, tri.p1.y, colour);
+pub fn draw_outlined_triangle<T: RasterTarget>(target: &mut T, tri: &Tri, colour: u32) {
+    let tri = tri.snapped();
+    let (p1, p2, p3) = (tri.p1, tri.p2, tri.p3);
+    draw_line(
+        target,
+        p1.x as u32,
+        p1.y as u32,
+        p2.x as u32,
+        p2.y as u32,
+        colour,
+    );
+    draw_line(
+        target,
+        p2.x as u32,
+        p2.y as u32,
+        p3.x as u32,
+        p3.y as u32,
+        colour,
+    );
+    draw_line(
+        target,
+        p3.x as u32,
+        p3.y as u32,
+        p1.x as u32,
+        p1.y as u32,
+        colour,
+    );
 }
 
 /// Any triangle (p1, p2, p3) can be split into two further triangles, one with a flat bottom
@@ -158,12 +272,15 @@ pub fn draw_outlined_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
 /// /// (0,0)---------------------> +x
 ///
 ///
-pub fn draw_filled_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
+pub fn draw_filled_triangle<T: RasterTarget>(target: &mut T, tri: &Tri, colour: u32) {
     // println!("Drawing triangle: {tri:?}");
 
     // Goal is to calculate p4
     // Then draw the flat topped triangle and flat bottomed triangle
 
+    // This works in whole pixels
+    let tri = &tri.snapped();
+
     // first sort the points so that p1.y > p2.y > p3.y
 
     let sorted_points = sort_points_by_y(tri.p1, tri.p2, tri.p3);
@@ -174,18 +291,16 @@ pub fn draw_filled_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
     // And we can calculate the gradient p3-->p4
     // Then we can solve for p4.x
 
-    let p4y = sorted_points.1.y;
-
-    let num = (sorted_points.0.y as f32) - (sorted_points.2.y as f32);
-    let denom = (sorted_points.0.x as f32) - (sorted_points.2.x as f32);
+    let p4y = sorted_points.1.y as i32;
 
-    let p4x;
+    let num = sorted_points.0.y - sorted_points.2.y;
+    let denom = sorted_points.0.x - sorted_points.2.x;
 
-    if denom == 0. {
+    let p4x = if denom == 0. {
         //The top and one of the bottom two points are in a vertical line, so the gradient is infinite
         //p4x has the same x value as the top point
 
-        p4x = sorted_points.0.x;
+        sorted_points.0.x as i32
     } else {
         let gradient_p3_p1 = num / denom;
 
@@ -193,15 +308,258 @@ pub fn draw_filled_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
         // c = y - mx
 
         // Can use either p1 or p3 to calculate c, pick p1 for no good reason
-        let c = (sorted_points.0.y as f32) - gradient_p3_p1 * (sorted_points.0.x as f32);
+        let c = sorted_points.0.y - gradient_p3_p1 * sorted_points.0.x;
 
         // x = (y -c)/m
 
-        p4x = (((p4y as f32) - c) / gradient_p3_p1).round() as u32;
+        (((p4y as f32) - c) / gradient_p3_p1).round() as i32
+    };
+
+    let depth = DepthPlane::from_tri(tri);
+
+    draw_flat_bottom_triangle(
+        target,
+        sorted_points.0,
+        sorted_points.1.x as i32,
+        p4x,
+        p4y,
+        &depth,
+        colour,
+    );
+    draw_flat_top_triangle(
+        target,
+        sorted_points.2,
+        sorted_points.1.x as i32,
+        p4x,
+        p4y,
+        &depth,
+        colour,
+    );
+}
+
+/// Bits of sub-pixel precision in the edge function rasterizer's fixed-point coordinates
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+/// Coordinates are clamped to this many pixels either side of the origin, which keeps every
+/// edge function product comfortably inside an i64
+const MAX_COORD: f32 = (1 << 20) as f32;
+
+fn to_fixed(v: f32) -> i64 {
+    (v.clamp(-MAX_COORD, MAX_COORD) * SUBPIXEL_ONE as f32).round() as i64
+}
+
+/// One edge of a counter-clockwise triangle as a half-space test. The edge function is twice the
+/// signed area of the triangle formed by the edge and a point, positive on the inside
+struct Edge {
+    /// Change in the edge function for a step of one pixel in x and in y
+    step_x: i64,
+    step_y: i64,
+    /// Value at the first pixel's sample point, with the fill rule bias already applied
+    start: i64,
+}
+
+impl Edge {
+    fn new(a: (i64, i64), b: (i64, i64), first_sample: (i64, i64)) -> Self {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let value = dx * (first_sample.1 - a.1) - dy * (first_sample.0 - a.0);
+
+        // Top-left fill rule: pixel centres exactly on an edge belong to the triangle only if it
+        // is a top edge (horizontal with the inside below) or a left edge (the inside to its right).
+        // With y up and counter-clockwise winding those are the edges heading left or down.
+        // Two triangles sharing an edge see it going opposite ways, so exactly one of them
+        // owns the pixels on it
+        let top_left = dy < 0 || (dy == 0 && dx < 0);
+        let bias = if top_left { 0 } else { -1 };
+
+        Edge {
+            step_x: -dy * SUBPIXEL_ONE,
+            step_y: dx * SUBPIXEL_ONE,
+            start: value + bias,
+        }
     }
+}
+
+/// Fill a triangle by testing each pixel centre in its bounding box against the three edges,
+/// in fixed point with `SUBPIXEL_BITS` of sub-pixel precision. Triangles sharing an edge never
+/// leave a gap or both draw the same pixel between them, and vertices don't snap to whole
+/// pixels so slow camera moves don't jitter
+pub fn draw_filled_triangle_edge<T: RasterTarget>(target: &mut T, tri: &Tri, colour: u32) {
+    draw_filled_triangle_at(target, tri, colour, (0.5, 0.5));
+}
+
+/// As `draw_filled_triangle_edge`, testing the point `sample` from the bottom left of each pixel
+/// instead of its centre. Each coordinate is in [0, 1)
+pub fn draw_filled_triangle_at<T: RasterTarget>(
+    target: &mut T,
+    tri: &Tri,
+    colour: u32,
+    sample: (f32, f32),
+) {
+    let rect = target.rect();
+    let Some(EdgeBounds {
+        verts: [v0, v1, v2],
+        min_x,
+        max_x,
+        min_y,
+        max_y,
+    }) = edge_bounds(tri, &rect)
+    else {
+        return;
+    };
 
-    draw_flat_bottom_triangle(buffer, sorted_points.0, sorted_points.1.x, p4x, p4y, colour);
-    draw_flat_top_triangle(buffer, sorted_points.2, sorted_points.1.x, p4x, p4y, colour);
+    let first_sample = (
+        min_x * SUBPIXEL_ONE + to_fixed(sample.0),
+        min_y * SUBPIXEL_ONE + to_fixed(sample.1),
+    );
+    let edges = [
+        Edge::new(v0, v1, first_sample),
+        Edge::new(v1, v2, first_sample),
+        Edge::new(v2, v0, first_sample),
+    ];
+
+    let depth = DepthPlane::from_tri(tri);
+    let (pixels, depths) = target.buffers();
+
+    let mut row = edges.each_ref().map(|e| e.start);
+    for y in min_y..=max_y {
+        let row_start = two_d_to_1d(&rect, min_x as i32, y as i32);
+        let row_end = row_start + (max_x - min_x) as usize + 1;
+        span::fill_edge_span(
+            &mut pixels[row_start..row_end],
+            &mut depths[row_start..row_end],
+            min_x as usize,
+            y as usize,
+            sample,
+            row,
+            edges.each_ref().map(|e| e.step_x),
+            &depth,
+            colour,
+        );
+        for (w, edge) in row.iter_mut().zip(&edges) {
+            *w += edge.step_y;
+        }
+    }
+}
+
+/// Multisample a triangle into `planes`, which hold one sample position from `samples` each.
+/// The triangle is walked once, testing all of a pixel's samples against its edges to get the
+/// samples it covers, then its colour is written to each covered sample that passes that
+/// sample's depth test
+pub fn draw_filled_triangle_multisample<T: RasterTarget>(
+    planes: &mut [T],
+    tri: &Tri,
+    colour: u32,
+    samples: &[(f32, f32)],
+) {
+    let Some(rect) = planes.first().map(|plane| plane.rect()) else {
+        return;
+    };
+    let Some(EdgeBounds {
+        verts: [v0, v1, v2],
+        min_x,
+        max_x,
+        min_y,
+        max_y,
+    }) = edge_bounds(tri, &rect)
+    else {
+        return;
+    };
+
+    // Edge values are kept at each pixel's bottom left corner, each sample is a fixed offset
+    // from there. The steps are whole multiples of a pixel so the offsets are exact
+    let corner = (min_x * SUBPIXEL_ONE, min_y * SUBPIXEL_ONE);
+    let edges = [
+        Edge::new(v0, v1, corner),
+        Edge::new(v1, v2, corner),
+        Edge::new(v2, v0, corner),
+    ];
+    let offsets: Vec<[i64; 3]> = samples
+        .iter()
+        .map(|sample| {
+            let (x, y) = (to_fixed(sample.0), to_fixed(sample.1));
+            edges
+                .each_ref()
+                .map(|e| (e.step_x * x + e.step_y * y) >> SUBPIXEL_BITS)
+        })
+        .collect();
+
+    let depth = DepthPlane::from_tri(tri);
+    let mut buffers: Vec<_> = planes.iter_mut().map(|plane| plane.buffers()).collect();
+
+    let mut row = edges.each_ref().map(|e| e.start);
+    for y in min_y..=max_y {
+        let mut w = row;
+        let row_start = two_d_to_1d(&rect, min_x as i32, y as i32);
+        for (index, x) in (row_start..).zip(min_x..=max_x) {
+            let mut covered = 0u32;
+            for (s, offset) in offsets.iter().enumerate() {
+                if w[0] + offset[0] >= 0 && w[1] + offset[1] >= 0 && w[2] + offset[2] >= 0 {
+                    covered |= 1 << s;
+                }
+            }
+
+            if covered != 0 {
+                for (s, ((pixels, depths), sample)) in buffers.iter_mut().zip(samples).enumerate() {
+                    if covered & 1 << s == 0 {
+                        continue;
+                    }
+                    let z = depth.at(x as f32 + sample.0, y as f32 + sample.1);
+                    if z <= depths[index] {
+                        pixels[index] = colour;
+                        depths[index] = z;
+                    }
+                }
+            }
+
+            for (w, edge) in w.iter_mut().zip(&edges) {
+                *w += edge.step_x;
+            }
+        }
+        for (w, edge) in row.iter_mut().zip(&edges) {
+            *w += edge.step_y;
+        }
+    }
+}
+
+/// A triangle ready for the edge function rasterizers
+struct EdgeBounds {
+    /// Corners in fixed point, wound counter-clockwise
+    verts: [(i64, i64); 3],
+    /// The pixels whose samples could be inside, inclusive
+    min_x: i64,
+    max_x: i64,
+    min_y: i64,
+    max_y: i64,
+}
+
+/// The triangle's corners and the pixels it could cover, cut down to `rect`. `None` if the
+/// triangle has no area or misses the rectangle
+fn edge_bounds(tri: &Tri, rect: &Rect) -> Option<EdgeBounds> {
+    let fixed = |p: Point| (to_fixed(p.x), to_fixed(p.y));
+    let (v0, mut v1, mut v2) = (fixed(tri.p1), fixed(tri.p2), fixed(tri.p3));
+
+    let area = (v1.0 - v0.0) * (v2.1 - v0.1) - (v1.1 - v0.1) * (v2.0 - v0.0);
+    if area == 0 {
+        return None;
+    }
+    if area < 0 {
+        mem::swap(&mut v1, &mut v2);
+    }
+
+    let min_x = (v0.0.min(v1.0).min(v2.0) >> SUBPIXEL_BITS).max(rect.x as i64);
+    let max_x = (v0.0.max(v1.0).max(v2.0) >> SUBPIXEL_BITS).min((rect.x + rect.width) as i64 - 1);
+    let min_y = (v0.1.min(v1.1).min(v2.1) >> SUBPIXEL_BITS).max(rect.y as i64);
+    let max_y = (v0.1.max(v1.1).max(v2.1) >> SUBPIXEL_BITS).min((rect.y + rect.height) as i64 - 1);
+    if min_x > max_x || min_y > max_y {
+        return None;
+    }
+    Some(EdgeBounds {
+        verts: [v0, v1, v2],
+        min_x,
+        max_x,
+        min_y,
+        max_y,
+    })
 }
 
 /// Draw a filled flat bottomed triangle by starting at the bottom
@@ -216,45 +574,50 @@ pub fn draw_filled_triangle(buffer: &mut Vec<u32>, tri: &Tri, colour: u32) {
 ///   |       p2-------p3
 /// (0,0)---------------------> +x
 ///
-fn draw_flat_bottom_triangle(
-    buffer: &mut Vec<u32>,
+fn draw_flat_bottom_triangle<T: RasterTarget>(
+    target: &mut T,
     p1: Point,
-    p2x: u32,
-    p3x: u32,
-    p23y: u32,
+    p2x: i32,
+    p3x: i32,
+    p23y: i32,
+    depth: &DepthPlane,
     colour: u32,
 ) {
     // println!("Drawing flat bottom triangle: p1:{p1:?}, p2x:{p2x}, p3x:{p3x}, p23y:{p23y}");
 
     // First calculate the inverse gradient of line p2 --> p1
     // Recall the gradient is Δy/Δx
-    let mut num = (p2x as f32) - (p1.x as f32);
-    let mut denom = (p23y as f32) - (p1.y as f32);
+    let mut num = (p2x as f32) - p1.x;
+    let mut denom = (p23y as f32) - p1.y;
     let inverse_gradient_p2_p1 = num / denom;
     // The inverse gradient is more convenient when later calculating the horizontal line length
 
     // Then calculate the gradient of line p1 --> p3
-    num = (p1.x as f32) - (p3x as f32);
-    denom = (p1.y as f32) - (p23y as f32);
+    num = p1.x - (p3x as f32);
+    denom = p1.y - (p23y as f32);
     let inverse_gradient_p1_p3 = num / denom;
 
-    // The starting point is the bottom two points, p2 and p3
-    let mut from = p2x as f32;
-    let mut to = p3x as f32;
-
     // We know the triangle is flat bottom, so p1.y > p23y
-    // Create the range of y values from p23y --> p1.y
-    let range = (p23y)..(p1.y);
+    // Create the range of y values from p23y --> p1.y, less any rows outside the target
+    let range = clip_rows(target, p23y, p1.y as i32);
 
     // Loop over this range
     for y in range {
-        // Drawing a horizontal line
-        draw_horiz_line(buffer, from.round() as u32, to.round() as u32, y, colour);
+        // The line starts at the bottom two points, p2 and p3, and every row up it gets a bit
+        // shorter as gradient_p2_p1 and gradient_p1_p3 are guaranteed to be opposite directions
+        let rows = (y - p23y) as f32;
+        let from = p2x as f32 + inverse_gradient_p2_p1 * rows;
+        let to = p3x as f32 + inverse_gradient_p1_p3 * rows;
 
-        // Every iteration the horizontal line will get a bit shorter
-        // as gradient_p2_p1 and gradient_p1_p3 are guaranteed to be opposite directions
-        from += inverse_gradient_p2_p1;
-        to += inverse_gradient_p1_p3;
+        // Drawing a horizontal line
+        draw_horiz_line(
+            target,
+            from.round() as i32,
+            to.round() as i32,
+            y,
+            depth,
+            colour,
+        );
     }
 }
 
@@ -270,69 +633,86 @@ fn draw_flat_bottom_triangle(
 ///   |           p1
 /// (0,0)---------------------> +x
 ///
-fn draw_flat_top_triangle(
-    buffer: &mut Vec<u32>,
+fn draw_flat_top_triangle<T: RasterTarget>(
+    target: &mut T,
     p1: Point,
-    p2x: u32,
-    p3x: u32,
-    p23y: u32,
+    p2x: i32,
+    p3x: i32,
+    p23y: i32,
+    depth: &DepthPlane,
     colour: u32,
 ) {
     // println!("Drawing flat topped triangle: p1:{p1:?}, p2x:{p2x}, p3x:{p3x}, p23y:{p23y}");
 
     // First calculate the inverse gradient of line p2 --> p1
     // Recall the gradient is Δy/Δx
-    let mut num = (p2x as f32) - (p1.x as f32);
-    let mut denom = (p23y as f32) - (p1.y as f32);
+    let mut num = (p2x as f32) - p1.x;
+    let mut denom = (p23y as f32) - p1.y;
     let inverse_gradient_p2_p1 = num / denom;
     // The inverse gradient is more convenient when later calculating the horizontal line length
 
     // Then calculate the gradient of line p1 --> p3
-    num = (p1.x as f32) - (p3x as f32);
-    denom = (p1.y as f32) - (p23y as f32);
+    num = p1.x - (p3x as f32);
+    denom = p1.y - (p23y as f32);
     let inverse_gradient_p1_p3 = num / denom;
 
-    // The starting point is the bottom points, p1
-    let mut from = p1.x as f32;
-    let mut to = p1.x as f32;
-
     // We know the triangle is flat top, so p1.y < p23y
-    // Create the range of y values from p1.y --> p23y
-    let range = (p1.y)..(p23y);
+    // Create the range of y values from p1.y --> p23y, less any rows outside the target
+    let range = clip_rows(target, p1.y as i32, p23y);
 
     // Loop over this range
     for y in range {
+        // The line starts at the bottom point, p1, and every row up it gets longer as it diverges
+        // towards p2 and p3, as gradient_p2_p1 and gradient_p1_p3 are guaranteed to be opposite signs
+        let rows = y as f32 - p1.y;
+        let from = p1.x + inverse_gradient_p2_p1 * rows;
+        let to = p1.x + inverse_gradient_p1_p3 * rows;
+
         // Drawing a horizontal line
-        draw_horiz_line(buffer, from.round() as u32, to.round() as u32, y, colour);
-        // Every iteration the horizontal line will get longer as it diverges from a single point of p1 --> p2 and p3
-        // as gradient_p2_p1  and gradient_p1_p3 are guaranteed to be opposite signs
-        from += inverse_gradient_p2_p1;
-        to += inverse_gradient_p1_p3;
+        draw_horiz_line(
+            target,
+            from.round() as i32,
+            to.round() as i32,
+            y,
+            depth,
+            colour,
+        );
     }
 }
 
-fn check_bounds(from: u32, to: u32, y: u32) -> bool {
-    if ((y as usize) < HEIGHT) & ((from as usize) < WIDTH) & ((to as usize) < WIDTH) {
-        true
-    } else {
-        false
-    }
+/// The rows from `bottom` up to but not including `top` that are inside the target
+fn clip_rows<T: RasterTarget>(target: &T, bottom: i32, top: i32) -> Range<i32> {
+    let rect = target.rect();
+    bottom.max(rect.y as i32)..top.min((rect.y + rect.height) as i32)
 }
 
 #[test]
 fn test_2d_to_1d_1() {
-    let expected = HEIGHT * WIDTH - WIDTH;
+    let framebuffer = FrameBuffer::new(800, 600);
+    let expected = 600 * 800 - 800;
 
-    let result = two_d_to_1d(0, 0);
+    let result = two_d_to_1d(&framebuffer.rect(), 0, 0);
 
     assert_eq!(expected, result);
 }
 
 #[test]
 fn test_sort_points_1() {
-    let p1 = Point { x: 0, y: 0, z: 0. };
-    let p2 = Point { x: 0, y: 1, z: 0. };
-    let p3 = Point { x: 0, y: 2, z: 0. };
+    let p1 = Point {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+    let p2 = Point {
+        x: 0.,
+        y: 1.,
+        z: 0.,
+    };
+    let p3 = Point {
+        x: 0.,
+        y: 2.,
+        z: 0.,
+    };
 
     let expected = (p3, p2, p1);
 
@@ -344,14 +724,18 @@ fn test_sort_points_1() {
 #[test]
 fn test_sort_points_2() {
     let p1 = Point {
-        x: 0,
-        y: 100,
+        x: 0.,
+        y: 100.,
+        z: 0.,
+    };
+    let p2 = Point {
+        x: 0.,
+        y: 50.,
         z: 0.,
     };
-    let p2 = Point { x: 0, y: 50, z: 0. };
     let p3 = Point {
-        x: 0,
-        y: 200,
+        x: 0.,
+        y: 200.,
         z: 0.,
     };
 
@@ -361,3 +745,169 @@ fn test_sort_points_2() {
 
     assert_eq!(expected, result);
 }
+
+#[test]
+fn test_depth_plane() {
+    let tri = Tri {
+        p1: Point {
+            x: 0.,
+            y: 0.,
+            z: 0.5,
+        },
+        p2: Point {
+            x: 10.,
+            y: 0.,
+            z: 0.6,
+        },
+        p3: Point {
+            x: 0.,
+            y: 10.,
+            z: 0.7,
+        },
+    };
+
+    let depth = DepthPlane::from_tri(&tri);
+
+    assert!((depth.at(0., 0.) - 0.5).abs() < 0.0001);
+    assert!((depth.at(10., 0.) - 0.6).abs() < 0.0001);
+    assert!((depth.at(0., 10.) - 0.7).abs() < 0.0001);
+    assert!((depth.at(5., 5.) - 0.65).abs() < 0.0001);
+}
+
+#[test]
+fn test_nearer_triangle_wins() {
+    let mut framebuffer = FrameBuffer::new(320, 240);
+    framebuffer.clear(crate::colour::Colour::new(0, 0, 0));
+
+    let tri_at = |z: f32| Tri {
+        p1: Point { x: 10., y: 10., z },
+        p2: Point { x: 50., y: 10., z },
+        p3: Point { x: 10., y: 50., z },
+    };
+
+    // The near triangle is drawn first, the far one must not overwrite it
+    draw_filled_triangle(&mut framebuffer, &tri_at(0.2), 0xff0000);
+    draw_filled_triangle(&mut framebuffer, &tri_at(0.8), 0x00ff00);
+
+    let index = two_d_to_1d(&framebuffer.rect(), 20, 20);
+    assert_eq!(0xff0000, framebuffer.pixels[index]);
+    assert!((framebuffer.depth[index] - 0.2).abs() < 0.0001);
+}
+
+#[test]
+fn test_triangle_off_left_edge_is_clipped() {
+    let mut framebuffer = FrameBuffer::new(64, 64);
+    framebuffer.clear(crate::colour::Colour::new(0, 0, 0));
+    let point = |x, y| Point { x, y, z: 0.5 };
+
+    // The right edge crosses column 0 at y = 30, above that the triangle is all off screen
+    let tri = Tri {
+        p1: point(-30., 10.),
+        p2: point(20., 10.),
+        p3: point(-30., 60.),
+    };
+    draw_filled_triangle(&mut framebuffer, &tri, 0xffffff);
+
+    let column_0 = |y| framebuffer.pixels[two_d_to_1d(&framebuffer.rect(), 0, y)];
+    assert_eq!(0xffffff, column_0(20));
+    for y in 32..64 {
+        assert_eq!(0, column_0(y), "row {y}");
+    }
+}
+
+#[test]
+fn test_line_off_screen_is_clipped() {
+    let mut framebuffer = FrameBuffer::new(64, 48);
+
+    draw_line(&mut framebuffer, 10, 10, 200, 100, 0xffffff);
+
+    assert_eq!(
+        0xffffff,
+        framebuffer.pixels[two_d_to_1d(&framebuffer.rect(), 10, 10)]
+    );
+}
+
+#[test]
+fn test_edge_function_watertight() {
+    let point = |x, y| Point { x, y, z: 0.5 };
+    let quads = [
+        // Awkward sub-pixel corners
+        [(3.3, 2.7), (40.6, 5.2), (37.1, 33.9), (6.8, 29.4)],
+        // The shared diagonal runs exactly through pixel centres
+        [(0.5, 0.5), (30.5, 4.5), (20.5, 20.5), (2.5, 30.5)],
+    ];
+
+    for quad in quads {
+        let [a, b, c, d] = quad.map(|(x, y)| point(x, y));
+        let mut first = FrameBuffer::new(48, 40);
+        let mut second = FrameBuffer::new(48, 40);
+
+        draw_filled_triangle_edge(
+            &mut first,
+            &Tri {
+                p1: a,
+                p2: b,
+                p3: c,
+            },
+            1,
+        );
+        draw_filled_triangle_edge(
+            &mut second,
+            &Tri {
+                p1: a,
+                p2: c,
+                p3: d,
+            },
+            1,
+        );
+
+        for y in 0..40 {
+            for x in 0..48 {
+                let index = two_d_to_1d(&first.rect(), x, y);
+                let covered = first.pixels[index] + second.pixels[index];
+                let centre = (x as f32 + 0.5, y as f32 + 0.5);
+                let inside = [(a, b), (b, c), (c, d), (d, a)].iter().all(|(p, q)| {
+                    (q.x - p.x) * (centre.1 - p.y) - (q.y - p.y) * (centre.0 - p.x) > 0.1
+                });
+
+                assert!(covered <= 1, "({x}, {y}) drawn twice");
+                if inside {
+                    assert_eq!(1, covered, "({x}, {y}) missed");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_multisample_matches_a_pass_per_sample() {
+    let point = |x, y, z| Point { x, y, z };
+    let tris = [
+        Tri {
+            p1: point(3.3, 2.7, 0.6),
+            p2: point(40.6, 5.2, 0.4),
+            p3: point(20.1, 33.9, 0.5),
+        },
+        // Crosses the first so the depth tests decide some samples
+        Tri {
+            p1: point(0.5, 30.5, 0.3),
+            p2: point(10.2, 0.5, 0.7),
+            p3: point(46.5, 20.5, 0.5),
+        },
+    ];
+    let samples = crate::antialias::sample_positions(4);
+
+    let mut planes: Vec<FrameBuffer> = samples.iter().map(|_| FrameBuffer::new(48, 40)).collect();
+    for (tri, colour) in tris.iter().zip([1, 2]) {
+        draw_filled_triangle_multisample(&mut planes, tri, colour, &samples);
+    }
+
+    for (plane, sample) in planes.iter().zip(&samples) {
+        let mut expected = FrameBuffer::new(48, 40);
+        for (tri, colour) in tris.iter().zip([1, 2]) {
+            draw_filled_triangle_at(&mut expected, tri, colour, *sample);
+        }
+        assert_eq!(expected.pixels, plane.pixels);
+        assert_eq!(expected.depth, plane.depth);
+    }
+}
@@ -0,0 +1,410 @@
+//! Hot reloading of the files a scene was built from. The files are polled for a new
+//! modification time rather than watched with OS notifications, there are only ever a handful.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::renderer::Renderer;
+use crate::scene::Scene;
+use crate::scene_file::{scene_dir, SceneFile};
+use crate::threed::*;
+
+/// What needs reloading when a watched file changes
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Scene,
+    /// Object index and the OBJ file to reload it from. MTL files and textures map to the
+    /// object whose OBJ refers to them
+    Object(usize, PathBuf),
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    target: Target,
+}
+
+/// Keeps a scene in step with the files on disk, reloading objects in place when their files change
+pub struct SceneReloader {
+    /// The scene file, when the scene came from one
+    scene_path: Option<String>,
+    /// The scene file as last loaded. Only what changed in the file since then is applied on a
+    /// reload, so objects the user has moved in the viewer stay where they are
+    scene_file: Option<SceneFile>,
+    files: Vec<WatchedFile>,
+}
+
+impl SceneReloader {
+    /// Watch the mesh files of the objects in a scene that wasn't loaded from a scene file
+    pub fn for_objects(scene: &Scene) -> SceneReloader {
+        let mut reloader = SceneReloader {
+            scene_path: None,
+            scene_file: None,
+            files: Vec::new(),
+        };
+        reloader.watch(scene);
+        reloader
+    }
+
+    /// Watch a scene file as well as the mesh files of the scene loaded from it
+    pub fn for_scene_file(path: &str, scene: &Scene) -> SceneReloader {
+        let mut reloader = SceneReloader {
+            scene_path: Some(path.to_string()),
+            scene_file: SceneFile::load(path).ok(),
+            files: Vec::new(),
+        };
+        reloader.watch(scene);
+        reloader
+    }
+
+    /// Reload anything whose files have changed since the last poll. Returns `None` when nothing
+    /// changed, otherwise a message saying what was reloaded or the first error hit.
+    /// A file that fails to load isn't retried until it changes again
+    pub fn poll(
+        &mut self,
+        scene: &mut Scene,
+        renderer: &mut Renderer,
+    ) -> Option<Result<String, io::Error>> {
+        let mut targets: Vec<Target> = Vec::new();
+        for file in &mut self.files {
+            let modified = modified_time(&file.path);
+            if modified != file.modified {
+                file.modified = modified;
+                if !targets.contains(&file.target) {
+                    targets.push(file.target.clone());
+                }
+            }
+        }
+
+        if targets.is_empty() {
+            return None;
+        }
+
+        let result = if targets.contains(&Target::Scene) {
+            // Everything comes from the scene file, reloading it reloads every mesh too
+            let path = self.scene_path.clone().unwrap_or_default();
+            self.reload_scene(&path, scene, renderer)
+                .map(|()| format!("Reloaded {path}"))
+        } else {
+            let mut reloaded = Vec::new();
+            let mut error = None;
+            for target in &targets {
+                if let Target::Object(index, path) = target {
+                    let Some(object) = scene.objects.get_mut(*index) else {
+                        continue;
+                    };
+                    match object.reload_mesh(&path.to_string_lossy()) {
+                        Ok(()) => reloaded.push(object.name.clone()),
+                        Err(e) => {
+                            error.get_or_insert_with(|| {
+                                io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+                            });
+                        }
+                    }
+                }
+            }
+            // Bounds change with the mesh
+            scene.refit();
+            match error {
+                Some(e) => Err(e),
+                None => Ok(format!("Reloaded {}", reloaded.join(", "))),
+            }
+        };
+
+        // A reload can add or remove MTL and texture references, start again from the files as they are now
+        self.watch(scene);
+
+        Some(result)
+    }
+
+    fn reload_scene(
+        &mut self,
+        path: &str,
+        scene: &mut Scene,
+        renderer: &mut Renderer,
+    ) -> Result<(), io::Error> {
+        let file = SceneFile::load(path)?;
+        let mut new_scene = file.build(&scene_dir(path))?;
+
+        if let Some(old_file) = &self.scene_file {
+            if old_file.camera == file.camera {
+                mem::swap(&mut new_scene.camera, &mut scene.camera);
+            }
+
+            for (index, (object, desc)) in
+                new_scene.objects.iter_mut().zip(&file.objects).enumerate()
+            {
+                // Names can repeat, so an object is matched with the one that has the same name
+                // and the same number of objects of that name before it
+                let occurrence = file.objects[..index]
+                    .iter()
+                    .filter(|other| other.name == desc.name)
+                    .count();
+                let old = old_file
+                    .objects
+                    .iter()
+                    .filter(|old| old.name == desc.name)
+                    .nth(occurrence);
+                let current = scene
+                    .objects
+                    .iter()
+                    .filter(|o| o.name == desc.name)
+                    .nth(occurrence);
+                let (Some(old), Some(current)) = (old, current) else {
+                    continue;
+                };
+                if old.position == desc.position && old.rotation == desc.rotation {
+                    object.transform = Transform {
+                        position: current.transform.position,
+                        rotation: current.transform.rotation,
+                    };
+                }
+            }
+
+            // Leave any toggles the user has made alone unless the file asks for something new
+            if old_file.render != file.render {
+                file.render.apply(renderer);
+            }
+        } else {
+            file.render.apply(renderer);
+        }
+
+        new_scene.refit();
+        *scene = new_scene;
+        self.scene_file = Some(file);
+        Ok(())
+    }
+
+    /// Rebuild the list of watched files from the scene, taking their current modification times
+    fn watch(&mut self, scene: &Scene) {
+        self.files.clear();
+
+        let dir = match self.scene_path.clone() {
+            Some(path) => {
+                self.add_file(PathBuf::from(&path), Target::Scene);
+                scene_dir(&path)
+            }
+            None => PathBuf::new(),
+        };
+
+        for (index, object) in scene.objects.iter().enumerate() {
+            let Some(mesh_path) = &object.mesh_path else {
+                continue;
+            };
+            let obj_path = dir.join(mesh_path);
+            let target = Target::Object(index, obj_path.clone());
+            for dependency in obj_dependencies(&obj_path) {
+                self.add_file(dependency, target.clone());
+            }
+            self.add_file(obj_path, target);
+        }
+    }
+
+    fn add_file(&mut self, path: PathBuf, target: Target) {
+        let modified = modified_time(&path);
+        self.files.push(WatchedFile {
+            path,
+            modified,
+            target,
+        });
+    }
+}
+
+/// `None` for a missing file, so a file being deleted and written again counts as a change
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The MTL files an OBJ refers to, and the textures those refer to
+pub fn obj_dependencies(obj_path: &Path) -> Vec<PathBuf> {
+    let mut dependencies = Vec::new();
+    let Ok(obj) = fs::read_to_string(obj_path) else {
+        return dependencies;
+    };
+    let obj_dir = obj_path.parent().unwrap_or(Path::new(""));
+
+    for line in obj.lines() {
+        let Some(libs) = line.trim().strip_prefix("mtllib ") else {
+            continue;
+        };
+        for lib in libs.split_whitespace() {
+            let mtl_path = obj_dir.join(lib);
+            if let Ok(mtl) = fs::read_to_string(&mtl_path) {
+                let mtl_dir = mtl_path.parent().unwrap_or(Path::new(""));
+                for line in mtl.lines() {
+                    let mut tokens = line.split_whitespace();
+                    let is_map = tokens
+                        .next()
+                        .is_some_and(|s| s.starts_with("map_") || s == "bump" || s == "disp");
+                    // Options like -bm 0.5 come first, the file name is always last
+                    if let (true, Some(texture)) = (is_map, tokens.last()) {
+                        dependencies.push(mtl_dir.join(texture));
+                    }
+                }
+            }
+            dependencies.push(mtl_path);
+        }
+    }
+
+    dependencies
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reload::*;
+    use crate::scene_file::load_scene;
+
+    /// A scratch copy of the files under test, so they can be rewritten
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("threedengine_{name}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Rewrite a file and make sure its modification time moves, some filesystems only keep seconds
+    fn rewrite(path: &Path, contents: &str) {
+        let before = modified_time(path);
+        fs::write(path, contents).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        let later = before.map_or(SystemTime::now(), |t| t + std::time::Duration::from_secs(2));
+        file.set_modified(later).unwrap();
+    }
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n";
+
+    #[test]
+    fn test_obj_dependencies() {
+        let dir = scratch_dir("deps");
+        let obj = dir.join("model.obj");
+        fs::write(&obj, format!("mtllib model.mtl\n{TRIANGLE}")).unwrap();
+        fs::write(
+            dir.join("model.mtl"),
+            "newmtl a\nKd 1 1 1\nmap_Kd albedo.png\nbump -bm 0.5 bump.png\n",
+        )
+        .unwrap();
+
+        let deps = obj_dependencies(&obj);
+
+        assert_eq!(
+            vec![
+                dir.join("albedo.png"),
+                dir.join("bump.png"),
+                dir.join("model.mtl")
+            ],
+            deps
+        );
+    }
+
+    #[test]
+    fn test_reload_object_keeps_transform() {
+        let dir = scratch_dir("object");
+        let obj = dir.join("model.obj");
+        fs::write(&obj, TRIANGLE).unwrap();
+        let mut scene = Scene::from_models(&[obj.to_string_lossy().to_string()]).unwrap();
+        let mut renderer = Renderer::new(80, 60);
+        let mut reloader = SceneReloader::for_objects(&scene);
+        scene.objects[0].transform.position.x = 7.;
+
+        assert!(reloader.poll(&mut scene, &mut renderer).is_none());
+
+        rewrite(&obj, QUAD);
+        let result = reloader.poll(&mut scene, &mut renderer).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(2, scene.objects[0].mesh.indices.len());
+        assert_eq!(7., scene.objects[0].transform.position.x);
+    }
+
+    #[test]
+    fn test_reload_error_keeps_old_mesh() {
+        let dir = scratch_dir("error");
+        let obj = dir.join("model.obj");
+        fs::write(&obj, TRIANGLE).unwrap();
+        let mut scene = Scene::from_models(&[obj.to_string_lossy().to_string()]).unwrap();
+        let mut renderer = Renderer::new(80, 60);
+        let mut reloader = SceneReloader::for_objects(&scene);
+
+        rewrite(&obj, "v 0 0 0\nf 1 2 3\n");
+        let result = reloader.poll(&mut scene, &mut renderer).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(1, scene.objects[0].mesh.indices.len());
+        // Not retried until the file changes again
+        assert!(reloader.poll(&mut scene, &mut renderer).is_none());
+    }
+
+    #[test]
+    fn test_reload_scene_file() {
+        let dir = scratch_dir("scene");
+        fs::write(dir.join("model.obj"), TRIANGLE).unwrap();
+        let scene_path = dir.join("test.ron");
+        let scene_text = |x: f32| {
+            format!(
+                "(camera: (position: (0.0, 0.0, -5.0)), light: (direction: (0.0, 1.0, 0.0)), objects: [
+                    (name: \"a\", mesh: \"model.obj\", material: (albedo: (1, 2, 3))),
+                    (name: \"b\", mesh: \"model.obj\", position: ({x:.1}, 0.0, 0.0), material: (albedo: (1, 2, 3))),
+                ])"
+            )
+        };
+        fs::write(&scene_path, scene_text(0.)).unwrap();
+        let path = scene_path.to_string_lossy().to_string();
+        let mut renderer = Renderer::new(80, 60);
+        let mut scene = load_scene(&path, &mut renderer).unwrap();
+        let mut reloader = SceneReloader::for_scene_file(&path, &scene);
+
+        // Both moved in the viewer, then b is moved in the file as well
+        scene.objects[0].transform.position.y = 3.;
+        scene.objects[1].transform.position.y = 3.;
+        rewrite(&scene_path, &scene_text(5.));
+        let result = reloader.poll(&mut scene, &mut renderer).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(3., scene.objects[0].transform.position.y);
+        assert_eq!(0., scene.objects[1].transform.position.y);
+        assert_eq!(5., scene.objects[1].transform.position.x);
+    }
+
+    #[test]
+    fn test_reload_scene_file_repeated_names() {
+        let dir = scratch_dir("repeated");
+        fs::write(dir.join("model.obj"), TRIANGLE).unwrap();
+        let scene_path = dir.join("test.ron");
+        let scene_text = |z: f32| {
+            format!(
+                "(camera: (position: (0.0, 0.0, -5.0)), light: (direction: (0.0, 1.0, 0.0)), objects: [
+                    (name: \"tile\", mesh: \"model.obj\", position: (0.0, 0.0, 0.0), material: (albedo: (1, 2, 3))),
+                    (name: \"tile\", mesh: \"model.obj\", position: (2.0, 0.0, {z:.1}), material: (albedo: (1, 2, 3))),
+                    (name: \"tile\", mesh: \"model.obj\", position: (4.0, 0.0, 0.0), material: (albedo: (1, 2, 3))),
+                ])"
+            )
+        };
+        fs::write(&scene_path, scene_text(0.)).unwrap();
+        let path = scene_path.to_string_lossy().to_string();
+        let mut renderer = Renderer::new(80, 60);
+        let mut scene = load_scene(&path, &mut renderer).unwrap();
+        let mut reloader = SceneReloader::for_scene_file(&path, &scene);
+
+        // The first one moved in the viewer, the second one in the file
+        scene.objects[0].transform.position.y = 3.;
+        rewrite(&scene_path, &scene_text(6.));
+        let result = reloader.poll(&mut scene, &mut renderer).unwrap();
+
+        assert!(result.is_ok());
+        let positions: Vec<[f32; 3]> = scene
+            .objects
+            .iter()
+            .map(|o| {
+                [
+                    o.transform.position.x,
+                    o.transform.position.y,
+                    o.transform.position.z,
+                ]
+            })
+            .collect();
+        assert_eq!(vec![[0., 3., 0.], [2., 0., 6.], [4., 0., 0.]], positions);
+    }
+}
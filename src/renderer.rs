@@ -0,0 +1,1066 @@
+use ndarray::Array2;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+use std::time::Instant;
+
+use crate::antialias::{downsample, sample_positions};
+use crate::bounds::Frustum;
+use crate::bvh::Ray;
+use crate::colour::*;
+use crate::debug_draw::{DebugDraw, DebugOverlays};
+use crate::debug_view::{draw_overdraw, normal_colour, show_depth, triangle_id_colour, DebugView};
+use crate::grid::GroundGrid;
+use crate::line::{draw_line_styled, Line, LineStyle};
+use crate::lod::screen_size;
+use crate::mesh::Mesh;
+use crate::post::PostChain;
+use crate::raster::{
+    self, draw_filled_triangle, draw_filled_triangle_at, draw_filled_triangle_edge,
+    draw_filled_triangle_multisample, Point, RasterTarget,
+};
+use crate::scene::Scene;
+use crate::threed::*;
+use crate::tile::{SamplePlane, Tile, TileGrid};
+
+/// Vertices and triangles are handed out to threads in chunks at least this big, smaller
+/// meshes are done in one go as splitting them costs more than it saves
+const VERT_CHUNK: usize = 1024;
+const TRI_CHUNK: usize = 1024;
+
+/// Colour and depth buffers the renderer draws into, one element per pixel with the top row first.
+/// Colours are 0RGB, depths are screen space z with smaller values nearer the camera
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+    pub depth: Vec<f32>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            depth: vec![f32::INFINITY; width * height],
+        }
+    }
+
+    /// Reallocate for a new size, the contents are lost
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = FrameBuffer::new(width, height);
+    }
+
+    pub fn clear(&mut self, colour: Colour) {
+        self.pixels.fill(colour.as_0rgb());
+        self.depth.fill(f32::INFINITY);
+    }
+}
+
+/// Timings and counts from the last rendered frame, times are in seconds
+#[derive(Default)]
+pub struct RenderStats {
+    pub trans_and_proj_time: f32,
+    pub raster_time: f32,
+    pub vis_tris: usize,
+    pub trans_verts: usize,
+    pub vis_objects: usize,
+    pub culled_objects: usize,
+    /// Averaging multisamples or filtering a supersampled frame down, the extra rasterising
+    /// is in `raster_time`
+    pub aa_time: f32,
+    pub post_time: f32,
+}
+
+/// How triangle colours are worked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ShadingMode {
+    /// One lighting value per triangle from its face normal
+    #[default]
+    Flat,
+    /// The plain albedo colour, no lighting
+    Unlit,
+}
+
+/// How filled triangles are turned into pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Rasterizer {
+    /// Split into flat topped and flat bottomed halves and filled a row at a time, on whole pixels
+    #[default]
+    Scanline,
+    /// Half-space tests in sub-pixel fixed point with the top-left fill rule, watertight
+    EdgeFunction,
+}
+
+/// How triangle edges are smoothed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AntiAliasing {
+    #[default]
+    Off,
+    /// Supersampling, rendering at twice the width and height then filtering down
+    Ssaa2x,
+    /// Supersampling at four times the width and height
+    Ssaa4x,
+    /// Multisampling with 2 samples per pixel. Always rasterised with edge functions
+    Msaa2x,
+    Msaa4x,
+    Msaa8x,
+}
+
+impl AntiAliasing {
+    /// How many times the output width and height the frame is rendered at
+    pub fn scale(self) -> usize {
+        match self {
+            AntiAliasing::Ssaa2x => 2,
+            AntiAliasing::Ssaa4x => 4,
+            _ => 1,
+        }
+    }
+
+    /// Samples per pixel when multisampling, otherwise 1
+    pub fn samples(self) -> usize {
+        match self {
+            AntiAliasing::Msaa2x => 2,
+            AntiAliasing::Msaa4x => 4,
+            AntiAliasing::Msaa8x => 8,
+            _ => 1,
+        }
+    }
+
+    /// The next mode along, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            AntiAliasing::Off => AntiAliasing::Ssaa2x,
+            AntiAliasing::Ssaa2x => AntiAliasing::Ssaa4x,
+            AntiAliasing::Ssaa4x => AntiAliasing::Msaa2x,
+            AntiAliasing::Msaa2x => AntiAliasing::Msaa4x,
+            AntiAliasing::Msaa4x => AntiAliasing::Msaa8x,
+            AntiAliasing::Msaa8x => AntiAliasing::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AntiAliasing::Off => "off",
+            AntiAliasing::Ssaa2x => "SSAA 2x",
+            AntiAliasing::Ssaa4x => "SSAA 4x",
+            AntiAliasing::Msaa2x => "MSAA 2x",
+            AntiAliasing::Msaa4x => "MSAA 4x",
+            AntiAliasing::Msaa8x => "MSAA 8x",
+        }
+    }
+}
+
+/// How a supersampled frame is filtered down to the output size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Downsample {
+    /// The average of the samples inside each pixel
+    #[default]
+    Box,
+    /// Samples weighted by their distance from the pixel centre, reaching half way into the
+    /// neighbours. Softer, and steadier as edges move
+    Tent,
+}
+
+/// What is drawn of each triangle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ViewMode {
+    /// Filled and lit
+    #[default]
+    Shaded,
+    /// Every edge of the triangles facing the camera, including ones behind other triangles
+    Wireframe,
+    /// Only the edges that can be seen, the faces hide what is behind them but aren't drawn
+    HiddenLine,
+    /// Filled and lit with the visible edges drawn over the top
+    ShadedWireframe,
+    /// A dot on each vertex of the triangles facing the camera
+    Points,
+}
+
+impl ViewMode {
+    /// Whether triangles are filled, even if only to hide what is behind them
+    pub fn fills_faces(self) -> bool {
+        matches!(
+            self,
+            ViewMode::Shaded | ViewMode::HiddenLine | ViewMode::ShadedWireframe
+        )
+    }
+
+    pub fn draws_edges(self) -> bool {
+        matches!(
+            self,
+            ViewMode::Wireframe | ViewMode::HiddenLine | ViewMode::ShadedWireframe
+        )
+    }
+
+    /// The next mode along, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            ViewMode::Shaded => ViewMode::Wireframe,
+            ViewMode::Wireframe => ViewMode::HiddenLine,
+            ViewMode::HiddenLine => ViewMode::ShadedWireframe,
+            ViewMode::ShadedWireframe => ViewMode::Points,
+            ViewMode::Points => ViewMode::Shaded,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ViewMode::Shaded => "shaded",
+            ViewMode::Wireframe => "wireframe",
+            ViewMode::HiddenLine => "hidden line",
+            ViewMode::ShadedWireframe => "shaded wireframe",
+            ViewMode::Points => "points",
+        }
+    }
+}
+
+/// Edges drawn over shaded faces are all this colour, so they show up whatever the face colour
+const OVERLAY_EDGE_COLOUR: u32 = 0x000000;
+/// Width and height of the dots drawn on vertices in points mode, in pixels
+const POINT_SIZE: f32 = 3.;
+
+/// Draws a scene into its own framebuffer. Knows nothing about windows, the finished frame
+/// is handed to a presenter (or written to disk) by whoever owns the renderer
+pub struct Renderer {
+    pub framebuffer: FrameBuffer,
+    pub view_mode: ViewMode,
+    /// How wireframe edges are drawn
+    pub line_style: LineStyle,
+    pub shading: ShadingMode,
+    pub rasterizer: Rasterizer,
+    pub anti_aliasing: AntiAliasing,
+    /// Filter for supersampling
+    pub downsample: Downsample,
+    /// Screen space effects run over each finished frame
+    pub post: PostChain,
+    /// Drawn into the frame after anti-aliasing, before the post effects
+    pub grid: GroundGrid,
+    /// Shows depth, normals, overdraw or triangle ids in place of the lit scene
+    pub debug_view: DebugView,
+    /// Lines to draw over the next frame, cleared once they have been drawn
+    pub debug: DebugDraw,
+    /// Debug lines added every frame
+    pub overlays: DebugOverlays,
+    /// Threads to transform objects and rasterise tiles on, 0 for one per core.
+    /// The image is the same whatever the count
+    pub threads: usize,
+    pub stats: RenderStats,
+    /// One per visible object, kept between frames to save reallocating
+    vert_caches: Vec<VertCache>,
+    tiles: TileGrid,
+    /// The full size frame when supersampling, before it is filtered down into `framebuffer`
+    supersampled: FrameBuffer,
+    /// Built on first use, and again if `threads` changes
+    pool: Option<(usize, ThreadPool)>,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Renderer {
+            framebuffer: FrameBuffer::new(width, height),
+            view_mode: ViewMode::default(),
+            line_style: LineStyle::default(),
+            shading: ShadingMode::default(),
+            rasterizer: Rasterizer::default(),
+            anti_aliasing: AntiAliasing::default(),
+            downsample: Downsample::default(),
+            post: PostChain::standard(),
+            grid: GroundGrid::default(),
+            debug_view: DebugView::default(),
+            debug: DebugDraw::default(),
+            overlays: DebugOverlays::default(),
+            threads: 0,
+            stats: RenderStats::default(),
+            vert_caches: Vec::new(),
+            tiles: TileGrid::new(width, height),
+            supersampled: FrameBuffer::new(0, 0),
+            pool: None,
+        }
+    }
+
+    /// Render at a new resolution from the next frame on, the projection follows the framebuffer size
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.framebuffer.resize(width, height);
+        self.tiles = TileGrid::new(width, height);
+    }
+
+    pub fn projection_matrix(&self, camera: &Camera) -> Array2<f32> {
+        camera.create_projection_matrix(Screen {
+            width: self.framebuffer.width as i32,
+            height: self.framebuffer.height as i32,
+        })
+    }
+
+    /// World space ray from the camera through a point on the framebuffer.
+    /// Like mouse coordinates, y increases down the screen
+    pub fn screen_ray(&self, camera: &Camera, x: f32, y: f32) -> Ray {
+        let ndc_x = 2. * x / (self.framebuffer.width as f32) - 1.;
+        let ndc_y = 1. - 2. * y / (self.framebuffer.height as f32);
+
+        // Undo the projection for a point at view space z = 1
+        let proj_mat = self.projection_matrix(camera);
+        let view_dir = vec3 {
+            x: ndc_x / proj_mat[[0, 0]],
+            y: ndc_y / proj_mat[[1, 1]],
+            z: 1.,
+        };
+
+        let cam_to_world = quick_invert_mat4(camera.create_view_matrix());
+
+        Ray {
+            origin: camera.position,
+            dir: normalise_vec(&mult_dir_mat4(view_dir, &cam_to_world)),
+        }
+    }
+
+    /// The camera matrices for a frame `width` by `height`, which is bigger than the framebuffer
+    /// when supersampling
+    fn frame_projection(&self, camera: &Camera, width: usize, height: usize) -> Projection {
+        Projection {
+            view_mat: camera.create_view_matrix(),
+            proj_mat: self.projection_matrix(camera),
+            width: width as f32,
+            height: height as f32,
+            near_plane: camera.near_plane,
+        }
+    }
+
+    /// Draw one frame of the scene. The scene is mutable as each object's level of detail
+    /// is picked from its size on screen
+    pub fn render(&mut self, scene: &mut Scene) {
+        //Start of Transform and project
+        let trans_and_proj_time_start = Instant::now();
+
+        // Supersampling renders everything at a multiple of the framebuffer size
+        let scale = self.anti_aliasing.scale();
+        let samples = self.anti_aliasing.samples();
+        let width = self.framebuffer.width * scale;
+        let height = self.framebuffer.height * scale;
+
+        let projection = self.frame_projection(&scene.camera, width, height);
+
+        let frustum = Frustum::from_matrix(&projection.view_mat.dot(&projection.proj_mat));
+
+        // The scene hierarchy finds the objects whose world bounding box touches the frustum,
+        // sorting keeps the objects in scene order
+        let mut candidates = scene.bvh.query_frustum(&frustum);
+        candidates.sort_unstable();
+
+        // Culling and picking the level of detail are cheap and change the scene, so they
+        // happen up front on this thread
+        let mut visible = Vec::new();
+        for object_index in candidates {
+            let model_mat = scene.objects[object_index].transform.model_matrix();
+
+            // The bounding sphere can be tighter than the world box of a rotated object
+            let sphere = scene.objects[object_index]
+                .mesh
+                .sphere
+                .transform(&model_mat);
+            if !frustum.intersects_sphere(&sphere) {
+                continue;
+            }
+
+            let size = screen_size(&sphere, &scene.camera);
+            scene.objects[object_index].lods.select(size);
+            visible.push((object_index, model_mat));
+        }
+
+        // Objects are transformed in parallel, and large meshes are split into chunks of vertices
+        // and triangles as well. Collecting keeps everything in scene order, so the triangle
+        // list comes out the same whatever the thread count
+        self.vert_caches
+            .resize_with(visible.len(), VertCache::default);
+        let objects = &scene.objects;
+        let shade = Shade {
+            mode: self.shading,
+            light_dir: scene.light_dir,
+        };
+        let vert_caches = &mut self.vert_caches;
+        let view_mode = self.view_mode;
+        let debug_view = self.debug_view;
+        let fog = scene.fog;
+        let vertex_fog = fog.enabled() && fog.per_vertex && debug_view == DebugView::Off;
+        // Lines write no depth for the fog pass to find, so unless they are drawn over faces
+        // they are fogged at their ends
+        let line_fog = fog.enabled()
+            && debug_view == DebugView::Off
+            && (fog.per_vertex || !view_mode.fills_faces());
+        let projection_ref = &projection;
+        let fog_average = |depths: &[f32]| {
+            depths
+                .iter()
+                .map(|z| fog.amount(projection_ref.view_depth(*z)))
+                .sum::<f32>()
+                / depths.len() as f32
+        };
+        let per_object: Vec<ObjectOutput> =
+            worker_pool(&mut self.pool, self.threads).install(|| {
+                visible
+                    .par_iter()
+                    .zip(vert_caches.par_iter_mut())
+                    .map(|((object_index, model_mat), cache)| {
+                        let object = &objects[*object_index];
+                        let mesh = object.active_mesh();
+                        cache.transform(&projection, mesh, model_mat);
+
+                        let cache = &*cache;
+                        let tris: Vec<(&[usize; 3], raster::Tri, u32)> = mesh
+                            .indices
+                            .par_chunks(TRI_CHUNK)
+                            .enumerate()
+                            .flat_map_iter(|(chunk_index, chunk)| {
+                                chunk.iter().enumerate().filter_map(move |(i, index)| {
+                                    let (tri, normal) = process_tri(cache, index)?;
+                                    let colour = match debug_view {
+                                        DebugView::Normals => normal_colour(&normal),
+                                        DebugView::TriangleId => triangle_id_colour(
+                                            *object_index,
+                                            chunk_index * TRI_CHUNK + i,
+                                        ),
+                                        _ => shade.colour(&normal, object.albedo),
+                                    };
+                                    Some((index, tri, colour))
+                                })
+                            })
+                            .collect();
+
+                        let mut lines = if view_mode.draws_edges() {
+                            unique_edges(cache, &mesh.welded, &tris)
+                        } else if view_mode == ViewMode::Points {
+                            unique_points(cache, &mesh.welded, &tris)
+                        } else {
+                            Vec::new()
+                        };
+                        for (line, colour) in &mut lines {
+                            if view_mode == ViewMode::ShadedWireframe {
+                                *colour = OVERLAY_EDGE_COLOUR;
+                            }
+                            if line_fog {
+                                *colour = fog.apply(*colour, fog_average(&[line.p1.z, line.p2.z]));
+                            }
+                        }
+
+                        let tris = tris
+                            .into_iter()
+                            .map(|(_, tri, colour)| {
+                                // Triangles are filled in one colour, so they take the average
+                                // of the fog at their corners
+                                if vertex_fog {
+                                    let amount = fog_average(&[tri.p1.z, tri.p2.z, tri.p3.z]);
+                                    (tri, fog.apply(colour, amount))
+                                } else {
+                                    (tri, colour)
+                                }
+                            })
+                            .collect();
+                        (tris, lines)
+                    })
+                    .collect()
+            });
+
+        let trans_verts = visible
+            .iter()
+            .map(|(object_index, _)| objects[*object_index].active_mesh().verts.len())
+            .sum();
+        let (per_object_tris, per_object_lines): (Vec<_>, Vec<_>) = per_object.into_iter().unzip();
+        let (screen_tris, mut colours): (Vec<raster::Tri>, Vec<u32>) =
+            per_object_tris.into_iter().flatten().unzip();
+        let (lines, line_colours): (Vec<Line>, Vec<u32>) =
+            per_object_lines.into_iter().flatten().unzip();
+        // The faces are filled in the background colour, only to hide the edges behind them
+        if view_mode == ViewMode::HiddenLine {
+            colours.fill(scene.clear_colour().as_0rgb());
+        }
+
+        let indices = far_to_near(screen_tris.iter().map(|t| t.p1.z + t.p2.z + t.p3.z));
+        let line_indices = far_to_near(lines.iter().map(|l| l.p1.z + l.p2.z));
+
+        self.stats.trans_and_proj_time = trans_and_proj_time_start.elapsed().as_secs_f32();
+        //End of Transform and Project
+
+        //Start of Raster
+        let raster_time_start = Instant::now();
+
+        // The framebuffer may have been replaced or resized by the owner, or the anti-aliasing
+        // changed, since the last frame
+        if (self.tiles.width, self.tiles.height, self.tiles.samples) != (width, height, samples) {
+            self.tiles = TileGrid::with_samples(width, height, samples);
+        }
+        self.tiles.clear(scene.clear_colour());
+
+        let mut line_style = match view_mode {
+            ViewMode::Points => LineStyle {
+                width: POINT_SIZE,
+                ..LineStyle::default()
+            },
+            // Edges are tested against the filled faces so the ones behind them are left out
+            ViewMode::HiddenLine | ViewMode::ShadedWireframe => LineStyle {
+                depth_test: true,
+                ..self.line_style
+            },
+            _ => self.line_style,
+        };
+        // Supersampled lines are drawn wider to look the same once filtered down
+        line_style.width *= scale as f32;
+
+        // Lines are drawn after the triangles in each tile, so they can be tested against them
+        if view_mode.fills_faces() {
+            self.tiles.bin(&screen_tris, &indices);
+        }
+        self.tiles
+            .bin_lines(&lines, &line_indices, line_style.width);
+
+        let draw_list = DrawList {
+            tris: &screen_tris,
+            colours: &colours,
+            rasterizer: self.rasterizer,
+            lines: &lines,
+            line_colours: &line_colours,
+            line_style,
+            overdraw: debug_view == DebugView::Overdraw,
+        };
+        let sample_positions = sample_positions(samples);
+        let draw_tile = |tile: &mut Tile| {
+            let tris = mem::take(&mut tile.tris);
+            let lines = mem::take(&mut tile.lines);
+            if tile.samples.is_empty() {
+                draw_list.draw(tile, &tris, &lines, None);
+            } else {
+                draw_list.draw_samples(&mut tile.samples, &sample_positions, &tris, &lines);
+            }
+            tile.tris = tris;
+            tile.lines = lines;
+        };
+
+        let tiles = &mut self.tiles.tiles;
+        worker_pool(&mut self.pool, self.threads)
+            .install(|| tiles.par_iter_mut().for_each(draw_tile));
+
+        self.stats.raster_time = raster_time_start.elapsed().as_secs_f32();
+        //End of Raster
+
+        let aa_time_start = Instant::now();
+        if samples > 1 {
+            let tiles = &mut self.tiles.tiles;
+            worker_pool(&mut self.pool, self.threads)
+                .install(|| tiles.par_iter_mut().for_each(Tile::resolve_samples));
+        }
+        if scale > 1 {
+            if (self.supersampled.width, self.supersampled.height) != (width, height) {
+                self.supersampled.resize(width, height);
+            }
+            self.tiles.resolve(&mut self.supersampled);
+            downsample(&self.supersampled, &mut self.framebuffer, self.downsample);
+        } else {
+            self.tiles.resolve(&mut self.framebuffer);
+        }
+        self.stats.aa_time = aa_time_start.elapsed().as_secs_f32();
+
+        if debug_view == DebugView::Depth {
+            let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+            let projection = self.frame_projection(&scene.camera, width, height);
+            show_depth(&mut self.framebuffer, &projection);
+        }
+
+        if fog.enabled() && !fog.per_vertex && debug_view == DebugView::Off {
+            let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+            let projection = self.frame_projection(&scene.camera, width, height);
+            let framebuffer = &mut self.framebuffer;
+            worker_pool(&mut self.pool, self.threads)
+                .install(|| fog.draw(framebuffer, &projection));
+        }
+
+        // The debug views are left as they were drawn, nothing is blended into them
+        if self.grid.enabled && debug_view == DebugView::Off {
+            let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+            let projection = self.frame_projection(&scene.camera, width, height);
+            let (grid, framebuffer) = (&self.grid, &mut self.framebuffer);
+            worker_pool(&mut self.pool, self.threads)
+                .install(|| grid.draw(framebuffer, &projection, &fog));
+        }
+
+        let post_time_start = Instant::now();
+        if debug_view == DebugView::Off {
+            let (post, framebuffer) = (&mut self.post, &mut self.framebuffer);
+            worker_pool(&mut self.pool, self.threads).install(|| post.apply(framebuffer));
+        }
+        self.stats.post_time = post_time_start.elapsed().as_secs_f32();
+
+        // Over the finished frame so the anti-aliasing and post effects leave them sharp
+        self.draw_debug(scene);
+
+        self.stats.vis_tris = screen_tris.len();
+        self.stats.trans_verts = trans_verts;
+        self.stats.vis_objects = visible.len();
+        self.stats.culled_objects = scene.objects.len() - visible.len();
+    }
+
+    /// Draw the overlays and any lines added to `debug`, then clear them
+    fn draw_debug(&mut self, scene: &Scene) {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        self.overlays
+            .draw(scene, width as f32 / height as f32, &mut self.debug);
+        if self.debug.lines.is_empty() {
+            return;
+        }
+
+        let projection = self.frame_projection(&scene.camera, width, height);
+        for line in &self.debug.lines {
+            if let Some(screen_line) = projection.project_line(line.from, line.to) {
+                draw_line_styled(
+                    &mut self.framebuffer,
+                    &screen_line,
+                    line.colour,
+                    &self.debug.style,
+                );
+            }
+        }
+        self.debug.lines.clear();
+    }
+}
+
+/// Triangles and edges from one object, in mesh order
+type ObjectOutput = (Vec<(raster::Tri, u32)>, Vec<(Line, u32)>);
+
+/// Indices sorted by decreasing depth, for drawing back to front
+fn far_to_near(depths: impl Iterator<Item = f32>) -> Vec<usize> {
+    //This weird multiplication is just to be able to sort by z
+    let z_vals: Vec<u32> = depths.map(|z| (z * 1000000.) as u32).collect();
+
+    let mut indices = (0..z_vals.len()).collect::<Vec<_>>();
+    indices.sort_by_key(|&i| z_vals[i]);
+    indices.reverse();
+    indices
+}
+
+/// Each edge of the triangles once, in the colour of the nearest triangle to use it, as it would
+/// be if every triangle's outline were drawn back to front. Edges are matched by `welded`, so one
+/// along a texture or normal seam is still drawn once
+fn unique_edges(
+    cache: &VertCache,
+    welded: &[usize],
+    tris: &[(&[usize; 3], raster::Tri, u32)],
+) -> Vec<(Line, u32)> {
+    // Each edge's place in `lines` and the depth of the nearest triangle using it so far
+    let mut seen: HashMap<(usize, usize), (usize, f32)> = HashMap::new();
+    let mut lines: Vec<(Line, u32)> = Vec::new();
+    for &(&[i1, i2, i3], ref tri, colour) in tris {
+        let z = tri.p1.z + tri.p2.z + tri.p3.z;
+        for (a, b) in [(i1, i2), (i2, i3), (i3, i1)] {
+            let (wa, wb) = (welded[a], welded[b]);
+            match seen.entry((wa.min(wb), wa.max(wb))) {
+                Entry::Occupied(mut entry) => {
+                    let (index, nearest) = entry.get_mut();
+                    if z < *nearest {
+                        *nearest = z;
+                        lines[*index].1 = colour;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert((lines.len(), z));
+                    let line = Line {
+                        p1: screen_point(cache.screen[a]),
+                        p2: screen_point(cache.screen[b]),
+                    };
+                    lines.push((line, colour));
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Each vertex of the triangles once, as a line with no length, in the colour of the nearest
+/// triangle to use it. Vertices are matched by `welded`, like the edges
+fn unique_points(
+    cache: &VertCache,
+    welded: &[usize],
+    tris: &[(&[usize; 3], raster::Tri, u32)],
+) -> Vec<(Line, u32)> {
+    // Each vertex's place in `points` and the depth of the nearest triangle using it so far
+    let mut seen: HashMap<usize, (usize, f32)> = HashMap::new();
+    let mut points: Vec<(Line, u32)> = Vec::new();
+    for &(indices, ref tri, colour) in tris {
+        let z = tri.p1.z + tri.p2.z + tri.p3.z;
+        for &vertex in indices {
+            match seen.entry(welded[vertex]) {
+                Entry::Occupied(mut entry) => {
+                    let (index, nearest) = entry.get_mut();
+                    if z < *nearest {
+                        *nearest = z;
+                        points[*index].1 = colour;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert((points.len(), z));
+                    let point = screen_point(cache.screen[vertex]);
+                    points.push((
+                        Line {
+                            p1: point,
+                            p2: point,
+                        },
+                        colour,
+                    ));
+                }
+            }
+        }
+    }
+    points
+}
+
+/// The frame's triangles and lines and how to draw them, shared by every tile
+struct DrawList<'a> {
+    tris: &'a [raster::Tri],
+    colours: &'a [u32],
+    rasterizer: Rasterizer,
+    lines: &'a [Line],
+    line_colours: &'a [u32],
+    line_style: LineStyle,
+    /// Count the writes to each pixel instead of keeping the triangles' colours
+    overdraw: bool,
+}
+
+impl DrawList<'_> {
+    /// Draw the triangles in `tris` then the lines in `lines`. With a `sample` point only that
+    /// point in each pixel is tested for triangles, which needs the edge function rasterizer
+    fn draw<T: RasterTarget>(
+        &self,
+        target: &mut T,
+        tris: &[usize],
+        lines: &[usize],
+        sample: Option<(f32, f32)>,
+    ) {
+        if self.overdraw {
+            let tris = tris.iter().map(|&index| &self.tris[index]);
+            draw_overdraw(target, tris, |target, tri, colour| {
+                self.fill(target, tri, colour, sample)
+            });
+        } else {
+            for &index in tris {
+                self.fill(target, &self.tris[index], self.colours[index], sample);
+            }
+        }
+
+        self.draw_lines(target, lines);
+    }
+
+    /// Draw into a tile's sample planes, one per position in `samples`. Each triangle is walked
+    /// once for all of the samples, then the lines are drawn into every plane. The overdraw view
+    /// counts the writes to each plane on its own, so it draws them one at a time
+    fn draw_samples(
+        &self,
+        planes: &mut [SamplePlane],
+        samples: &[(f32, f32)],
+        tris: &[usize],
+        lines: &[usize],
+    ) {
+        if self.overdraw {
+            for (plane, sample) in planes.iter_mut().zip(samples) {
+                self.draw(plane, tris, lines, Some(*sample));
+            }
+            return;
+        }
+
+        for &index in tris {
+            draw_filled_triangle_multisample(
+                planes,
+                &self.tris[index],
+                self.colours[index],
+                samples,
+            );
+        }
+        for plane in planes {
+            self.draw_lines(plane, lines);
+        }
+    }
+
+    fn draw_lines<T: RasterTarget>(&self, target: &mut T, lines: &[usize]) {
+        for &index in lines {
+            let (line, colour) = (&self.lines[index], self.line_colours[index]);
+            draw_line_styled(target, line, colour, &self.line_style);
+        }
+    }
+
+    fn fill<T: RasterTarget>(
+        &self,
+        target: &mut T,
+        tri: &raster::Tri,
+        colour: u32,
+        sample: Option<(f32, f32)>,
+    ) {
+        match (sample, self.rasterizer) {
+            (Some(sample), _) => draw_filled_triangle_at(target, tri, colour, sample),
+            (None, Rasterizer::Scanline) => draw_filled_triangle(target, tri, colour),
+            (None, Rasterizer::EdgeFunction) => draw_filled_triangle_edge(target, tri, colour),
+        }
+    }
+}
+
+/// The pool for the requested thread count, building a new one if the count has changed
+fn worker_pool(pool: &mut Option<(usize, ThreadPool)>, threads: usize) -> &ThreadPool {
+    if pool.as_ref().is_none_or(|(n, _)| *n != threads) {
+        let new_pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("Unable to create the render thread pool");
+        *pool = Some((threads, new_pool));
+    }
+    &pool.as_ref().unwrap().1
+}
+
+/// How to light a triangle, copied out of the renderer and scene for the worker threads
+#[derive(Clone, Copy)]
+struct Shade {
+    mode: ShadingMode,
+    light_dir: vec3,
+}
+
+impl Shade {
+    fn colour(&self, normal: &vec3, albedo: Colour) -> u32 {
+        match self.mode {
+            ShadingMode::Flat => calc_tri_illum(&self.light_dir, normal, albedo),
+            ShadingMode::Unlit => albedo,
+        }
+        .as_0rgb()
+    }
+}
+
+/// The camera matrices and viewport size for the frame being rendered
+pub(crate) struct Projection {
+    pub(crate) view_mat: Array2<f32>,
+    pub(crate) proj_mat: Array2<f32>,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pub(crate) near_plane: f32,
+}
+
+impl Projection {
+    /// Take a world space vertex through the view and projection matrices and into screen space
+    fn project_vert(&self, vert: Vert) -> Vert {
+        self.view_to_screen(mult_vec3_mat4(vert, &self.view_mat))
+    }
+
+    /// Take a world space line into screen space, cutting off any part behind the near plane.
+    /// `None` if it is all behind
+    fn project_line(&self, from: Vert, to: Vert) -> Option<Line> {
+        let near = self.near_plane;
+        let a = mult_vec3_mat4(from, &self.view_mat);
+        let b = mult_vec3_mat4(to, &self.view_mat);
+        if a.z < near && b.z < near {
+            return None;
+        }
+
+        let clip = |p: Vert, q: Vert| {
+            if p.z >= near {
+                return p;
+            }
+            let t = (near - p.z) / (q.z - p.z);
+            Vert {
+                x: p.x + (q.x - p.x) * t,
+                y: p.y + (q.y - p.y) * t,
+                z: near,
+            }
+        };
+        Some(Line {
+            p1: screen_point(self.view_to_screen(clip(a, b))),
+            p2: screen_point(self.view_to_screen(clip(b, a))),
+        })
+    }
+
+    /// The view space depth of a screen space depth, the inverse of `view_to_screen`. Infinite
+    /// for the cleared depth
+    pub(crate) fn view_depth(&self, z: f32) -> f32 {
+        if !z.is_finite() {
+            return f32::INFINITY;
+        }
+        let ndc = z * 2. - 1.;
+        self.proj_mat[[3, 2]] / (ndc - self.proj_mat[[2, 2]])
+    }
+
+    /// Take a view space vertex into screen space
+    pub(crate) fn view_to_screen(&self, vert: Vert) -> Vert {
+        let mut vert = mult_vec3_mat4(vert, &self.proj_mat);
+
+        vert.x += 1.;
+        vert.x *= 0.5 * self.width;
+        vert.y += 1.;
+        vert.y *= 0.5 * self.height;
+        vert.z += 1.;
+        vert.z *= 0.5;
+
+        vert
+    }
+}
+
+/// Post-transform cache for the object currently being processed.
+/// Each unique mesh vertex is transformed once into world space (used for lighting and culling)
+/// and projected once into screen space (used for rasterisation), the triangles are then
+/// assembled from these by index.
+#[derive(Default)]
+struct VertCache {
+    world: Vec<Vert>,
+    screen: Vec<Vert>,
+}
+
+impl VertCache {
+    fn transform(&mut self, projection: &Projection, mesh: &Mesh, model_mat: &Array2<f32>) {
+        self.world.resize(mesh.verts.len(), Vert::default());
+        self.screen.resize(mesh.verts.len(), Vert::default());
+
+        mesh.verts
+            .par_iter()
+            .zip(self.world.par_iter_mut())
+            .zip(self.screen.par_iter_mut())
+            .with_min_len(VERT_CHUNK)
+            .for_each(|((vert, world), screen)| {
+                *world = mult_vec3_mat4(*vert, model_mat);
+                *screen = projection.project_vert(*world);
+            });
+    }
+}
+
+fn screen_point(vert: Vert) -> Point {
+    Point {
+        x: vert.x,
+        y: vert.y,
+        z: vert.z,
+    }
+}
+
+/// Back face culling test on a triangle's world space normal
+pub(crate) fn faces_camera(normal: &Vert) -> bool {
+    normal.z <= 0.
+}
+
+/// Assemble a triangle from the cache, giving its screen points and world space normal.
+/// `None` if it faces away from the camera
+fn process_tri(cache: &VertCache, index: &[usize; 3]) -> Option<(raster::Tri, Vert)> {
+    let [i1, i2, i3] = *index;
+
+    let tri = Tri {
+        v1: cache.world[i1],
+        v2: cache.world[i2],
+        v3: cache.world[i3],
+    };
+
+    let normal = normal(&tri);
+
+    if faces_camera(&normal) {
+        let p1 = screen_point(cache.screen[i1]);
+        let p2 = screen_point(cache.screen[i2]);
+        let p3 = screen_point(cache.screen[i3]);
+
+        Some((raster::Tri { p1, p2, p3 }, normal))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::demo::init_scene;
+    use crate::renderer::*;
+
+    #[test]
+    fn test_same_image_for_any_thread_count() {
+        let mut scene = init_scene();
+        let mut single = Renderer::new(320, 240);
+        single.threads = 1;
+        let mut multi = Renderer::new(320, 240);
+        multi.threads = 4;
+
+        single.render(&mut scene);
+        multi.render(&mut scene);
+
+        assert_eq!(single.framebuffer.pixels, multi.framebuffer.pixels);
+        assert_eq!(single.framebuffer.depth, multi.framebuffer.depth);
+    }
+
+    #[test]
+    fn test_screen_ray_picks_object() {
+        let scene = init_scene();
+        let renderer = Renderer::new(320, 240);
+        let projection = renderer.frame_projection(&scene.camera, 320, 240);
+
+        for (i, object) in scene.objects.iter().enumerate() {
+            let model_mat = object.transform.model_matrix();
+            let centre = mult_vec3_mat4(object.mesh.aabb.centre(), &model_mat);
+            // Screen space y is up from the bottom, the ray takes it down from the top
+            let on_screen = projection.project_vert(centre);
+            let ray = renderer.screen_ray(&scene.camera, on_screen.x, 240. - on_screen.y);
+
+            let hit = scene.bvh.intersect_ray_nearest(&scene.objects, &ray);
+
+            assert_eq!(Some(i), hit.map(|hit| hit.object));
+        }
+    }
+
+    #[test]
+    fn test_seam_edges_and_points_drawn_once() {
+        // Two triangles of a quad with different texture coordinates either side of the diagonal
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\n\
+                   f 1/1 2/1 3/1\nf 1/2 3/2 4/2\n";
+        let mesh = Mesh::from_obj_str(obj).unwrap();
+        let cache = VertCache {
+            world: mesh.verts.clone(),
+            screen: mesh.verts.clone(),
+        };
+        let tris: Vec<(&[usize; 3], raster::Tri, u32)> = mesh
+            .indices
+            .iter()
+            .map(|index| {
+                let point = |i: usize| screen_point(mesh.verts[index[i]]);
+                let tri = raster::Tri {
+                    p1: point(0),
+                    p2: point(1),
+                    p3: point(2),
+                };
+                (index, tri, 0)
+            })
+            .collect();
+
+        assert_eq!(5, unique_edges(&cache, &mesh.welded, &tris).len());
+        assert_eq!(4, unique_points(&cache, &mesh.welded, &tris).len());
+    }
+
+    #[test]
+    fn test_project_line_clipped_to_near_plane() {
+        let scene = init_scene();
+        let renderer = Renderer::new(320, 240);
+        let camera = &scene.camera;
+        let projection = renderer.frame_projection(camera, 320, 240);
+        let behind = camera.position
+            - Vert {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            };
+        let ahead = camera.position
+            + Vert {
+                x: 0.,
+                y: 0.,
+                z: 10.,
+            };
+
+        assert_eq!(None, projection.project_line(behind, behind));
+        let line = projection.project_line(behind, ahead).unwrap();
+        // The clipped end is on the near plane
+        let on_near_plane = projection.project_vert(
+            camera.position
+                + Vert {
+                    x: 0.,
+                    y: 0.,
+                    z: camera.near_plane,
+                },
+        );
+        assert!((line.p1.z - on_near_plane.z).abs() < 1e-4);
+        assert!(line.p2.z > line.p1.z);
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+const RESOURCE_DIR_NAME: &str = "Resource";
+
+/// The directory holding the Models and Scenes folders. Looked for in order:
+/// $THREEDENGINE_RESOURCES, Resource in the working directory, Resource beside the executable
+/// or any directory above it (covers running from target/release), then the source tree
+/// it was built from
+pub fn resource_dir() -> PathBuf {
+    if let Ok(dir) = env::var("THREEDENGINE_RESOURCES") {
+        return PathBuf::from(dir);
+    }
+
+    let mut candidates = Vec::new();
+    if let Ok(dir) = env::current_dir() {
+        candidates.push(dir.join(RESOURCE_DIR_NAME));
+    }
+    if let Ok(exe) = env::current_exe() {
+        for dir in exe.ancestors().skip(1) {
+            candidates.push(dir.join(RESOURCE_DIR_NAME));
+        }
+    }
+
+    let fallback = Path::new(env!("CARGO_MANIFEST_DIR")).join(RESOURCE_DIR_NAME);
+    candidates
+        .into_iter()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(fallback)
+}
+
+/// Path to one of the bundled models
+pub fn model_path(model_name: &str) -> String {
+    resource_dir()
+        .join("Models")
+        .join(model_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resources::*;
+
+    #[test]
+    fn test_model_path_exists() {
+        assert!(Path::new(&model_path("cube.obj")).is_file());
+    }
+}
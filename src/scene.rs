@@ -0,0 +1,150 @@
+use std::io;
+use std::path::Path;
+
+use crate::bvh::SceneBvh;
+use crate::colour::Colour;
+use crate::fog::Fog;
+use crate::threed::*;
+
+/// Colours handed out in turn to models opened without a scene file
+const MODEL_COLOURS: [(u8, u8, u8); 4] =
+    [(42, 170, 255), (1, 204, 3), (255, 140, 0), (200, 60, 200)];
+
+/// Everything the renderer needs to draw a frame, independent of any window or input handling
+pub struct Scene {
+    pub objects: Vec<Object>,
+    pub camera: Camera,
+    pub light_dir: vec3,
+    pub background: Colour,
+    pub fog: Fog,
+    pub bvh: SceneBvh,
+}
+
+impl Scene {
+    pub fn new(objects: Vec<Object>, camera: Camera, light_dir: vec3) -> Self {
+        let bvh = SceneBvh::build(&objects);
+        Scene {
+            objects,
+            camera,
+            light_dir,
+            background: Colour::new(59, 59, 59),
+            fog: Fog::default(),
+            bvh,
+        }
+    }
+
+    /// The colour behind everything, the fog colour if the fog is set to cover the background
+    pub fn clear_colour(&self) -> Colour {
+        if self.fog.enabled() && self.fog.background {
+            self.fog.colour
+        } else {
+            self.background
+        }
+    }
+
+    /// Line the models up side by side along x, with the camera pulled back to see them all
+    pub fn from_models(paths: &[String]) -> Result<Scene, io::Error> {
+        let mut objects = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let name = Path::new(path)
+                .file_stem()
+                .map_or(path.clone(), |s| s.to_string_lossy().to_string());
+            let (r, g, b) = MODEL_COLOURS[i % MODEL_COLOURS.len()];
+            let transform = Transform {
+                position: vec3::default(),
+                rotation: vec3::default(),
+            };
+            let object =
+                Object::create_from_file(name, path.clone(), transform, Colour::new(r, g, b))
+                    .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+            objects.push(object);
+        }
+
+        // Each model gets a slot as wide as its bounding sphere, centred on the origin overall
+        let gap = 0.5;
+        let total_width: f32 = objects
+            .iter()
+            .map(|o| 2. * o.mesh.sphere.radius + gap)
+            .sum::<f32>()
+            - gap;
+        let mut x = -total_width / 2.;
+        let mut max_radius = 0f32;
+        for object in &mut objects {
+            let sphere = object.mesh.sphere;
+            x += sphere.radius;
+            object.transform.position = vec3 {
+                x: x - sphere.centre.x,
+                y: -sphere.centre.y,
+                z: -sphere.centre.z,
+            };
+            x += sphere.radius + gap;
+            max_radius = max_radius.max(sphere.radius);
+        }
+
+        let mut camera = Camera {
+            fov: 60.,
+            near_plane: 0.1,
+            far_plane: 1000.,
+            position: vec3::default(),
+            yaw: 0.,
+            fwd_speed: 1.,
+            yaw_speed: 5.,
+        };
+        // Back off until the whole row fits the vertical field of view, with some margin
+        let half_extent = (total_width / 2.).max(max_radius);
+        let distance = 1.2 * half_extent / (camera.fov / 2.).to_radians().tan() + max_radius;
+        camera.position.z = -distance;
+        camera.far_plane = camera.far_plane.max(4. * distance);
+
+        let light_dir = vec3 {
+            x: 0.,
+            y: 10.,
+            z: -10.,
+        };
+
+        Ok(Scene::new(objects, camera, light_dir))
+    }
+
+    /// Bring the object hierarchy up to date after objects have been moved
+    pub fn refit(&mut self) {
+        self.bvh.refit(&self.objects);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::Frustum;
+    use crate::scene::*;
+
+    #[test]
+    fn test_from_models_all_in_view() {
+        let paths = vec![
+            "Resource/Models/cube.obj".to_string(),
+            "Resource/Models/teapot.obj".to_string(),
+            "Resource/Models/cube.obj".to_string(),
+        ];
+
+        let scene = Scene::from_models(&paths).unwrap();
+
+        let screen = Screen {
+            width: 800,
+            height: 600,
+        };
+        let view_proj = scene
+            .camera
+            .create_view_matrix()
+            .dot(&scene.camera.create_projection_matrix(screen));
+        let frustum = Frustum::from_matrix(&view_proj);
+
+        assert_eq!(3, scene.objects.len());
+        assert_eq!("teapot", scene.objects[1].name);
+        for object in &scene.objects {
+            let model_mat = object.transform.model_matrix();
+            let aabb = object.mesh.aabb.transform(&model_mat);
+            // Every corner is inside every plane
+            for corner in aabb.corners() {
+                assert!(frustum.planes.iter().all(|p| p.distance(corner) >= 0.));
+            }
+        }
+    }
+}
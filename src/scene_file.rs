@@ -0,0 +1,494 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::colour::Colour;
+use crate::debug_view::DebugView;
+use crate::fog::{Fog, FogMode};
+use crate::lod::Lods;
+use crate::renderer::{AntiAliasing, Downsample, Rasterizer, Renderer, ShadingMode, ViewMode};
+use crate::scene::Scene;
+use crate::threed::*;
+
+/// Human editable description of a scene, stored as RON. Mesh paths are relative to the
+/// scene file. Loading turns it into a `Scene` plus render settings, saving goes the other way
+/// so objects moved around in the viewer can be written back out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub camera: CameraDesc,
+    pub light: LightDesc,
+    #[serde(default = "default_background")]
+    pub background: [u8; 3],
+    #[serde(default)]
+    pub fog: FogDesc,
+    #[serde(default)]
+    pub render: RenderSettings,
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default = "default_near_plane")]
+    pub near_plane: f32,
+    #[serde(default = "default_far_plane")]
+    pub far_plane: f32,
+    #[serde(default = "default_fwd_speed")]
+    pub fwd_speed: f32,
+    #[serde(default = "default_yaw_speed")]
+    pub yaw_speed: f32,
+}
+
+/// A single directional light, shining along `direction`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightDesc {
+    pub direction: [f32; 3],
+}
+
+/// Distance fog, see `Fog`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FogDesc {
+    #[serde(default)]
+    pub mode: FogMode,
+    #[serde(default = "default_fog_colour")]
+    pub colour: [u8; 3],
+    #[serde(default = "default_fog_start")]
+    pub start: f32,
+    #[serde(default = "default_fog_end")]
+    pub end: f32,
+    #[serde(default)]
+    pub per_vertex: bool,
+    /// Clear to the fog colour instead of the background colour
+    #[serde(default = "default_true")]
+    pub background: bool,
+}
+
+impl Default for FogDesc {
+    fn default() -> Self {
+        FogDesc::from_fog(&Fog::default())
+    }
+}
+
+impl FogDesc {
+    fn from_fog(fog: &Fog) -> Self {
+        FogDesc {
+            mode: fog.mode,
+            colour: [fog.colour.r, fog.colour.g, fog.colour.b],
+            start: fog.start,
+            end: fog.end,
+            per_vertex: fog.per_vertex,
+            background: fog.background,
+        }
+    }
+
+    fn to_fog(&self) -> Fog {
+        let [r, g, b] = self.colour;
+        Fog {
+            mode: self.mode,
+            colour: Colour::new(r, g, b),
+            start: self.start,
+            end: self.end,
+            per_vertex: self.per_vertex,
+            background: self.background,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RenderSettings {
+    #[serde(default)]
+    pub view_mode: ViewMode,
+    /// From before view modes, true loads as `ViewMode::Wireframe`. Never saved
+    #[serde(default, skip_serializing)]
+    pub wireframe: bool,
+    /// Draw the ground grid
+    #[serde(default)]
+    pub grid: bool,
+    /// Show depth, normals, overdraw or triangle ids instead of the lit scene
+    #[serde(default)]
+    pub debug_view: DebugView,
+    #[serde(default)]
+    pub shading: ShadingMode,
+    #[serde(default)]
+    pub rasterizer: Rasterizer,
+    #[serde(default)]
+    pub anti_aliasing: AntiAliasing,
+    #[serde(default)]
+    pub downsample: Downsample,
+    /// Names of the post-process effects to switch on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_effects: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectDesc {
+    pub name: String,
+    pub mesh: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Degrees about each axis
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    pub material: MaterialDesc,
+    /// Lower detail levels to generate from the mesh
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lods: Vec<LodDesc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialDesc {
+    pub albedo: [u8; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LodDesc {
+    /// Fraction of the mesh's triangles to keep
+    pub fraction: f32,
+    /// Screen size below which this level is used, see `LodLevel::threshold`
+    pub threshold: f32,
+}
+
+fn default_background() -> [u8; 3] {
+    [59, 59, 59]
+}
+
+fn default_fog_colour() -> [u8; 3] {
+    let colour = Fog::default().colour;
+    [colour.r, colour.g, colour.b]
+}
+
+fn default_fog_start() -> f32 {
+    Fog::default().start
+}
+
+fn default_fog_end() -> f32 {
+    Fog::default().end
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_fov() -> f32 {
+    60.
+}
+
+fn default_near_plane() -> f32 {
+    0.1
+}
+
+fn default_far_plane() -> f32 {
+    1000.
+}
+
+fn default_fwd_speed() -> f32 {
+    1.
+}
+
+fn default_yaw_speed() -> f32 {
+    5.
+}
+
+fn to_vec3(v: [f32; 3]) -> vec3 {
+    vec3 {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    }
+}
+
+fn from_vec3(v: vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl SceneFile {
+    pub fn load(path: &str) -> Result<SceneFile, io::Error> {
+        let text = fs::read_to_string(path)?;
+        SceneFile::from_ron(&text).map_err(|e| invalid_data(format!("{path}: {e}")))
+    }
+
+    pub fn from_ron(text: &str) -> Result<SceneFile, ron::error::SpannedError> {
+        ron::from_str(text)
+    }
+
+    pub fn to_ron(&self) -> String {
+        let config = ron::ser::PrettyConfig::new().struct_names(false);
+        ron::ser::to_string_pretty(self, config).expect("Scene should always serialise")
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), io::Error> {
+        fs::write(path, self.to_ron())
+    }
+
+    /// Load the meshes and build the scene, `dir` is the directory relative mesh paths start from
+    pub fn build(&self, dir: &Path) -> Result<Scene, io::Error> {
+        let mut objects = Vec::new();
+        for desc in &self.objects {
+            let path = dir.join(&desc.mesh);
+            let transform = Transform {
+                position: to_vec3(desc.position),
+                rotation: to_vec3(desc.rotation),
+            };
+            let [r, g, b] = desc.material.albedo;
+            let mut object = Object::create_from_file(
+                desc.name.clone(),
+                path.to_string_lossy().to_string(),
+                transform,
+                Colour::new(r, g, b),
+            )
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+
+            // Keep the path as written so saving the scene gives back the same file
+            object.mesh_path = Some(desc.mesh.clone());
+
+            if !desc.lods.is_empty() {
+                let levels: Vec<(f32, f32)> = desc
+                    .lods
+                    .iter()
+                    .map(|lod| (lod.fraction, lod.threshold))
+                    .collect();
+                object.lods = Lods::generate(&object.mesh, &levels);
+            }
+
+            objects.push(object);
+        }
+
+        let c = &self.camera;
+        let camera = Camera {
+            fov: c.fov,
+            near_plane: c.near_plane,
+            far_plane: c.far_plane,
+            yaw: c.yaw,
+            position: to_vec3(c.position),
+            fwd_speed: c.fwd_speed,
+            yaw_speed: c.yaw_speed,
+        };
+
+        let mut scene = Scene::new(objects, camera, to_vec3(self.light.direction));
+        let [r, g, b] = self.background;
+        scene.background = Colour::new(r, g, b);
+        scene.fog = self.fog.to_fog();
+        Ok(scene)
+    }
+
+    /// Describe the current state of a scene. Every object needs to have come from a mesh file
+    pub fn from_scene(scene: &Scene, renderer: &Renderer) -> Result<SceneFile, io::Error> {
+        let mut objects = Vec::new();
+        for object in &scene.objects {
+            let mesh = object.mesh_path.clone().ok_or_else(|| {
+                invalid_data(format!("{} was not loaded from a mesh file", object.name))
+            })?;
+
+            let tris = object.mesh.indices.len().max(1) as f32;
+            let lods = object
+                .lods
+                .levels
+                .iter()
+                .map(|level| LodDesc {
+                    fraction: level.mesh.indices.len() as f32 / tris,
+                    threshold: level.threshold,
+                })
+                .collect();
+
+            objects.push(ObjectDesc {
+                name: object.name.clone(),
+                mesh,
+                position: from_vec3(object.transform.position),
+                rotation: from_vec3(object.transform.rotation),
+                material: MaterialDesc {
+                    albedo: [object.albedo.r, object.albedo.g, object.albedo.b],
+                },
+                lods,
+            });
+        }
+
+        let c = &scene.camera;
+        Ok(SceneFile {
+            camera: CameraDesc {
+                position: from_vec3(c.position),
+                yaw: c.yaw,
+                fov: c.fov,
+                near_plane: c.near_plane,
+                far_plane: c.far_plane,
+                fwd_speed: c.fwd_speed,
+                yaw_speed: c.yaw_speed,
+            },
+            light: LightDesc {
+                direction: from_vec3(scene.light_dir),
+            },
+            background: [scene.background.r, scene.background.g, scene.background.b],
+            fog: FogDesc::from_fog(&scene.fog),
+            render: RenderSettings {
+                view_mode: renderer.view_mode,
+                wireframe: false,
+                grid: renderer.grid.enabled,
+                debug_view: renderer.debug_view,
+                shading: renderer.shading,
+                rasterizer: renderer.rasterizer,
+                anti_aliasing: renderer.anti_aliasing,
+                downsample: renderer.downsample,
+                post_effects: renderer.post.enabled(),
+            },
+            objects,
+        })
+    }
+}
+
+impl RenderSettings {
+    pub fn apply(&self, renderer: &mut Renderer) {
+        renderer.view_mode = if self.wireframe {
+            ViewMode::Wireframe
+        } else {
+            self.view_mode
+        };
+        renderer.grid.enabled = self.grid;
+        renderer.debug_view = self.debug_view;
+        renderer.shading = self.shading;
+        renderer.rasterizer = self.rasterizer;
+        renderer.anti_aliasing = self.anti_aliasing;
+        renderer.downsample = self.downsample;
+        renderer.post.set_enabled_only(&self.post_effects);
+    }
+}
+
+/// Load a scene file and its meshes, applying its render settings to the renderer
+pub fn load_scene(path: &str, renderer: &mut Renderer) -> Result<Scene, io::Error> {
+    let file = SceneFile::load(path)?;
+    let scene = file.build(&scene_dir(path))?;
+    file.render.apply(renderer);
+    Ok(scene)
+}
+
+/// Save the scene as it is now, mesh paths are written exactly as they were loaded
+pub fn save_scene(path: &str, scene: &Scene, renderer: &Renderer) -> Result<(), io::Error> {
+    SceneFile::from_scene(scene, renderer)?.save(path)
+}
+
+/// The directory relative paths in a scene file are resolved against
+pub fn scene_dir(path: &str) -> PathBuf {
+    Path::new(path)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scene_file::*;
+
+    const TEST_SCENE: &str = r#"(
+        camera: (position: (0.0, 2.0, -8.0), yaw: 10.0),
+        light: (direction: (0.0, 10.0, -10.0)),
+        objects: [
+            (
+                name: "cube",
+                mesh: "../Models/cube.obj",
+                position: (1.0, 2.0, 3.0),
+                rotation: (0.0, 45.0, 0.0),
+                material: (albedo: (42, 170, 255)),
+            ),
+            (
+                name: "teapot",
+                mesh: "../Models/teapot.obj",
+                material: (albedo: (1, 204, 3)),
+                lods: [(fraction: 0.5, threshold: 0.3)],
+            ),
+        ],
+    )"#;
+
+    #[test]
+    fn test_parse_with_defaults() {
+        let file = SceneFile::from_ron(TEST_SCENE).unwrap();
+
+        assert_eq!(60., file.camera.fov);
+        assert_eq!([59, 59, 59], file.background);
+        assert_eq!(ViewMode::Shaded, file.render.view_mode);
+        assert_eq!(FogMode::Off, file.fog.mode);
+        assert!(file.fog.background);
+        assert_eq!(2, file.objects.len());
+        assert_eq!([0., 0., 0.], file.objects[1].position);
+        assert_eq!(1, file.objects[1].lods.len());
+    }
+
+    #[test]
+    fn test_legacy_wireframe() {
+        let file = SceneFile::from_ron(&TEST_SCENE.replacen('(', "(render: (wireframe: true),", 1))
+            .unwrap();
+        let scene = file.build(Path::new("Resource/Scenes")).unwrap();
+        let mut renderer = Renderer::new(80, 60);
+
+        file.render.apply(&mut renderer);
+
+        assert_eq!(ViewMode::Wireframe, renderer.view_mode);
+        let saved = SceneFile::from_scene(&scene, &renderer).unwrap();
+        assert!(!saved.to_ron().contains("wireframe:"));
+    }
+
+    #[test]
+    fn test_build_resolves_paths() {
+        let file = SceneFile::from_ron(TEST_SCENE).unwrap();
+
+        let scene = file.build(Path::new("Resource/Scenes")).unwrap();
+
+        assert_eq!(2, scene.objects.len());
+        assert_eq!(12, scene.objects[0].mesh.indices.len());
+        assert_eq!(3., scene.objects[0].transform.position.z);
+        assert_eq!(1, scene.objects[1].lods.levels.len());
+        assert_eq!(10., scene.camera.yaw);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = SceneFile::from_ron(TEST_SCENE).unwrap();
+        let mut scene = file.build(Path::new("Resource/Scenes")).unwrap();
+        let mut renderer = Renderer::new(80, 60);
+        renderer.view_mode = ViewMode::HiddenLine;
+        renderer.grid.enabled = true;
+        renderer.debug_view = DebugView::Overdraw;
+
+        scene.fog.mode = FogMode::Exp2;
+        scene.fog.end = 80.;
+
+        // As if the user had dragged the cube somewhere else
+        scene.objects[0].transform.position.x = -4.;
+
+        let saved = SceneFile::from_scene(&scene, &renderer).unwrap();
+        let reloaded = SceneFile::from_ron(&saved.to_ron()).unwrap();
+
+        assert_eq!(saved, reloaded);
+        assert_eq!(-4., reloaded.objects[0].position[0]);
+        assert_eq!("../Models/cube.obj", reloaded.objects[0].mesh);
+        assert_eq!(ViewMode::HiddenLine, reloaded.render.view_mode);
+        assert!(reloaded.render.grid);
+        assert_eq!(DebugView::Overdraw, reloaded.render.debug_view);
+        assert_eq!(FogMode::Exp2, reloaded.fog.mode);
+        assert_eq!(80., reloaded.fog.end);
+        assert!((reloaded.objects[1].lods[0].fraction - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_missing_mesh_is_an_error() {
+        let mut file = SceneFile::from_ron(TEST_SCENE).unwrap();
+        file.objects[0].mesh = "missing.obj".to_string();
+
+        assert!(file.build(Path::new("Resource/Scenes")).is_err());
+    }
+
+    #[test]
+    fn test_load_sample_scene() {
+        let mut renderer = Renderer::new(80, 60);
+
+        let scene = load_scene("Resource/Scenes/demo.ron", &mut renderer).unwrap();
+
+        assert!(!scene.objects.is_empty());
+    }
+}
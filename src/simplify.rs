@@ -0,0 +1,610 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::mesh::Mesh;
+use crate::threed::*;
+
+/// How strongly the planes added along boundary edges hold the boundary in place,
+/// relative to the planes of the triangles themselves
+const BOUNDARY_WEIGHT: f64 = 1000.;
+
+/// A collapse is rejected if it turns any remaining triangle by more than this,
+/// given as the minimum cosine between the old and new triangle normals
+const MIN_NORMAL_DOT: f64 = 0.2;
+
+pub struct SimplifyOptions {
+    /// Stop once the mesh has this many triangles or fewer
+    pub target_tris: usize,
+    /// Stop once the cheapest collapse would add more than this error.
+    /// The error is a sum of squared distances to the original planes, so is in model units squared
+    pub max_error: f64,
+    /// Add extra planes along open edges so that holes and outlines keep their shape
+    pub preserve_boundary: bool,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        SimplifyOptions {
+            target_tris: 0,
+            max_error: f64::MAX,
+            preserve_boundary: true,
+        }
+    }
+}
+
+/// Symmetric 4x4 matrix giving the sum of squared distances from a point to a set of planes.
+/// Only the upper triangle is stored: aa ab ac ad bb bc bd cc cd dd
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64) -> Self {
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut q = *self;
+        for (a, b) in q.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+        q
+    }
+
+    fn scale(&self, factor: f64) -> Quadric {
+        let mut q = *self;
+        for a in q.0.iter_mut() {
+            *a *= factor;
+        }
+        q
+    }
+
+    fn error(&self, v: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = v;
+        q[0] * x * x
+            + 2. * q[1] * x * y
+            + 2. * q[2] * x * z
+            + 2. * q[3] * x
+            + q[4] * y * y
+            + 2. * q[5] * y * z
+            + 2. * q[6] * y
+            + q[7] * z * z
+            + 2. * q[8] * z
+            + q[9]
+    }
+
+    /// The point with the least error, found by solving the 3x3 system with Cramer's rule.
+    /// None if the system is singular, e.g. when all the planes are parallel
+    fn optimal(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let rhs = [-q[3], -q[6], -q[8]];
+
+        let det3 = |m: &[[f64; 3]; 3]| {
+            m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+        };
+
+        let det = det3(&m);
+        if det.abs() < 1e-10 {
+            return None;
+        }
+
+        let mut result = [0.; 3];
+        for (col, value) in result.iter_mut().enumerate() {
+            let mut mc = m;
+            for row in 0..3 {
+                mc[row][col] = rhs[row];
+            }
+            *value = det3(&mc) / det;
+        }
+        Some(result)
+    }
+}
+
+/// A possible collapse of edge v1-v2 to `target`.
+/// The stamps record the vertex versions when this was calculated, if either vertex
+/// has changed since then the candidate is out of date and is skipped
+#[derive(Debug)]
+struct Candidate {
+    cost: f64,
+    v1: usize,
+    v2: usize,
+    stamp1: u32,
+    stamp2: u32,
+    target: [f64; 3],
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed so that the BinaryHeap gives the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalise(a: [f64; 3]) -> Option<[f64; 3]> {
+    let length = dot(a, a).sqrt();
+    if length > 1e-12 {
+        Some([a[0] / length, a[1] / length, a[2] / length])
+    } else {
+        None
+    }
+}
+
+fn tri_normal(p1: [f64; 3], p2: [f64; 3], p3: [f64; 3]) -> Option<[f64; 3]> {
+    normalise(cross(sub(p2, p1), sub(p3, p1)))
+}
+
+struct Simplifier {
+    pos: Vec<[f64; 3]>,
+    normals: Vec<Vert>,
+    uvs: Vec<[f32; 2]>,
+    quadrics: Vec<Quadric>,
+    stamps: Vec<u32>,
+    vert_alive: Vec<bool>,
+    vert_tris: Vec<Vec<usize>>,
+    tris: Vec<[usize; 3]>,
+    tri_alive: Vec<bool>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh, options: &SimplifyOptions) -> Self {
+        let num_verts = mesh.verts.len();
+
+        let mut simplifier = Simplifier {
+            pos: mesh
+                .verts
+                .iter()
+                .map(|v| [v.x as f64, v.y as f64, v.z as f64])
+                .collect(),
+            normals: mesh.normals.clone(),
+            uvs: mesh.uvs.clone(),
+            quadrics: vec![Quadric::default(); num_verts],
+            stamps: vec![0; num_verts],
+            vert_alive: vec![true; num_verts],
+            vert_tris: vec![Vec::new(); num_verts],
+            tris: mesh.indices.clone(),
+            tri_alive: vec![true; mesh.indices.len()],
+            heap: BinaryHeap::new(),
+        };
+
+        // Each vertex starts with the planes of the triangles around it
+        let mut edge_tris: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (t, tri) in mesh.indices.iter().enumerate() {
+            for &v in tri {
+                simplifier.vert_tris[v].push(t);
+            }
+
+            for e in 0..3 {
+                let (a, b) = (tri[e], tri[(e + 1) % 3]);
+                edge_tris.entry((a.min(b), a.max(b))).or_default().push(t);
+            }
+
+            let [p1, p2, p3] = tri.map(|v| simplifier.pos[v]);
+            if let Some(n) = tri_normal(p1, p2, p3) {
+                let q = Quadric::from_plane(n[0], n[1], n[2], -dot(n, p1));
+                for &v in tri {
+                    simplifier.quadrics[v] = simplifier.quadrics[v].add(&q);
+                }
+            }
+        }
+
+        // An edge with only one triangle is on a boundary. That includes the seams where vertices were split
+        // for differing normals or texture coordinates, which keeps those attribute discontinuities in place.
+        // A plane through the edge and perpendicular to its triangle pulls collapses back onto the boundary
+        if options.preserve_boundary {
+            for (&(a, b), tris) in &edge_tris {
+                if tris.len() != 1 {
+                    continue;
+                }
+                let [p1, p2, p3] = mesh.indices[tris[0]].map(|v| simplifier.pos[v]);
+                let (pa, pb) = (simplifier.pos[a], simplifier.pos[b]);
+                let face_normal = match tri_normal(p1, p2, p3) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                if let Some(n) = normalise(cross(sub(pb, pa), face_normal)) {
+                    let q =
+                        Quadric::from_plane(n[0], n[1], n[2], -dot(n, pa)).scale(BOUNDARY_WEIGHT);
+                    simplifier.quadrics[a] = simplifier.quadrics[a].add(&q);
+                    simplifier.quadrics[b] = simplifier.quadrics[b].add(&q);
+                }
+            }
+        }
+
+        for &(a, b) in edge_tris.keys() {
+            simplifier.push_candidate(a, b);
+        }
+
+        simplifier
+    }
+
+    /// Work out the best place to collapse edge v1-v2 to and queue it up
+    fn push_candidate(&mut self, v1: usize, v2: usize) {
+        let q = self.quadrics[v1].add(&self.quadrics[v2]);
+
+        let (p1, p2) = (self.pos[v1], self.pos[v2]);
+        let mid = [
+            (p1[0] + p2[0]) * 0.5,
+            (p1[1] + p2[1]) * 0.5,
+            (p1[2] + p2[2]) * 0.5,
+        ];
+
+        let has_attributes = !self.normals.is_empty() || !self.uvs.is_empty();
+
+        let target = match q.optimal() {
+            // Off the edge there is nothing sensible to interpolate normals and texture coordinates from,
+            // so only use the free optimum for meshes without them
+            Some(p) if !has_attributes => p,
+            _ => {
+                // Along the edge the error is a quadratic in how far along it we are, which is
+                // fitted through the two ends and the middle then minimised
+                let e0 = q.error(p1);
+                let e1 = q.error(p2);
+                let e_mid = q.error(mid);
+                let a = 2. * e1 + 2. * e0 - 4. * e_mid;
+                let b = 4. * e_mid - 3. * e0 - e1;
+
+                let t = if a > 0. {
+                    (-b / (2. * a)).clamp(0., 1.)
+                } else if e1 < e0 {
+                    1.
+                } else {
+                    0.
+                };
+
+                [
+                    p1[0] + (p2[0] - p1[0]) * t,
+                    p1[1] + (p2[1] - p1[1]) * t,
+                    p1[2] + (p2[2] - p1[2]) * t,
+                ]
+            }
+        };
+
+        self.heap.push(Candidate {
+            cost: q.error(target).max(0.),
+            v1,
+            v2,
+            stamp1: self.stamps[v1],
+            stamp2: self.stamps[v2],
+            target,
+        });
+    }
+
+    fn is_current(&self, c: &Candidate) -> bool {
+        self.vert_alive[c.v1]
+            && self.vert_alive[c.v2]
+            && self.stamps[c.v1] == c.stamp1
+            && self.stamps[c.v2] == c.stamp2
+    }
+
+    /// Check none of the triangles that survive the collapse would be flipped or badly turned
+    fn collapse_folds(&self, v1: usize, v2: usize, target: [f64; 3]) -> bool {
+        for &v in &[v1, v2] {
+            for &t in &self.vert_tris[v] {
+                let tri = self.tris[t];
+                if !self.tri_alive[t] || (tri.contains(&v1) && tri.contains(&v2)) {
+                    // Dead, or one that the collapse removes
+                    continue;
+                }
+
+                let old = tri.map(|i| self.pos[i]);
+                let new = tri.map(|i| if i == v { target } else { self.pos[i] });
+
+                match (
+                    tri_normal(old[0], old[1], old[2]),
+                    tri_normal(new[0], new[1], new[2]),
+                ) {
+                    (Some(n_old), Some(n_new)) if dot(n_old, n_new) < MIN_NORMAL_DOT => {
+                        return true
+                    }
+                    (Some(_), None) => return true,
+                    _ => (),
+                }
+            }
+        }
+        false
+    }
+
+    /// Move v1 to the target and replace v2 with v1 everywhere, returning the number of triangles removed
+    fn collapse(&mut self, v1: usize, v2: usize, target: [f64; 3]) -> usize {
+        // Carry the attributes across by how far along the edge the target is
+        let edge = sub(self.pos[v2], self.pos[v1]);
+        let edge_len_sq = dot(edge, edge);
+        let t = if edge_len_sq > 0. {
+            (dot(sub(target, self.pos[v1]), edge) / edge_len_sq).clamp(0., 1.) as f32
+        } else {
+            0.
+        };
+
+        if !self.normals.is_empty() {
+            let (n1, n2) = (self.normals[v1], self.normals[v2]);
+            let n = Vert {
+                x: n1.x + (n2.x - n1.x) * t,
+                y: n1.y + (n2.y - n1.y) * t,
+                z: n1.z + (n2.z - n1.z) * t,
+            };
+            self.normals[v1] = normalise_vec(&n);
+        }
+
+        if !self.uvs.is_empty() {
+            let (uv1, uv2) = (self.uvs[v1], self.uvs[v2]);
+            self.uvs[v1] = [
+                uv1[0] + (uv2[0] - uv1[0]) * t,
+                uv1[1] + (uv2[1] - uv1[1]) * t,
+            ];
+        }
+
+        self.pos[v1] = target;
+        self.quadrics[v1] = self.quadrics[v1].add(&self.quadrics[v2]);
+        self.vert_alive[v2] = false;
+        self.stamps[v1] += 1;
+
+        let mut removed = 0;
+        let v2_tris = std::mem::take(&mut self.vert_tris[v2]);
+        for t in v2_tris {
+            if !self.tri_alive[t] {
+                continue;
+            }
+
+            if self.tris[t].contains(&v1) {
+                self.tri_alive[t] = false;
+                removed += 1;
+            } else {
+                for i in self.tris[t].iter_mut() {
+                    if *i == v2 {
+                        *i = v1;
+                    }
+                }
+                self.vert_tris[v1].push(t);
+            }
+        }
+
+        let tri_alive = &self.tri_alive;
+        self.vert_tris[v1].retain(|&t| tri_alive[t]);
+
+        // Every edge around v1 has changed, so queue them up again
+        let mut neighbours: Vec<usize> = self.vert_tris[v1]
+            .iter()
+            .flat_map(|&t| self.tris[t])
+            .filter(|&v| v != v1)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for v in neighbours {
+            self.push_candidate(v1, v);
+        }
+
+        removed
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let mut remap = vec![usize::MAX; self.pos.len()];
+        let mut verts = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut indices = Vec::new();
+
+        for (t, tri) in self.tris.iter().enumerate() {
+            if !self.tri_alive[t] {
+                continue;
+            }
+
+            let new_tri = tri.map(|v| {
+                if remap[v] == usize::MAX {
+                    remap[v] = verts.len();
+                    let p = self.pos[v];
+                    verts.push(Vert {
+                        x: p[0] as f32,
+                        y: p[1] as f32,
+                        z: p[2] as f32,
+                    });
+                    if !self.normals.is_empty() {
+                        normals.push(self.normals[v]);
+                    }
+                    if !self.uvs.is_empty() {
+                        uvs.push(self.uvs[v]);
+                    }
+                }
+                remap[v]
+            });
+            indices.push(new_tri);
+        }
+
+        Mesh::new(verts, indices).with_attributes(normals, uvs)
+    }
+}
+
+/// Reduce the triangle count of a mesh by repeatedly collapsing the edge that changes the shape least,
+/// using the quadric error metric from Garland & Heckbert, "Surface Simplification Using Quadric Error Metrics".
+/// Each vertex keeps a quadric measuring the squared distance to the planes of its original triangles,
+/// when an edge is collapsed the new vertex goes wherever the sum of the two quadrics is smallest.
+/// Collapses that would flip a triangle over are skipped.
+/// For meshes with normals or texture coordinates the new vertex is kept on the collapsed edge
+/// so that they can be interpolated along it.
+pub fn simplify(mesh: &Mesh, options: &SimplifyOptions) -> Mesh {
+    let mut simplifier = Simplifier::new(mesh, options);
+    let mut num_tris = mesh.indices.len();
+
+    while num_tris > options.target_tris {
+        let candidate = match simplifier.heap.pop() {
+            Some(c) => c,
+            None => break,
+        };
+
+        if !simplifier.is_current(&candidate) {
+            continue;
+        }
+
+        if candidate.cost > options.max_error {
+            break;
+        }
+
+        if simplifier.collapse_folds(candidate.v1, candidate.v2, candidate.target) {
+            continue;
+        }
+
+        num_tris -= simplifier.collapse(candidate.v1, candidate.v2, candidate.target);
+    }
+
+    simplifier.into_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::Mesh;
+    use crate::simplify::*;
+
+    /// A flat n x n grid of quads in the XY plane, with texture coordinates equal to the positions
+    fn grid(n: usize) -> Mesh {
+        let mut verts = Vec::new();
+        let mut uvs = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                verts.push(Vert {
+                    x: x as f32,
+                    y: y as f32,
+                    z: 0.,
+                });
+                uvs.push([x as f32, y as f32]);
+            }
+        }
+
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                indices.push([i, i + 1, i + n + 2]);
+                indices.push([i, i + n + 2, i + n + 1]);
+            }
+        }
+
+        Mesh::new(verts, indices).with_attributes(Vec::new(), uvs)
+    }
+
+    #[test]
+    fn test_reaches_target() {
+        let mesh = Mesh::from_obj_file("Resource/Models/teapot.obj").unwrap();
+        let options = SimplifyOptions {
+            target_tris: 1000,
+            ..SimplifyOptions::default()
+        };
+
+        let result = simplify(&mesh, &options);
+
+        let tris = result.indices.len();
+        assert!(tris <= 1000, "{tris}");
+        assert!(tris >= 900, "{tris}");
+    }
+
+    #[test]
+    fn test_flat_grid_keeps_shape() {
+        let mesh = grid(10);
+
+        // A zero error threshold still allows any collapse that stays in the plane and on the boundary
+        let options = SimplifyOptions {
+            max_error: 1e-6,
+            ..SimplifyOptions::default()
+        };
+
+        let result = simplify(&mesh, &options);
+
+        assert!(result.indices.len() < mesh.indices.len() / 4);
+        assert_eq!(mesh.aabb, result.aabb);
+        for (v, uv) in result.verts.iter().zip(result.uvs.iter()) {
+            assert!(v.z.abs() < 0.0001);
+            assert!((v.x - uv[0]).abs() < 0.0001);
+            assert!((v.y - uv[1]).abs() < 0.0001);
+        }
+
+        // The area has to be unchanged, so there can't be any holes or overlaps
+        let area: f32 = (0..result.indices.len())
+            .map(|i| {
+                let tri = result.tri(i);
+                let n = cross_product(tri.v2 - tri.v1, tri.v3 - tri.v1);
+                0.5 * n.z
+            })
+            .sum();
+        assert!((area - 100.).abs() < 0.001, "{area}");
+    }
+
+    #[test]
+    fn test_error_threshold_stops_early() {
+        let mesh = Mesh::from_obj_file("Resource/Models/teapot.obj").unwrap();
+
+        let strict = simplify(
+            &mesh,
+            &SimplifyOptions {
+                max_error: 1e-5,
+                ..SimplifyOptions::default()
+            },
+        );
+        let loose = simplify(
+            &mesh,
+            &SimplifyOptions {
+                max_error: 1e-2,
+                ..SimplifyOptions::default()
+            },
+        );
+
+        assert!(strict.indices.len() < mesh.indices.len());
+        assert!(loose.indices.len() < strict.indices.len());
+    }
+
+    #[test]
+    fn test_boundary_preserved() {
+        let mesh = grid(10);
+        let options = SimplifyOptions {
+            target_tris: 2,
+            ..SimplifyOptions::default()
+        };
+
+        let result = simplify(&mesh, &options);
+
+        // Even taken right down the outline of the grid stays put
+        assert_eq!(mesh.aabb, result.aabb);
+    }
+}
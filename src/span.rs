@@ -0,0 +1,313 @@
+//! The innermost loops of the rasterizers, filling a run of pixels along one row.
+//! With the `simd` feature, x86_64 CPUs that have AVX2 do 8 pixels at a time for plain spans
+//! and 4 at a time where edge tests are needed (the edge values are 64-bit). Everything else
+//! takes the scalar loops. Both paths work out depth with the same operations in the same
+//! order, so they give bit for bit the same image.
+
+use crate::raster::DepthPlane;
+
+/// The path the span functions take on this machine, for benchmark output
+pub fn simd_level() -> &'static str {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if avx2::available() {
+        return "avx2";
+    }
+    "scalar"
+}
+
+/// Depth test and write the run of pixels starting at `x` on row `y`. `pixels` and `depths`
+/// are just that run. Depth is taken at `centre` past each pixel's corner
+pub(crate) fn fill_span(
+    pixels: &mut [u32],
+    depths: &mut [f32],
+    x: usize,
+    y: usize,
+    centre: f32,
+    depth: &DepthPlane,
+    colour: u32,
+) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if avx2::available() {
+        // Safety: the CPU has just been checked for AVX2
+        unsafe { avx2::fill_span(pixels, depths, x, y, centre, depth, colour) };
+        return;
+    }
+    fill_span_scalar(pixels, depths, x, y, centre, depth, colour)
+}
+
+/// As `fill_span`, but only for pixels where all three edge values are at least 0.
+/// `w` are the edge values at the first pixel and `step` how much they change for each pixel along.
+/// The edges were tested at `sample` within each pixel, so depth is taken there too
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fill_edge_span(
+    pixels: &mut [u32],
+    depths: &mut [f32],
+    x: usize,
+    y: usize,
+    sample: (f32, f32),
+    w: [i64; 3],
+    step: [i64; 3],
+    depth: &DepthPlane,
+    colour: u32,
+) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if avx2::available() {
+        // Safety: the CPU has just been checked for AVX2
+        unsafe { avx2::fill_edge_span(pixels, depths, x, y, sample, w, step, depth, colour) };
+        return;
+    }
+    fill_edge_span_scalar(pixels, depths, x, y, sample, w, step, depth, colour)
+}
+
+fn fill_span_scalar(
+    pixels: &mut [u32],
+    depths: &mut [f32],
+    x: usize,
+    y: usize,
+    centre: f32,
+    depth: &DepthPlane,
+    colour: u32,
+) {
+    let y = y as f32 + centre;
+    for (i, (pixel, d)) in pixels.iter_mut().zip(depths.iter_mut()).enumerate() {
+        let z = depth.at((x + i) as f32 + centre, y);
+
+        // Ties go to the later triangle, as they did before there was a depth buffer
+        if z <= *d {
+            *pixel = colour;
+            *d = z;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill_edge_span_scalar(
+    pixels: &mut [u32],
+    depths: &mut [f32],
+    x: usize,
+    y: usize,
+    sample: (f32, f32),
+    mut w: [i64; 3],
+    step: [i64; 3],
+    depth: &DepthPlane,
+    colour: u32,
+) {
+    let y = y as f32 + sample.1;
+    for (i, (pixel, d)) in pixels.iter_mut().zip(depths.iter_mut()).enumerate() {
+        if w[0] >= 0 && w[1] >= 0 && w[2] >= 0 {
+            let z = depth.at((x + i) as f32 + sample.0, y);
+            if z <= *d {
+                *pixel = colour;
+                *d = z;
+            }
+        }
+        for (w, step) in w.iter_mut().zip(step) {
+            *w += step;
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    pub fn available() -> bool {
+        is_x86_feature_detected!("avx2")
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn fill_span(
+        pixels: &mut [u32],
+        depths: &mut [f32],
+        x: usize,
+        y: usize,
+        centre: f32,
+        depth: &DepthPlane,
+        colour: u32,
+    ) {
+        let len = pixels.len().min(depths.len());
+        let dzdx = _mm256_set1_ps(depth.dzdx);
+        let row = _mm256_set1_ps(depth.dzdy * (y as f32 + centre));
+        let z0 = _mm256_set1_ps(depth.z0);
+        let centre_v = _mm256_set1_ps(centre);
+        let colour_v = _mm256_set1_epi32(colour as i32);
+        let lanes = _mm256_setr_ps(0., 1., 2., 3., 4., 5., 6., 7.);
+
+        let mut i = 0;
+        while i + 8 <= len {
+            // Whole numbers well under 2^24, so adding the lane offsets in float is exact
+            let xs = _mm256_add_ps(
+                _mm256_add_ps(_mm256_set1_ps((x + i) as f32), lanes),
+                centre_v,
+            );
+            let z = _mm256_add_ps(_mm256_add_ps(_mm256_mul_ps(dzdx, xs), row), z0);
+
+            let d_ptr = depths.as_mut_ptr().add(i);
+            let p_ptr = pixels.as_mut_ptr().add(i) as *mut __m256i;
+            let d = _mm256_loadu_ps(d_ptr);
+            let pass = _mm256_cmp_ps::<_CMP_LE_OQ>(z, d);
+            _mm256_storeu_ps(d_ptr, _mm256_blendv_ps(d, z, pass));
+            let p = _mm256_loadu_si256(p_ptr);
+            _mm256_storeu_si256(
+                p_ptr,
+                _mm256_blendv_epi8(p, colour_v, _mm256_castps_si256(pass)),
+            );
+            i += 8;
+        }
+
+        fill_span_scalar(
+            &mut pixels[i..len],
+            &mut depths[i..len],
+            x + i,
+            y,
+            centre,
+            depth,
+            colour,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn fill_edge_span(
+        pixels: &mut [u32],
+        depths: &mut [f32],
+        x: usize,
+        y: usize,
+        sample: (f32, f32),
+        w: [i64; 3],
+        step: [i64; 3],
+        depth: &DepthPlane,
+        colour: u32,
+    ) {
+        let len = pixels.len().min(depths.len());
+        let dzdx = _mm_set1_ps(depth.dzdx);
+        let row = _mm_set1_ps(depth.dzdy * (y as f32 + sample.1));
+        let z0 = _mm_set1_ps(depth.z0);
+        let centre = _mm_set1_ps(sample.0);
+        let colour_v = _mm_set1_epi32(colour as i32);
+        let lanes = _mm_setr_ps(0., 1., 2., 3.);
+        let minus_one = _mm256_set1_epi64x(-1);
+        // The low half of each 64-bit mask, to line the edge results up with 32-bit pixels
+        let low_halves = _mm256_setr_epi32(0, 2, 4, 6, 0, 0, 0, 0);
+
+        let edge_lanes =
+            |w: i64, step: i64| _mm256_setr_epi64x(w, w + step, w + 2 * step, w + 3 * step);
+        let mut ws = [
+            edge_lanes(w[0], step[0]),
+            edge_lanes(w[1], step[1]),
+            edge_lanes(w[2], step[2]),
+        ];
+        let steps = step.map(|s| _mm256_set1_epi64x(4 * s));
+
+        let mut i = 0;
+        while i + 4 <= len {
+            let inside = _mm256_and_si256(
+                _mm256_and_si256(
+                    _mm256_cmpgt_epi64(ws[0], minus_one),
+                    _mm256_cmpgt_epi64(ws[1], minus_one),
+                ),
+                _mm256_cmpgt_epi64(ws[2], minus_one),
+            );
+
+            // Most of a triangle's bounding box is usually outside it
+            if _mm256_testz_si256(inside, inside) == 0 {
+                let inside =
+                    _mm256_castsi256_si128(_mm256_permutevar8x32_epi32(inside, low_halves));
+
+                let xs = _mm_add_ps(_mm_add_ps(_mm_set1_ps((x + i) as f32), lanes), centre);
+                let z = _mm_add_ps(_mm_add_ps(_mm_mul_ps(dzdx, xs), row), z0);
+
+                let d_ptr = depths.as_mut_ptr().add(i);
+                let p_ptr = pixels.as_mut_ptr().add(i) as *mut __m128i;
+                let d = _mm_loadu_ps(d_ptr);
+                let pass = _mm_and_ps(_mm_cmple_ps(z, d), _mm_castsi128_ps(inside));
+                _mm_storeu_ps(d_ptr, _mm_blendv_ps(d, z, pass));
+                let p = _mm_loadu_si128(p_ptr);
+                _mm_storeu_si128(p_ptr, _mm_blendv_epi8(p, colour_v, _mm_castps_si128(pass)));
+            }
+
+            for (w, step) in ws.iter_mut().zip(&steps) {
+                *w = _mm256_add_epi64(*w, *step);
+            }
+            i += 4;
+        }
+
+        let done = i as i64;
+        fill_edge_span_scalar(
+            &mut pixels[i..len],
+            &mut depths[i..len],
+            x + i,
+            y,
+            sample,
+            [0, 1, 2].map(|e| w[e] + done * step[e]),
+            step,
+            depth,
+            colour,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::span::*;
+
+    fn random_depth(rng: &mut impl Rng) -> DepthPlane {
+        DepthPlane {
+            dzdx: rng.gen_range(-0.01..0.01),
+            dzdy: rng.gen_range(-0.01..0.01),
+            z0: rng.gen_range(0.2..0.8),
+        }
+    }
+
+    /// Whichever path is compiled in must match the scalar loops exactly
+    #[test]
+    fn test_spans_match_scalar() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        for _ in 0..200 {
+            let len = rng.gen_range(0..70);
+            let (x, y) = (rng.gen_range(0..2000), rng.gen_range(0..2000));
+            let depth = random_depth(&mut rng);
+            let start_depths: Vec<f32> = (0..len).map(|_| rng.gen_range(0.0..1.0)).collect();
+            let w = [0; 3].map(|_| rng.gen_range(-5000..5000));
+            let step = [0; 3].map(|_| rng.gen_range(-300..300));
+
+            let mut expected = (vec![0; len], start_depths.clone());
+            let mut actual = (vec![0; len], start_depths.clone());
+            fill_span_scalar(&mut expected.0, &mut expected.1, x, y, 0., &depth, 7);
+            fill_span(&mut actual.0, &mut actual.1, x, y, 0., &depth, 7);
+            assert_eq!(expected, actual);
+
+            let mut expected = (vec![0; len], start_depths.clone());
+            let mut actual = (vec![0; len], start_depths);
+            let sample = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+            fill_edge_span_scalar(
+                &mut expected.0,
+                &mut expected.1,
+                x,
+                y,
+                sample,
+                w,
+                step,
+                &depth,
+                7,
+            );
+            fill_edge_span(
+                &mut actual.0,
+                &mut actual.1,
+                x,
+                y,
+                sample,
+                w,
+                step,
+                &depth,
+                7,
+            );
+            assert_eq!(expected, actual);
+        }
+    }
+}
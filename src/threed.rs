@@ -1,4 +1,3 @@
-use std::fs;
 use std::io;
 use std::ops::{Add, Sub};
 
@@ -7,31 +6,79 @@ use ndarray::prelude::*;
 use ndarray::Array;
 
 use crate::colour::*;
+use crate::lod::Lods;
+use crate::mesh::Mesh;
 
-use float_eq::{derive_float_eq, float_eq};
+use float_eq::derive_float_eq;
 
 pub struct Transform {
     pub position: vec3,
     pub rotation: vec3,
 }
 
+impl Transform {
+    /// Combine the rotations (applied Z, then Y, then X) and the translation into a single model matrix
+    pub fn model_matrix(&self) -> Array2<f32> {
+        let rot_x_mat = create_x_rotation_matrix(self.rotation.x);
+        let rot_y_mat = create_y_rotation_matrix(self.rotation.y);
+        let rot_z_mat = create_z_rotation_matrix(self.rotation.z);
+        let trans_mat =
+            create_translation_matrix(self.position.x, self.position.y, self.position.z);
+
+        rot_z_mat.dot(&rot_y_mat).dot(&rot_x_mat).dot(&trans_mat)
+    }
+}
+
 //#[derive(Debug)]
 pub struct Object {
     pub name: String,
-    pub tris: Vec<Tri>,
+    pub mesh: Mesh,
+    pub lods: Lods,
     pub transform: Transform,
     pub albedo: Colour,
+    /// The OBJ file the mesh was loaded from, if it came from one
+    pub mesh_path: Option<String>,
 }
 
 impl Object {
-    pub fn _new(name: String, tris: Vec<Tri>, transform: Transform, albedo: Colour) -> Self {
+    pub fn new(name: String, mesh: Mesh, transform: Transform, albedo: Colour) -> Self {
         Self {
             name,
-            tris,
+            mesh,
+            lods: Lods::default(),
             transform,
             albedo,
+            mesh_path: None,
+        }
+    }
+
+    /// The mesh for the currently selected level of detail
+    pub fn active_mesh(&self) -> &Mesh {
+        match self.lods.current {
+            0 => &self.mesh,
+            lod => &self.lods.levels[lod - 1].mesh,
         }
     }
+
+    /// Swap in a freshly loaded mesh, regenerating any detail levels at the same fractions.
+    /// The object is left untouched if the file can't be read
+    pub fn reload_mesh(&mut self, path: &str) -> Result<(), io::Error> {
+        let mesh = Mesh::from_obj_file(path)?;
+
+        if !self.lods.is_empty() {
+            let tris = self.mesh.indices.len().max(1) as f32;
+            let levels: Vec<(f32, f32)> = self
+                .lods
+                .levels
+                .iter()
+                .map(|level| (level.mesh.indices.len() as f32 / tris, level.threshold))
+                .collect();
+            self.lods = Lods::generate(&mesh, &levels);
+        }
+
+        self.mesh = mesh;
+        Ok(())
+    }
 }
 
 pub struct Screen {
@@ -39,6 +86,7 @@ pub struct Screen {
     pub height: i32,
 }
 
+#[derive(Clone)]
 pub struct Camera {
     pub fov: f32,
     pub near_plane: f32,
@@ -70,8 +118,7 @@ impl Camera {
 
         let point_at = point_at(self.position, target_vert, up);
 
-        let vm = quick_invert_mat4(point_at);
-        vm
+        quick_invert_mat4(point_at)
     }
 
     pub fn create_projection_matrix(&self, screen: Screen) -> Array<f32, Ix2> {
@@ -87,7 +134,7 @@ impl Camera {
         m[[0, 0]] = afq.aspect_ratio * afq.fov;
         m[[1, 1]] = afq.fov;
         m[[2, 2]] = afq.q;
-        m[[3, 2]] = -1. * afq.q * self.near_plane;
+        m[[3, 2]] = -afq.q * self.near_plane;
 
         m
     }
@@ -154,6 +201,7 @@ impl Camera {
     all_tol = "f32"
 )]
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) struct AFQ {
     aspect_ratio: f32,
     fov: f32,
@@ -167,7 +215,7 @@ pub(crate) struct AFQ {
     debug_ulps_diff_derive = "Clone, Copy, Debug, PartialEq",
     all_tol = "f32"
 )]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Vert {
     pub x: f32,
     pub y: f32,
@@ -178,19 +226,15 @@ impl Vert {
     pub fn from_string(s: String) -> Option<Vert> {
         //     println!("Vert from string: {s}");
 
-        let chunks: Vec<&str> = s.split(" ").collect();
-
-        if chunks.len() == 4 {
-            if chunks[0] == "v" {
-                let x: f32 = chunks[1].parse().unwrap();
-                let y: f32 = chunks[2].parse().unwrap();
-                let z: f32 = chunks[3].parse().unwrap();
-                return Some(Vert { x, y, z });
-            } else {
-                return None;
-            }
+        let chunks: Vec<&str> = s.split(' ').collect();
+
+        if chunks.len() == 4 && chunks[0] == "v" {
+            let x: f32 = chunks[1].parse().unwrap();
+            let y: f32 = chunks[2].parse().unwrap();
+            let z: f32 = chunks[3].parse().unwrap();
+            Some(Vert { x, y, z })
         } else {
-            return None;
+            None
         }
     }
 }
@@ -227,65 +271,21 @@ pub struct Tri {
 }
 
 impl Object {
-    fn face_from_string(s: String) -> Option<(usize, usize, usize)> {
-        // println!("Face from string: {s}");
-        let chunks: Vec<&str> = s.split(" ").collect();
-
-        if chunks.len() == 4 {
-            if chunks[0] == "f" {
-                let v1_index: usize = chunks[1].parse().unwrap();
-                let v2_index: usize = chunks[2].parse().unwrap();
-                let v3_index: usize = chunks[3].parse().unwrap();
-                Some((v1_index, v2_index, v3_index))
-            } else {
-                return None;
-            }
-        } else {
-            return None;
-        }
-    }
-
     pub fn create_from_file(
         name: String,
         obj_path: String,
         transform: Transform,
         albedo: Colour,
     ) -> Result<Object, io::Error> {
-        let content = fs::read_to_string(obj_path)?;
-        //   println!("{content}");
-        let lines: Vec<&str> = content.split("\n").collect();
-
-        let mut verts: Vec<Vert> = Vec::new();
-        let mut faces: Vec<(usize, usize, usize)> = Vec::new();
-
-        for line in lines {
-            let vert = Vert::from_string(line.to_string());
-            match vert {
-                Some(v) => verts.push(v), //Vertex was ok
-                None => (),               //Not a valid vertex, just ignore
-            }
-
-            let face = Object::face_from_string(line.to_string());
-            match face {
-                Some(f) => faces.push(f), //Vertex was ok
-                None => (),               //Not a valid face, just ignore
-            }
-        }
-
-        let mut mesh: Vec<Tri> = Vec::new();
-        for face in faces {
-            let v1 = verts[face.0 - 1];
-            let v2 = verts[face.1 - 1];
-            let v3 = verts[face.2 - 1];
-
-            mesh.push(Tri { v1, v2, v3 });
-        }
+        let mesh = Mesh::from_obj_file(&obj_path)?;
 
         Ok(Object {
             name,
-            tris: mesh,
+            mesh,
+            lods: Lods::default(),
             transform,
             albedo,
+            mesh_path: Some(obj_path),
         })
     }
 }
@@ -398,6 +398,15 @@ pub fn mult_vec3_mat4(vec: vec3, mat: &Array2<f32>) -> vec3 {
     }
 }
 
+/// Multiply a direction by the matrix, i.e. with w = 0 so any translation is ignored
+pub fn mult_dir_mat4(vec: vec3, mat: &Array2<f32>) -> vec3 {
+    let x = mat[[0, 0]] * vec.x + mat[[1, 0]] * vec.y + mat[[2, 0]] * vec.z;
+    let y = mat[[0, 1]] * vec.x + mat[[1, 1]] * vec.y + mat[[2, 1]] * vec.z;
+    let z = mat[[0, 2]] * vec.x + mat[[1, 2]] * vec.y + mat[[2, 2]] * vec.z;
+
+    vec3 { x, y, z }
+}
+
 pub fn quick_invert_mat4(mat: Array2<f32>) -> Array2<f32> {
     let mut out: ArrayBase<ndarray::OwnedRepr<f32>, Dim<[usize; 2]>> = Array::eye(4);
 
@@ -427,7 +436,7 @@ pub fn quick_invert_mat4(mat: Array2<f32>) -> Array2<f32> {
     out
 }
 
-pub(crate) fn normalise_vec(vec: &vec3) -> vec3 {
+pub fn normalise_vec(vec: &vec3) -> vec3 {
     let x = vec.x.powf(2.);
     let y = vec.y.powf(2.);
     let z = vec.z.powf(2.);
@@ -452,11 +461,11 @@ pub fn normal(tri: &Tri) -> vec3 {
     normalise_vec(&vec3 { x, y, z })
 }
 
-fn dot_product(v1: vec3, v2: vec3) -> f32 {
+pub fn dot_product(v1: vec3, v2: vec3) -> f32 {
     (v1.x * v2.x) + (v1.y * v2.y) + (v1.z * v2.z)
 }
 
-pub(crate) fn cross_product(v1: vec3, v2: vec3) -> vec3 {
+pub fn cross_product(v1: vec3, v2: vec3) -> vec3 {
     let x = v1.y * v2.z - v1.z * v2.y;
     let y = v1.z * v2.x - v1.x * v2.z;
     let z = v1.x * v2.y - v1.y * v2.x;
@@ -473,8 +482,9 @@ pub fn calc_tri_illum(light_dir: &vec3, tri_normal: &vec3, colour: Colour) -> Co
 }
 
 #[cfg(test)]
+#[allow(clippy::excessive_precision)]
 mod tests {
-    use crate::*;
+    use crate::threed::*;
     use float_eq::assert_float_eq;
     use ndarray::arr2;
 
@@ -604,6 +614,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_model_matrix() {
+        let transform = Transform {
+            position: vec3 {
+                x: 1.,
+                y: -2.,
+                z: 3.,
+            },
+            rotation: vec3 {
+                x: 10.,
+                y: 20.,
+                z: 30.,
+            },
+        };
+
+        let vert = vec3 {
+            x: 0.5,
+            y: 1.5,
+            z: -2.,
+        };
+
+        let mut expected = mult_vec3_mat4(vert, &create_z_rotation_matrix(30.));
+        expected = mult_vec3_mat4(expected, &create_y_rotation_matrix(20.));
+        expected = mult_vec3_mat4(expected, &create_x_rotation_matrix(10.));
+        expected = mult_vec3_mat4(expected, &create_translation_matrix(1., -2., 3.));
+
+        let result = mult_vec3_mat4(vert, &transform.model_matrix());
+
+        assert_float_eq!(expected, result, abs_all <= 0.0001);
+    }
+
     #[test]
     fn test_point_at() {
         let expected = arr2(&[
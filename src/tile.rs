@@ -0,0 +1,297 @@
+//! Screen tiles for parallel rasterisation. Each frame the triangles are binned into the tiles
+//! they overlap, then every tile draws its own list into its own colour and depth, so tiles
+//! can be rasterised on different threads without sharing anything. Within a tile triangles are
+//! drawn in the order they were binned, which keeps the result the same whatever the thread count.
+
+use std::ops::Range;
+
+use crate::antialias::resolve_samples;
+use crate::colour::Colour;
+use crate::line::Line;
+use crate::raster::{RasterTarget, Rect, Tri};
+use crate::renderer::FrameBuffer;
+
+/// Width and height of a tile in pixels, tiles along the right and top edges can be smaller
+pub const TILE_SIZE: usize = 64;
+
+pub struct Tile {
+    pub rect: Rect,
+    pub pixels: Vec<u32>,
+    pub depth: Vec<f32>,
+    /// One plane per sample position when multisampling, otherwise empty and triangles are
+    /// drawn straight into `pixels` and `depth`
+    pub samples: Vec<SamplePlane>,
+    /// Indices of the triangles touching this tile, in drawing order
+    pub tris: Vec<usize>,
+    /// Indices of the lines touching this tile, drawn after the triangles
+    pub lines: Vec<usize>,
+}
+
+impl Tile {
+    /// Average the sample planes into the tile's pixels, keeping the nearest depth.
+    /// Nothing to do without multisampling
+    pub fn resolve_samples(&mut self) {
+        resolve_samples(&self.samples, &mut self.pixels, &mut self.depth);
+    }
+}
+
+/// Colour and depth at one sample position in each pixel of a tile
+pub struct SamplePlane {
+    pub rect: Rect,
+    pub pixels: Vec<u32>,
+    pub depth: Vec<f32>,
+}
+
+impl RasterTarget for SamplePlane {
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    fn buffers(&mut self) -> (&mut [u32], &mut [f32]) {
+        (&mut self.pixels, &mut self.depth)
+    }
+}
+
+impl RasterTarget for Tile {
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    fn buffers(&mut self) -> (&mut [u32], &mut [f32]) {
+        (&mut self.pixels, &mut self.depth)
+    }
+}
+
+/// The screen split into tiles, row by row from the bottom left
+pub struct TileGrid {
+    pub width: usize,
+    pub height: usize,
+    pub columns: usize,
+    /// Samples per pixel, 1 without multisampling
+    pub samples: usize,
+    pub tiles: Vec<Tile>,
+}
+
+impl TileGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        TileGrid::with_samples(width, height, 1)
+    }
+
+    /// Tiles with a colour and depth plane for each of `samples` sample positions
+    pub fn with_samples(width: usize, height: usize, samples: usize) -> Self {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        let mut tiles = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = column * TILE_SIZE;
+                let y = row * TILE_SIZE;
+                let rect = Rect {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(width - x),
+                    height: TILE_SIZE.min(height - y),
+                };
+                let size = rect.width * rect.height;
+                let planes = if samples > 1 { samples } else { 0 };
+                tiles.push(Tile {
+                    rect,
+                    pixels: vec![0; size],
+                    depth: vec![f32::INFINITY; size],
+                    samples: (0..planes)
+                        .map(|_| SamplePlane {
+                            rect,
+                            pixels: vec![0; size],
+                            depth: vec![f32::INFINITY; size],
+                        })
+                        .collect(),
+                    tris: Vec::new(),
+                    lines: Vec::new(),
+                });
+            }
+        }
+
+        TileGrid {
+            width,
+            height,
+            columns,
+            samples,
+            tiles,
+        }
+    }
+
+    /// Clear every tile's colour and depth, and empty the triangle lists
+    pub fn clear(&mut self, colour: Colour) {
+        for tile in &mut self.tiles {
+            tile.pixels.fill(colour.as_0rgb());
+            tile.depth.fill(f32::INFINITY);
+            for plane in &mut tile.samples {
+                plane.pixels.fill(colour.as_0rgb());
+                plane.depth.fill(f32::INFINITY);
+            }
+            tile.tris.clear();
+            tile.lines.clear();
+        }
+    }
+
+    /// Add each triangle to the list of every tile its screen bounding box overlaps.
+    /// `order` gives the indices into `tris` in the order they should be drawn
+    pub fn bin(&mut self, tris: &[Tri], order: &[usize]) {
+        for &index in order {
+            let tri = &tris[index];
+            // Whole pixels either side of the sub-pixel bounds, enough for either rasterizer
+            let min = (
+                tri.p1.x.min(tri.p2.x).min(tri.p3.x),
+                tri.p1.y.min(tri.p2.y).min(tri.p3.y),
+            );
+            let max = (
+                tri.p1.x.max(tri.p2.x).max(tri.p3.x),
+                tri.p1.y.max(tri.p2.y).max(tri.p3.y),
+            );
+            let (rows, columns) = self.overlapping(min, max, 0.);
+            for row in rows {
+                for column in columns.clone() {
+                    self.tiles[row * self.columns + column].tris.push(index);
+                }
+            }
+        }
+    }
+
+    /// As `bin` for lines up to `width` pixels wide
+    pub fn bin_lines(&mut self, lines: &[Line], order: &[usize], width: f32) {
+        for &index in order {
+            let line = &lines[index];
+            let min = (line.p1.x.min(line.p2.x), line.p1.y.min(line.p2.y));
+            let max = (line.p1.x.max(line.p2.x), line.p1.y.max(line.p2.y));
+            // Wide lines and their caps reach half the width past the end points
+            let (rows, columns) = self.overlapping(min, max, width / 2. + 1.);
+            for row in rows {
+                for column in columns.clone() {
+                    self.tiles[row * self.columns + column].lines.push(index);
+                }
+            }
+        }
+    }
+
+    /// The rows and columns of tiles touching a screen space box grown by `pad` pixels each way
+    fn overlapping(
+        &self,
+        min: (f32, f32),
+        max: (f32, f32),
+        pad: f32,
+    ) -> (Range<usize>, Range<usize>) {
+        let min_x = (min.0 - pad).floor() as usize;
+        let max_x = (max.0 + pad).ceil() as usize;
+        let min_y = (min.1 - pad).floor() as usize;
+        let max_y = (max.1 + pad).ceil() as usize;
+
+        if self.tiles.is_empty() || min_x >= self.width || min_y >= self.height {
+            return (0..0, 0..0);
+        }
+
+        let last_column = max_x.min(self.width - 1) / TILE_SIZE;
+        let last_row = max_y.min(self.height - 1) / TILE_SIZE;
+        (
+            min_y / TILE_SIZE..last_row + 1,
+            min_x / TILE_SIZE..last_column + 1,
+        )
+    }
+
+    /// Copy the finished tiles into the framebuffer, which must be the same size as the grid
+    pub fn resolve(&self, framebuffer: &mut FrameBuffer) {
+        assert_eq!(
+            (self.width, self.height),
+            (framebuffer.width, framebuffer.height)
+        );
+
+        for tile in &self.tiles {
+            let rect = tile.rect;
+            for row in 0..rect.height {
+                // Both store the top row first, the tile's top row is the highest y it covers
+                let fb_row = self.height - (rect.y + rect.height) + row;
+                let dst = fb_row * framebuffer.width + rect.x;
+                let src = row * rect.width;
+                framebuffer.pixels[dst..dst + rect.width]
+                    .copy_from_slice(&tile.pixels[src..src + rect.width]);
+                framebuffer.depth[dst..dst + rect.width]
+                    .copy_from_slice(&tile.depth[src..src + rect.width]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raster::{draw_filled_triangle, Point};
+    use crate::tile::*;
+
+    fn tri(points: [(u32, u32); 3]) -> Tri {
+        let [p1, p2, p3] = points.map(|(x, y)| Point {
+            x: x as f32,
+            y: y as f32,
+            z: 0.5,
+        });
+        Tri { p1, p2, p3 }
+    }
+
+    #[test]
+    fn test_grid_covers_screen() {
+        let grid = TileGrid::new(150, 70);
+
+        assert_eq!(3, grid.columns);
+        assert_eq!(6, grid.tiles.len());
+        let area: usize = grid
+            .tiles
+            .iter()
+            .map(|t| t.rect.width * t.rect.height)
+            .sum();
+        assert_eq!(150 * 70, area);
+        assert_eq!(22, grid.tiles[5].rect.width);
+        assert_eq!(6, grid.tiles[5].rect.height);
+    }
+
+    #[test]
+    fn test_bin_keeps_order() {
+        let mut grid = TileGrid::new(128, 128);
+        let tris = [
+            tri([(10, 10), (100, 10), (10, 100)]),
+            tri([(70, 70), (80, 70), (70, 80)]),
+            // Off the right of the screen
+            tri([(300, 10), (310, 10), (300, 20)]),
+        ];
+
+        grid.bin(&tris, &[1, 0, 2]);
+
+        assert_eq!(vec![0], grid.tiles[0].tris);
+        assert_eq!(vec![0], grid.tiles[1].tris);
+        assert_eq!(vec![1, 0], grid.tiles[3].tris);
+    }
+
+    #[test]
+    fn test_tiles_match_whole_framebuffer() {
+        let (width, height) = (200, 150);
+        let tris = [
+            tri([(5, 5), (190, 20), (60, 140)]),
+            tri([(100, 0), (199, 149), (0, 149)]),
+        ];
+
+        let mut expected = FrameBuffer::new(width, height);
+        for tri in &tris {
+            draw_filled_triangle(&mut expected, tri, 0xff8000);
+        }
+
+        let mut grid = TileGrid::new(width, height);
+        grid.clear(Colour::new(0, 0, 0));
+        grid.bin(&tris, &[0, 1]);
+        for tile in &mut grid.tiles {
+            for index in tile.tris.clone() {
+                draw_filled_triangle(tile, &tris[index], 0xff8000);
+            }
+        }
+        let mut actual = FrameBuffer::new(width, height);
+        grid.resolve(&mut actual);
+
+        assert_eq!(expected.pixels, actual.pixels);
+    }
+}